@@ -0,0 +1,311 @@
+use std::fs;
+
+use assembler::assembler::Assembler;
+
+use crate::fast::Program;
+use crate::keyboard::KeyEvent;
+use crate::screen;
+
+pub const ROM_SIZE: usize = 32768;
+pub const RAM_SIZE: usize = 32768;
+pub const SCREEN: usize = 16384;
+pub const SCREEN_SIZE: usize = 8192;
+pub const KBD: usize = 24576;
+
+// Executes .hack ROM images (one 16-bit binary word per line, as the assembler's
+// to_binary writes them) with the same RAM layout as the Hack computer.
+pub struct Cpu {
+    rom: Vec<u16>,
+    rom_len: usize,
+    pub(crate) ram: Vec<i16>,
+    pub(crate) a: i16,
+    pub(crate) d: i16,
+    pub(crate) pc: usize,
+    pub(crate) cycles: usize,
+    pub(crate) halted: bool,
+    // every change of KBD, to record a run's input
+    key_events: Vec<KeyEvent>,
+    // decoded for run_fast, dropped when the ROM changes
+    pub(crate) program: Option<Program>,
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Cpu {
+            rom: vec![0; ROM_SIZE],
+            rom_len: 0,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+            halted: false,
+            key_events: Vec::new(),
+            program: None,
+        }
+    }
+
+    pub fn from_file(path: &str) -> Self {
+        let text = fs::read_to_string(path).expect("Not found file!");
+        let mut cpu = Cpu::new();
+        cpu.load(&text);
+        cpu
+    }
+
+    // replaces the ROM and resets the registers; RAM is kept
+    pub fn load(&mut self, text: &str) {
+        // checked first: past 32K words the assembler writes 17-bit addresses
+        let words = text.lines().filter(|line| !line.trim().is_empty()).count();
+        if words > ROM_SIZE {
+            panic!(
+                "ERROR: program too large for ROM: {} words, {} fit",
+                words, ROM_SIZE
+            );
+        }
+        self.rom = vec![0; ROM_SIZE];
+        self.rom_len = 0;
+        self.program = None;
+        for (row, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let word = match u16::from_str_radix(line, 2) {
+                Ok(word) if line.len() == 16 => word,
+                _ => panic!(
+                    "ERROR: line {} is not a 16-bit binary word: {}",
+                    row + 1,
+                    line
+                ),
+            };
+            self.rom[self.rom_len] = word;
+            self.rom_len += 1;
+        }
+        self.reset();
+    }
+
+    // assembles an .asm file into the ROM; the assembler is handed back for its
+    // labels and source lines
+    pub fn load_asm(&mut self, path: &str) -> Assembler {
+        let mut assembler = Assembler::new(path);
+        assembler.assemble();
+        self.load(&assembler.to_binary());
+        assembler
+    }
+
+    // PC = 0 like the reset pin; A, D and RAM are left as they are
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.cycles = 0;
+        self.halted = false;
+        self.key_events.clear();
+    }
+
+    pub fn rom(&self, address: usize) -> u16 {
+        self.rom[address]
+    }
+
+    // number of words loaded from the .hack file
+    pub fn rom_len(&self) -> usize {
+        self.rom_len
+    }
+
+    pub fn ram(&self, address: usize) -> i16 {
+        self.ram[address]
+    }
+
+    pub fn set_ram(&mut self, address: usize, value: i16) {
+        if address == KBD {
+            self.set_keyboard(value);
+        } else {
+            self.ram[address] = value;
+        }
+    }
+
+    pub fn screen(&self) -> &[i16] {
+        &self.ram[SCREEN..SCREEN + SCREEN_SIZE]
+    }
+
+    // screen memory map as a .png or .pbm image
+    pub fn save_screen(&self, path: &str) {
+        screen::save(self.screen(), path);
+    }
+
+    // key code the keyboard reports, 0 when no key is pressed
+    pub fn set_keyboard(&mut self, key: i16) {
+        if self.ram[KBD] != key {
+            self.key_events.push(KeyEvent {
+                cycle: self.cycles,
+                key,
+            });
+        }
+        self.ram[KBD] = key;
+    }
+
+    // the KBD changes made so far; KeyScript::from replays them
+    pub fn key_events(&self) -> &[KeyEvent] {
+        &self.key_events
+    }
+
+    pub fn a(&self) -> i16 {
+        self.a
+    }
+
+    pub fn set_a(&mut self, value: i16) {
+        self.a = value;
+    }
+
+    pub fn d(&self) -> i16 {
+        self.d
+    }
+
+    pub fn set_d(&mut self, value: i16) {
+        self.d = value;
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+        self.halted = false;
+    }
+
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    // execution ran past the loaded program or is parked in an
+    // `(END) @END 0;JMP` loop
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // execute one instruction; false once the program has halted
+    pub fn step(&mut self) -> bool {
+        if self.halted {
+            return false;
+        }
+        if self.pc >= self.rom_len {
+            self.halted = true;
+            return false;
+        }
+        let instruction = self.rom[self.pc];
+        self.cycles += 1;
+
+        if instruction & 0x8000 == 0 {
+            self.a = instruction as i16;
+            self.pc += 1;
+            return true;
+        }
+
+        let address = address_of(self.a);
+        let y = if instruction & 0x1000 != 0 {
+            self.ram[address]
+        } else {
+            self.a
+        };
+        let out = alu(self.d, y, (instruction >> 6) & 0x3f);
+
+        // M is written to the address A held before this instruction
+        if instruction & 0x08 != 0 {
+            self.ram[address] = out;
+        }
+        if instruction & 0x10 != 0 {
+            self.d = out;
+        }
+        if instruction & 0x20 != 0 {
+            self.a = out;
+        }
+
+        let jump = (instruction & 0x04 != 0 && out < 0)
+            || (instruction & 0x02 != 0 && out == 0)
+            || (instruction & 0x01 != 0 && out > 0);
+        if jump {
+            // an unconditional jump to itself, or back to the @ that loads it
+            let target = address;
+            let is_loop = instruction & 0x07 == 0x07
+                && instruction & 0x38 == 0
+                && (target == self.pc
+                    || (target + 1 == self.pc && self.rom[target] as usize == target));
+            if is_loop {
+                self.halted = true;
+            }
+            self.pc = target;
+        } else {
+            self.pc += 1;
+        }
+        !self.halted
+    }
+
+    // run until halted or `max_cycles` instructions; returns the instructions executed
+    pub fn run(&mut self, max_cycles: usize) -> usize {
+        self.run_until(max_cycles, |_| false)
+    }
+
+    // run until `stop` holds before an instruction, the program halts, or `max_cycles` instructions
+    pub fn run_until<F>(&mut self, max_cycles: usize, stop: F) -> usize
+    where
+        F: Fn(&Cpu) -> bool,
+    {
+        let start = self.cycles;
+        while self.cycles - start < max_cycles && !stop(self) && self.step() {}
+        self.cycles - start
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Cpu::new()
+    }
+}
+
+// RAM and ROM address A selects; like the Hack computer's 15 address bits,
+// the top bit of A is ignored
+pub(crate) fn address_of(a: i16) -> usize {
+    a as u16 as usize & (RAM_SIZE - 1)
+}
+
+// the Hack ALU; `control` is the six c bits zx nx zy ny f no
+pub fn alu(x: i16, y: i16, control: u16) -> i16 {
+    let x = if control & 0x20 != 0 { 0 } else { x };
+    let x = if control & 0x10 != 0 { !x } else { x };
+    let y = if control & 0x08 != 0 { 0 } else { y };
+    let y = if control & 0x04 != 0 { !y } else { y };
+    let out = if control & 0x02 != 0 {
+        x.wrapping_add(y)
+    } else {
+        x & y
+    };
+    if control & 0x01 != 0 {
+        !out
+    } else {
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_a_addresses_the_top_of_ram() {
+        let mut cpu = Cpu::new();
+        // A=-1, M=1, D=M
+        cpu.load("1110111010100000\n1110111111001000\n1111110000010000\n");
+        cpu.run(3);
+        assert_eq!(cpu.ram(RAM_SIZE - 1), 1);
+        assert_eq!(cpu.d(), 1);
+        assert_eq!(cpu.a(), -1);
+    }
+
+    #[test]
+    #[should_panic(expected = "program too large for ROM: 32769 words")]
+    fn programs_past_32k_words_are_too_large() {
+        // the assembler's 17-bit addresses must not be what gets reported
+        let mut text = "0000000000000000\n".repeat(ROM_SIZE);
+        text.push_str("11000000000000000\n");
+        Cpu::new().load(&text);
+    }
+}
@@ -0,0 +1,587 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use crate::cpu::{Cpu, KBD, RAM_SIZE, SCREEN};
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const STACK: usize = 256;
+
+const HELP: &str = "\
+step [n]            execute n instructions (s)
+next                step over a vm `call` or routine call (n)
+continue [max]      run to a breakpoint, watchpoint or halt (c)
+break <addr|label>  break before a ROM address (b)
+delete <addr|label> remove a breakpoint (d)
+watch <addr|symbol> stop when a RAM word changes (w)
+unwatch <addr|symbol>
+info                list breakpoints and watchpoints
+regs                A, D, PC and the vm pointers (r)
+x <addr|symbol> [n] show n RAM words
+set <A|D|PC|RAM[n]> <value>
+key <code>          set the KBD register
+stack               RAM[256..SP] with LCL and ARG marked
+frame               arguments, saved frame and locals of the current function
+bt                  return addresses up the LCL chain
+list [addr]         source around PC (l)
+quit                (q)";
+
+// Line-oriented debugger over the CPU; an .asm program brings its labels,
+// variables and source lines along, a .hack program only has addresses.
+pub struct Debugger {
+    cpu: Cpu,
+    labels: Vec<(String, usize)>,
+    symbols: BTreeMap<String, usize>,
+    source: Vec<String>,
+    source_lines: Vec<usize>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeMap<usize, i16>,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(path: &str) -> Self {
+        let mut debugger = Debugger {
+            cpu: Cpu::new(),
+            labels: Vec::new(),
+            symbols: BTreeMap::new(),
+            source: Vec::new(),
+            source_lines: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            last_command: String::new(),
+        };
+        if path.ends_with(".asm") {
+            let assembler = debugger.cpu.load_asm(path);
+            debugger.labels = assembler.labels();
+            debugger.source_lines = assembler.source_lines();
+            debugger.source = fs::read_to_string(path)
+                .expect("Not found file!")
+                .split('\n')
+                .map(|line| line.trim_end().to_string())
+                .collect();
+            // variables are the A-instruction symbols that are not labels
+            for line in &debugger.source {
+                let line = line.split("//").next().unwrap().trim();
+                if let Some(name) = line.strip_prefix('@') {
+                    if name.parse::<usize>().is_err() {
+                        if let Some(address) = assembler.symbol(name) {
+                            debugger.symbols.insert(name.to_string(), address);
+                        }
+                    }
+                }
+            }
+            for (label, _) in &debugger.labels {
+                debugger.symbols.remove(label);
+            }
+        } else {
+            debugger.cpu = Cpu::from_file(path);
+        }
+        debugger
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    // reads commands from stdin until `quit` or end of input
+    pub fn repl(&mut self) {
+        let stdin = io::stdin();
+        let mut stdout = io::stdout();
+        self.show_position(&mut stdout);
+        loop {
+            write!(stdout, "(hack) ").unwrap();
+            stdout.flush().unwrap();
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            if !self.command(&line, &mut stdout) {
+                break;
+            }
+        }
+    }
+
+    // runs one command line; false for `quit`. An empty line repeats the last command.
+    pub fn command(&mut self, line: &str, out: &mut dyn Write) -> bool {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let arg = |idx: usize| words.get(idx).copied();
+
+        match words.first().copied().unwrap_or("") {
+            "" => {}
+            "step" | "s" => {
+                let n = arg(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+                for _ in 0..n {
+                    if !self.step_checked(out) {
+                        break;
+                    }
+                }
+                self.show_position(out);
+            }
+            "next" | "n" => {
+                self.next(out);
+                self.show_position(out);
+            }
+            "continue" | "c" => {
+                let max = arg(1).and_then(|n| n.parse().ok()).unwrap_or(usize::MAX);
+                self.continue_run(max, out);
+                self.show_position(out);
+            }
+            "break" | "b" => match arg(1).and_then(|target| self.rom_address(target)) {
+                Some(address) => {
+                    self.breakpoints.insert(address);
+                    writeln!(out, "Breakpoint at {}", self.describe(address)).unwrap();
+                }
+                None => writeln!(out, "Unknown address or label").unwrap(),
+            },
+            "delete" | "d" => match arg(1).and_then(|target| self.rom_address(target)) {
+                Some(address) if self.breakpoints.remove(&address) => {
+                    writeln!(out, "Deleted breakpoint at {}", self.describe(address)).unwrap()
+                }
+                _ => writeln!(out, "No such breakpoint").unwrap(),
+            },
+            "watch" | "w" => match arg(1).and_then(|target| self.ram_address(target)) {
+                Some(address) => {
+                    self.watchpoints.insert(address, self.cpu.ram(address));
+                    writeln!(out, "Watching RAM[{}] = {}", address, self.cpu.ram(address)).unwrap();
+                }
+                None => writeln!(out, "Unknown address or symbol").unwrap(),
+            },
+            "unwatch" => match arg(1).and_then(|target| self.ram_address(target)) {
+                Some(address) if self.watchpoints.remove(&address).is_some() => {
+                    writeln!(out, "Stopped watching RAM[{}]", address).unwrap()
+                }
+                _ => writeln!(out, "No such watchpoint").unwrap(),
+            },
+            "info" | "i" => {
+                for address in &self.breakpoints {
+                    writeln!(out, "break {}", self.describe(*address)).unwrap();
+                }
+                for address in self.watchpoints.keys() {
+                    writeln!(out, "watch RAM[{}] = {}", address, self.cpu.ram(*address)).unwrap();
+                }
+            }
+            "regs" | "r" => self.show_registers(out),
+            "x" => match arg(1).and_then(|target| self.ram_address(target)) {
+                Some(address) => {
+                    let n = arg(2).and_then(|n| n.parse().ok()).unwrap_or(1);
+                    for address in address..(address + n).min(RAM_SIZE) {
+                        writeln!(out, "RAM[{}] = {}", address, self.cpu.ram(address)).unwrap();
+                    }
+                }
+                None => writeln!(out, "Unknown address or symbol").unwrap(),
+            },
+            "set" => match (arg(1), arg(2).and_then(|value| value.parse::<i16>().ok())) {
+                (Some("A"), Some(value)) => self.cpu.set_a(value),
+                (Some("D"), Some(value)) => self.cpu.set_d(value),
+                (Some("PC"), Some(value)) => self.cpu.set_pc(value as u16 as usize),
+                (Some(target), Some(value)) => match self.ram_address(target) {
+                    Some(address) => self.cpu.set_ram(address, value),
+                    None => writeln!(out, "Unknown address or symbol").unwrap(),
+                },
+                _ => writeln!(out, "usage: set <A|D|PC|RAM[n]> <value>").unwrap(),
+            },
+            "key" => match arg(1).and_then(|key| key.parse::<i16>().ok()) {
+                Some(key) => self.cpu.set_keyboard(key),
+                None => writeln!(out, "usage: key <code>").unwrap(),
+            },
+            "stack" => self.show_stack(out),
+            "frame" => self.show_frame(out),
+            "bt" => self.show_backtrace(out),
+            "list" | "l" => {
+                let address = arg(1)
+                    .and_then(|target| self.rom_address(target))
+                    .unwrap_or_else(|| self.cpu.pc());
+                self.show_source(address, out);
+            }
+            "help" | "h" => writeln!(out, "{}", HELP).unwrap(),
+            "quit" | "q" => return false,
+            command => writeln!(out, "Unknown command {}, try help", command).unwrap(),
+        }
+        true
+    }
+
+    // one instruction; false when it halted or hit a watchpoint
+    fn step_checked(&mut self, out: &mut dyn Write) -> bool {
+        if !self.cpu.step() {
+            writeln!(out, "Halted").unwrap();
+            return false;
+        }
+        let mut changed = false;
+        for (address, value) in self.watchpoints.iter_mut() {
+            let now = self.cpu.ram(*address);
+            if now != *value {
+                writeln!(out, "RAM[{}]: {} -> {}", address, value, now).unwrap();
+                *value = now;
+                changed = true;
+            }
+        }
+        !changed
+    }
+
+    fn continue_run(&mut self, max: usize, out: &mut dyn Write) {
+        // leave the breakpoint we are sitting on first
+        if !self.step_checked(out) {
+            return;
+        }
+        for _ in 1..max {
+            if self.breakpoints.contains(&self.cpu.pc()) {
+                writeln!(out, "Breakpoint").unwrap();
+                return;
+            }
+            if !self.step_checked(out) {
+                return;
+            }
+        }
+    }
+
+    // A vm `call` ends in `@f 0;JMP` followed by its return label, so run until
+    // that label is reached with the caller's stack back in place. Shared routines
+    // work the same way without touching the stack.
+    fn next(&mut self, out: &mut dyn Write) {
+        let pc = self.cpu.pc();
+        let is_jump = self.cpu.rom(pc) & 0xe007 == 0xe007;
+        let returns_here = self.labels.iter().any(|(label, address)| {
+            *address == pc + 1
+                && (label.starts_with("return-address.") || label.starts_with("$vm.return."))
+        });
+        if !is_jump || !returns_here {
+            self.step_checked(out);
+            return;
+        }
+
+        let sp = self.cpu.ram(SP);
+        if !self.step_checked(out) {
+            return;
+        }
+        while !(self.cpu.pc() == pc + 1 && self.cpu.ram(SP) <= sp) {
+            if self.breakpoints.contains(&self.cpu.pc()) {
+                writeln!(out, "Breakpoint").unwrap();
+                return;
+            }
+            if !self.step_checked(out) {
+                return;
+            }
+        }
+    }
+
+    fn show_position(&self, out: &mut dyn Write) {
+        let pc = self.cpu.pc();
+        match self.source_text(pc) {
+            Some(text) => writeln!(out, "{}  {}", self.describe(pc), text).unwrap(),
+            None => writeln!(out, "{}", self.describe(pc)).unwrap(),
+        }
+    }
+
+    fn show_registers(&self, out: &mut dyn Write) {
+        writeln!(
+            out,
+            "A = {}  D = {}  PC = {}  cycles = {}",
+            self.cpu.a(),
+            self.cpu.d(),
+            self.cpu.pc(),
+            self.cpu.cycles()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "SP = {}  LCL = {}  ARG = {}  THIS = {}  THAT = {}",
+            self.cpu.ram(SP),
+            self.cpu.ram(LCL),
+            self.cpu.ram(ARG),
+            self.cpu.ram(THIS),
+            self.cpu.ram(THAT)
+        )
+        .unwrap();
+    }
+
+    fn show_stack(&self, out: &mut dyn Write) {
+        let sp = self.pointer(SP);
+        let lcl = self.pointer(LCL);
+        let arg = self.pointer(ARG);
+        for address in STACK..sp.min(RAM_SIZE) {
+            let mark = match (address == arg, address == lcl) {
+                (true, true) => "  <- ARG, LCL",
+                (true, false) => "  <- ARG",
+                (false, true) => "  <- LCL",
+                _ => "",
+            };
+            writeln!(out, "RAM[{}] = {}{}", address, self.cpu.ram(address), mark).unwrap();
+        }
+        writeln!(out, "SP = {}", sp).unwrap();
+    }
+
+    // ARG..saved frame..LCL..SP as the translator's `call` lays them out
+    fn show_frame(&self, out: &mut dyn Write) {
+        let sp = self.pointer(SP);
+        let lcl = self.pointer(LCL);
+        let arg = self.pointer(ARG);
+        if lcl < STACK + 5 || arg > lcl || lcl > sp {
+            writeln!(out, "No vm frame").unwrap();
+            return;
+        }
+        for address in arg..lcl - 5 {
+            writeln!(
+                out,
+                "argument {} = {}",
+                address - arg,
+                self.cpu.ram(address)
+            )
+            .unwrap();
+        }
+        let saved = ["return", "LCL", "ARG", "THIS", "THAT"];
+        for (idx, name) in saved.iter().enumerate() {
+            let value = self.cpu.ram(lcl - 5 + idx);
+            if idx == 0 {
+                let address = value as u16 as usize;
+                writeln!(out, "saved {} = {}", name, self.describe(address)).unwrap();
+            } else {
+                writeln!(out, "saved {} = {}", name, value).unwrap();
+            }
+        }
+        for address in lcl..sp.min(RAM_SIZE) {
+            writeln!(
+                out,
+                "local/stack {} = {}",
+                address - lcl,
+                self.cpu.ram(address)
+            )
+            .unwrap();
+        }
+    }
+
+    fn show_backtrace(&self, out: &mut dyn Write) {
+        writeln!(out, "#0 {}", self.describe(self.cpu.pc())).unwrap();
+        let mut lcl = self.pointer(LCL);
+        let mut depth = 1;
+        while (STACK + 5..=RAM_SIZE).contains(&lcl) && depth < 1000 {
+            let return_address = self.cpu.ram(lcl - 5) as u16 as usize;
+            writeln!(out, "#{} {}", depth, self.describe(return_address)).unwrap();
+            lcl = self.cpu.ram(lcl - 4) as u16 as usize;
+            depth += 1;
+        }
+    }
+
+    fn show_source(&self, address: usize, out: &mut dyn Write) {
+        let start = address.saturating_sub(5);
+        for rom in start..(address + 6).min(self.cpu.rom_len()) {
+            let mark = if rom == self.cpu.pc() { "=>" } else { "  " };
+            let text = self
+                .source_text(rom)
+                .unwrap_or_else(|| format!("{:016b}", self.cpu.rom(rom)));
+            writeln!(out, "{} {:5}  {}", mark, rom, text).unwrap();
+        }
+    }
+
+    // `ROM 42 (LOOP+3, line 17)`
+    fn describe(&self, address: usize) -> String {
+        let mut text = format!("ROM {}", address);
+        let label = self
+            .labels
+            .iter()
+            .rev()
+            .find(|(_, label_address)| *label_address <= address);
+        let line = self.source_lines.get(address);
+        match (label, line) {
+            (Some((label, label_address)), Some(line)) if *label_address == address => {
+                text += &format!(" ({}, line {})", label, line)
+            }
+            (Some((label, label_address)), Some(line)) => {
+                text += &format!(" ({}+{}, line {})", label, address - label_address, line)
+            }
+            (None, Some(line)) => text += &format!(" (line {})", line),
+            _ => {}
+        }
+        text
+    }
+
+    fn source_text(&self, address: usize) -> Option<String> {
+        let line = self.source_lines.get(address)?;
+        self.source
+            .get(line - 1)
+            .map(|text| text.trim().to_string())
+    }
+
+    fn rom_address(&self, target: &str) -> Option<usize> {
+        if let Ok(address) = target.parse() {
+            return Some(address);
+        }
+        self.labels
+            .iter()
+            .find(|(label, _)| label == target)
+            .map(|(_, address)| *address)
+    }
+
+    // a number, `RAM[n]`, a register name like SP or R13, or an assembler variable
+    fn ram_address(&self, target: &str) -> Option<usize> {
+        let target = target
+            .strip_prefix("RAM[")
+            .and_then(|target| target.strip_suffix(']'))
+            .unwrap_or(target);
+        if let Ok(address) = target.parse::<usize>() {
+            return Some(address).filter(|address| *address < RAM_SIZE);
+        }
+        let predefined = match target {
+            "SP" => Some(SP),
+            "LCL" => Some(LCL),
+            "ARG" => Some(ARG),
+            "THIS" => Some(THIS),
+            "THAT" => Some(THAT),
+            "SCREEN" => Some(SCREEN),
+            "KBD" => Some(KBD),
+            _ => target
+                .strip_prefix('R')
+                .and_then(|n| n.parse::<usize>().ok())
+                .filter(|n| *n < 16),
+        };
+        predefined.or_else(|| self.symbols.get(target).copied())
+    }
+
+    fn pointer(&self, address: usize) -> usize {
+        self.cpu.ram(address) as u16 as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNT: &str = "// counts RAM[counter] up to 3
+@counter
+M=0
+(LOOP)
+@counter
+M=M+1
+D=M
+@3
+D=D-A
+@LOOP
+D;JLT
+(END)
+@END
+0;JMP
+";
+
+    fn debugger(name: &str) -> Debugger {
+        let path =
+            std::env::temp_dir().join(format!("debugger_{}_{}.asm", name, std::process::id()));
+        fs::write(&path, COUNT).unwrap();
+        let debugger = Debugger::new(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+        debugger
+    }
+
+    fn command(debugger: &mut Debugger, line: &str) -> String {
+        let mut out = Vec::new();
+        assert!(debugger.command(line, &mut out));
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn addresses_and_symbols() {
+        let debugger = debugger("symbols");
+        assert_eq!(debugger.rom_address("LOOP"), Some(2));
+        assert_eq!(debugger.rom_address("END"), Some(9));
+        assert_eq!(debugger.rom_address("7"), Some(7));
+        assert_eq!(debugger.rom_address("NOWHERE"), None);
+        assert_eq!(debugger.ram_address("counter"), Some(16));
+        assert_eq!(debugger.ram_address("RAM[300]"), Some(300));
+        assert_eq!(debugger.ram_address("R13"), Some(13));
+        assert_eq!(debugger.ram_address("R16"), None);
+        assert_eq!(debugger.ram_address("KBD"), Some(KBD));
+        assert_eq!(debugger.ram_address("40000"), None);
+        // labels are not variables
+        assert_eq!(debugger.ram_address("LOOP"), None);
+        assert_eq!(debugger.describe(2), "ROM 2 (LOOP, line 5)");
+        assert_eq!(debugger.describe(4), "ROM 4 (LOOP+2, line 7)");
+    }
+
+    #[test]
+    fn command_parsing() {
+        let mut debugger = debugger("commands");
+        assert_eq!(
+            command(&mut debugger, "b LOOP"),
+            "Breakpoint at ROM 2 (LOOP, line 5)\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "break 99999x"),
+            "Unknown address or label\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "w counter"),
+            "Watching RAM[16] = 0\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "watch nothing"),
+            "Unknown address or symbol\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "info"),
+            "break ROM 2 (LOOP, line 5)\nwatch RAM[16] = 0\n"
+        );
+        assert_eq!(command(&mut debugger, "d END"), "No such breakpoint\n");
+        assert_eq!(command(&mut debugger, "unwatch R1"), "No such watchpoint\n");
+        assert_eq!(command(&mut debugger, "set RAM[5] 42"), "");
+        assert_eq!(
+            command(&mut debugger, "x R5 2"),
+            "RAM[5] = 42\nRAM[6] = 0\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "set D x"),
+            "usage: set <A|D|PC|RAM[n]> <value>\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "frobnicate"),
+            "Unknown command frobnicate, try help\n"
+        );
+
+        // `s 2` runs two instructions, and an empty line repeats it; the
+        // second write to counter trips the watchpoint
+        assert_eq!(
+            command(&mut debugger, "s 2"),
+            "ROM 2 (LOOP, line 5)  @counter\n"
+        );
+        assert_eq!(
+            command(&mut debugger, ""),
+            "RAM[16]: 0 -> 1\nROM 4 (LOOP+2, line 7)  D=M\n"
+        );
+        assert_eq!(debugger.cpu().cycles(), 4);
+        assert!(!debugger.command("quit", &mut Vec::new()));
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints_stop_continue() {
+        let mut debugger = debugger("breakpoints");
+        command(&mut debugger, "break LOOP");
+        assert_eq!(
+            command(&mut debugger, "c"),
+            "Breakpoint\nROM 2 (LOOP, line 5)  @counter\n"
+        );
+        assert_eq!(debugger.cpu().ram(16), 0);
+        command(&mut debugger, "c");
+        assert_eq!(debugger.cpu().pc(), 2);
+        assert_eq!(debugger.cpu().ram(16), 1);
+
+        // the watchpoint stops right after the write
+        command(&mut debugger, "delete LOOP");
+        command(&mut debugger, "watch counter");
+        assert_eq!(
+            command(&mut debugger, "c"),
+            "RAM[16]: 1 -> 2\nROM 4 (LOOP+2, line 7)  D=M\n"
+        );
+
+        command(&mut debugger, "unwatch counter");
+        assert!(command(&mut debugger, "c").starts_with("Halted\n"));
+        assert!(debugger.cpu().is_halted());
+        assert_eq!(debugger.cpu().ram(16), 3);
+    }
+}
@@ -0,0 +1,286 @@
+use std::fs;
+
+use crate::cpu::Cpu;
+
+// KBD holds `key` from the moment `cycle` instructions have run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub cycle: usize,
+    pub key: i16,
+}
+
+// Timed key codes fed into the KBD register, so runs that read the keyboard
+// can be replayed exactly.
+//
+// One event per line, `<time> <key>`:
+//   frame 20000    // cycles per frame, for times written as `<n>f`
+//   0 0
+//   150000 RIGHT
+//   40f 0
+// A key is a code, a single non-digit character, F1-F12 or one of KEY_NAMES.
+#[derive(Debug, Clone, Default)]
+pub struct KeyScript {
+    events: Vec<KeyEvent>,
+    next: usize,
+}
+
+// the Hack keyboard's codes for keys that are not characters
+const KEY_NAMES: [(&str, i16); 15] = [
+    ("NONE", 0),
+    ("SPACE", 32),
+    ("NEWLINE", 128),
+    ("BACKSPACE", 129),
+    ("LEFT", 130),
+    ("UP", 131),
+    ("RIGHT", 132),
+    ("DOWN", 133),
+    ("HOME", 134),
+    ("END", 135),
+    ("PAGEUP", 136),
+    ("PAGEDOWN", 137),
+    ("INSERT", 138),
+    ("DELETE", 139),
+    ("ESC", 140),
+];
+
+impl KeyScript {
+    pub fn new() -> Self {
+        KeyScript {
+            events: Vec::new(),
+            next: 0,
+        }
+    }
+
+    pub fn from_file(path: &str) -> Self {
+        let text = fs::read_to_string(path).expect("Not found key script!");
+        KeyScript::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut script = KeyScript::new();
+        let mut frame_cycles = None;
+        for (row, line) in text.lines().enumerate() {
+            let line = line.split("//").next().unwrap().trim();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["frame", cycles] => {
+                    frame_cycles = Some(cycles.parse::<usize>().unwrap_or_else(|_| {
+                        panic!("ERROR: line {}: bad frame length {}", row + 1, cycles)
+                    }))
+                }
+                [time, key] => {
+                    let cycle = match time.strip_suffix('f') {
+                        Some(frame) => {
+                            let frame_cycles = frame_cycles.unwrap_or_else(|| {
+                                panic!("ERROR: line {}: frame time before `frame`", row + 1)
+                            });
+                            frame
+                                .parse::<usize>()
+                                .ok()
+                                .map(|frame| frame * frame_cycles)
+                        }
+                        None => time.parse::<usize>().ok(),
+                    };
+                    let cycle = cycle
+                        .unwrap_or_else(|| panic!("ERROR: line {}: bad time {}", row + 1, time));
+                    let key = key_code(key)
+                        .unwrap_or_else(|| panic!("ERROR: line {}: unknown key {}", row + 1, key));
+                    script.push(cycle, key);
+                }
+                _ => panic!("ERROR: line {}: expected `<time> <key>`: {}", row + 1, line),
+            }
+        }
+        script
+    }
+
+    // events are kept in time order
+    pub fn push(&mut self, cycle: usize, key: i16) {
+        let idx = self.events.partition_point(|event| event.cycle <= cycle);
+        self.events.insert(idx, KeyEvent { cycle, key });
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    // start feeding from the first event again
+    pub fn rewind(&mut self) {
+        self.next = 0;
+    }
+
+    // sets KBD to the latest event due at the cpu's cycle count
+    pub fn feed(&mut self, cpu: &mut Cpu) {
+        while let Some(event) = self.events.get(self.next) {
+            if event.cycle > cpu.cycles() {
+                break;
+            }
+            cpu.set_keyboard(event.key);
+            self.next += 1;
+        }
+    }
+
+    // Cpu::run with the keys fed in on time
+    pub fn run(&mut self, cpu: &mut Cpu, max_cycles: usize) -> usize {
+        let start = cpu.cycles();
+        while cpu.cycles() - start < max_cycles {
+            self.feed(cpu);
+            if !cpu.step() {
+                break;
+            }
+        }
+        cpu.cycles() - start
+    }
+
+    pub fn to_text(&self) -> String {
+        self.events
+            .iter()
+            .map(|event| format!("{} {}\n", event.cycle, event.key))
+            .collect()
+    }
+
+    pub fn save(&self, path: &str) {
+        fs::write(path, self.to_text()).expect("Cannot write key script!");
+    }
+}
+
+impl From<&[KeyEvent]> for KeyScript {
+    fn from(events: &[KeyEvent]) -> Self {
+        let mut script = KeyScript::new();
+        for event in events {
+            script.push(event.cycle, event.key);
+        }
+        script
+    }
+}
+
+fn key_code(key: &str) -> Option<i16> {
+    let upper = key.to_uppercase();
+    if let Some((_, code)) = KEY_NAMES.iter().find(|(name, _)| *name == upper) {
+        return Some(*code);
+    }
+    if let Some(n) = upper.strip_prefix('F').and_then(|n| n.parse::<i16>().ok()) {
+        if (1..=12).contains(&n) {
+            return Some(140 + n);
+        }
+    }
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_ascii_digit() => Some(c as i16),
+        _ => key.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::KBD;
+
+    fn events(script: &KeyScript) -> Vec<(usize, i16)> {
+        script
+            .events()
+            .iter()
+            .map(|event| (event.cycle, event.key))
+            .collect()
+    }
+
+    #[test]
+    fn parses_key_names_codes_and_characters() {
+        let script = KeyScript::parse(
+            "// every kind of key
+             0 NONE
+             10 right   // names ignore case
+             20 F1
+             30 F12
+             40 a
+             50 65
+             60 SPACE
+             70 ~
+             80 ESC",
+        );
+        assert_eq!(
+            events(&script),
+            vec![
+                (0, 0),
+                (10, 132),
+                (20, 141),
+                (30, 152),
+                (40, 97),
+                (50, 65),
+                (60, 32),
+                (70, 126),
+                (80, 140),
+            ]
+        );
+    }
+
+    #[test]
+    fn frame_times_and_order() {
+        let script = KeyScript::parse("frame 20000\n2f LEFT\n100 UP\n1f 0\n");
+        assert_eq!(events(&script), vec![(100, 131), (20000, 0), (40000, 130)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "line 2: unknown key F13")]
+    fn unknown_key() {
+        KeyScript::parse("0 F12\n10 F13\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "line 1: bad time 1.5")]
+    fn bad_time() {
+        KeyScript::parse("1.5 a\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "line 1: frame time before `frame`")]
+    fn frame_time_without_frame() {
+        KeyScript::parse("3f a\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "line 1: bad frame length fast")]
+    fn bad_frame_length() {
+        KeyScript::parse("frame fast\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "line 3: expected `<time> <key>`: 10 a b")]
+    fn too_many_words() {
+        KeyScript::parse("0 a\n\n10 a b\n");
+    }
+
+    #[test]
+    fn replays_into_kbd() {
+        // @KBD, D=M, @R0, M=D, @0, 0;JMP: copies KBD into R0 every 6 cycles
+        let mut cpu = Cpu::new();
+        cpu.load(
+            "0110000000000000
+             1111110000010000
+             0000000000000000
+             1110001100001000
+             0000000000000000
+             1110101010000111",
+        );
+        let mut script = KeyScript::parse("0 A\n5 RIGHT\n8 0\n");
+
+        assert_eq!(script.run(&mut cpu, 3), 3);
+        assert_eq!(cpu.ram(KBD), 65);
+        assert_eq!(cpu.ram(0), 0);
+        script.run(&mut cpu, 7);
+        // R0 got A at cycle 4 and RIGHT at cycle 10; KBD was cleared at 8
+        assert_eq!(cpu.ram(0), 132);
+        assert_eq!(cpu.ram(KBD), 0);
+        script.run(&mut cpu, 100);
+        assert_eq!(cpu.cycles(), 110);
+        assert_eq!(cpu.ram(0), 0);
+
+        // what the run recorded replays as the same script
+        let recorded = KeyScript::from(cpu.key_events());
+        assert_eq!(recorded.to_text(), "0 65\n5 132\n8 0\n");
+        assert_eq!(
+            KeyScript::parse(&recorded.to_text()).to_text(),
+            recorded.to_text()
+        );
+    }
+}
@@ -0,0 +1,8 @@
+pub mod cpu;
+pub mod debugger;
+mod fast;
+pub mod keyboard;
+pub mod profiler;
+pub mod screen;
+pub mod test_script;
+pub mod trace;
@@ -0,0 +1,189 @@
+use hack_cpu::cpu::{Cpu, RAM_SIZE};
+use hack_cpu::debugger::Debugger;
+use hack_cpu::keyboard::KeyScript;
+use hack_cpu::profiler::Profiler;
+use hack_cpu::test_script::TestScript;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::Instant;
+
+// usage: hack_cpu file.hack | file.asm [max_cycles]
+//                 [--screen=snapshot.png | --screen=snapshot.pbm]
+//                 [--keys=input.keys] [--record-keys=output.keys]
+//                 [--profile] [--profile-collapsed=stacks.folded] [--fast]
+//                 [--trace=trace.txt | --trace=-]
+//        hack_cpu --benchmark file.hack | file.asm [max_cycles]
+//        hack_cpu file.tst
+//        hack_cpu --debug file.asm | file.hack
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let positionals: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let path = positionals
+        .first()
+        .map_or("../../06/files/Add.hack", |path| path.as_str());
+    let flag = |name: &str| args.iter().find_map(|arg| arg.strip_prefix(name));
+    let screen_path = flag("--screen=");
+    let keys_path = flag("--keys=");
+    let record_path = flag("--record-keys=");
+    let collapsed_path = flag("--profile-collapsed=");
+    let trace_path = flag("--trace=");
+    let profile = args.iter().any(|arg| arg == "--profile");
+    let fast = args.iter().any(|arg| arg == "--fast");
+
+    if args.iter().any(|arg| arg == "--debug") {
+        Debugger::new(path).repl();
+        return;
+    }
+
+    if path.ends_with(".tst") {
+        let mut script = TestScript::new(path, Cpu::new());
+        match script.run() {
+            Ok(()) => println!("End of script - Comparison ended successfully"),
+            Err(mismatch) => {
+                print!("{}", mismatch);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let max_cycles = match positionals.get(1) {
+        Some(s) => s.parse().expect("max_cycles must be a number"),
+        None => 1_000_000,
+    };
+
+    if args.iter().any(|arg| arg == "--benchmark") {
+        benchmark(path, max_cycles);
+        return;
+    }
+
+    let (mut cpu, labels) = load(path);
+    let mut keys = keys_path.map(KeyScript::from_file);
+    let mut profiler = if profile || collapsed_path.is_some() {
+        Some(Profiler::new(&labels, &cpu))
+    } else {
+        None
+    };
+
+    // "-" streams the trace to stdout
+    let mut trace: Option<Box<dyn Write>> = trace_path.map(|trace_path| -> Box<dyn Write> {
+        if trace_path == "-" {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            Box::new(BufWriter::new(
+                File::create(trace_path).expect("Cannot create trace file!"),
+            ))
+        }
+    });
+
+    let start = cpu.cycles();
+    if fast && keys.is_none() && profiler.is_none() && trace.is_none() {
+        cpu.run_fast(max_cycles);
+    }
+    while cpu.cycles() - start < max_cycles && !cpu.is_halted() {
+        if let Some(keys) = &mut keys {
+            keys.feed(&mut cpu);
+        }
+        let pc = cpu.pc();
+        let before = cpu.cycles();
+        match &mut trace {
+            Some(out) => {
+                if let Some(entry) = cpu.step_traced() {
+                    writeln!(out, "{}", entry).expect("Cannot write trace!");
+                }
+            }
+            None => {
+                cpu.step();
+            }
+        }
+        let running = !cpu.is_halted();
+        if let Some(profiler) = &mut profiler {
+            if cpu.cycles() > before {
+                profiler.record(pc, cpu.pc());
+            }
+        }
+        if !running {
+            break;
+        }
+    }
+    let cycles = cpu.cycles() - start;
+    if let Some(mut out) = trace {
+        out.flush().expect("Cannot write trace!");
+    }
+
+    println!(
+        "{} cycles{}",
+        cycles,
+        if cpu.is_halted() { ", halted" } else { "" }
+    );
+    println!("A = {}  D = {}  PC = {}", cpu.a(), cpu.d(), cpu.pc());
+    for address in 0..16 {
+        println!("RAM[{}] = {}", address, cpu.ram(address));
+    }
+    if let Some(screen_path) = screen_path {
+        cpu.save_screen(screen_path);
+    }
+    if let Some(record_path) = record_path {
+        KeyScript::from(cpu.key_events()).save(record_path);
+    }
+    if let Some(profiler) = profiler {
+        if profile {
+            println!();
+            print!("{}", profiler.flat());
+        }
+        if let Some(collapsed_path) = collapsed_path {
+            std::fs::write(collapsed_path, profiler.collapsed())
+                .expect("Cannot write collapsed stacks!");
+        }
+    }
+}
+
+// labels only come with an .asm program
+fn load(path: &str) -> (Cpu, Vec<(String, usize)>) {
+    if path.ends_with(".asm") {
+        let mut cpu = Cpu::new();
+        let labels = cpu.load_asm(path).labels();
+        (cpu, labels)
+    } else {
+        (Cpu::from_file(path), Vec::new())
+    }
+}
+
+// step() against run_fast on the same program; both must end in the same state
+fn benchmark(path: &str, max_cycles: usize) {
+    let (mut slow, _) = load(path);
+    let (mut fast, _) = load(path);
+
+    let started = Instant::now();
+    let slow_cycles = slow.run(max_cycles);
+    let slow_seconds = started.elapsed().as_secs_f64();
+    let started = Instant::now();
+    let fast_cycles = fast.run_fast(max_cycles);
+    let fast_seconds = started.elapsed().as_secs_f64();
+
+    let rate = |cycles: usize, seconds: f64| cycles as f64 / seconds.max(1e-9) / 1e6;
+    println!(
+        "step():     {} cycles in {:.3}s, {:.1}M cycles/s",
+        slow_cycles,
+        slow_seconds,
+        rate(slow_cycles, slow_seconds)
+    );
+    println!(
+        "run_fast(): {} cycles in {:.3}s, {:.1}M cycles/s",
+        fast_cycles,
+        fast_seconds,
+        rate(fast_cycles, fast_seconds)
+    );
+    println!("speedup {:.1}x", slow_seconds / fast_seconds.max(1e-9));
+
+    let same = slow_cycles == fast_cycles
+        && (slow.a(), slow.d(), slow.pc()) == (fast.a(), fast.d(), fast.pc())
+        && (0..RAM_SIZE).all(|address| slow.ram(address) == fast.ram(address));
+    if same {
+        println!("RAM, registers and PC match");
+    } else {
+        println!("MISMATCH between step() and run_fast()");
+        std::process::exit(1);
+    }
+}
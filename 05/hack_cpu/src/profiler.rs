@@ -0,0 +1,447 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::cpu::Cpu;
+
+// deeper than the Hack stack can hold; a program that keeps jumping into
+// functions without returning stays at this depth instead of growing the tree
+const MAX_DEPTH: usize = 1024;
+
+// Attributes executed cycles to the enclosing assembler label and, for
+// VM-translated programs, to a shadow call stack of functions.
+//
+// A function is any label that a `@f 0;JMP` jumps to when a return label
+// (`return-address.N` from `call`, `$vm.return.N` from a shared routine)
+// follows the jump. Such a jump pushes the function, jumping to a return
+// label from anywhere else pops it.
+pub struct Profiler {
+    label_names: Vec<String>,
+    label_at: Vec<Option<usize>>,
+    label_cycles: Vec<usize>,
+    function_names: Vec<String>,
+    entries: HashMap<usize, usize>,
+    returns: HashSet<usize>,
+    nodes: Vec<Node>,
+    node: usize,
+    // calls past MAX_DEPTH, whose returns must not pop a node
+    skipped: usize,
+    cycles: usize,
+}
+
+// one call path; the root is code that runs outside any function
+struct Node {
+    function: Option<usize>,
+    parent: usize,
+    depth: usize,
+    children: HashMap<usize, usize>,
+    cycles: usize,
+}
+
+impl Profiler {
+    // `labels` as Assembler::labels gives them
+    pub fn new(labels: &[(String, usize)], cpu: &Cpu) -> Self {
+        let rom_len = cpu.rom_len();
+        let is_return =
+            |label: &str| label.starts_with("return-address.") || label.starts_with("$vm.return.");
+
+        // return labels only mark call sites, they do not enclose code
+        let mut label_names = Vec::new();
+        let mut label_at = vec![None; rom_len];
+        let mut enclosing = labels
+            .iter()
+            .filter(|(label, _)| !is_return(label))
+            .peekable();
+        let mut current = None;
+        for (address, slot) in label_at.iter_mut().enumerate() {
+            while let Some((label, _)) = enclosing.next_if(|(_, start)| *start <= address) {
+                label_names.push(label.to_string());
+                current = Some(label_names.len() - 1);
+            }
+            *slot = current;
+        }
+
+        let returns: HashSet<usize> = labels
+            .iter()
+            .filter(|(label, _)| is_return(label))
+            .map(|(_, address)| *address)
+            .collect();
+        let mut function_names = Vec::new();
+        let mut entries = HashMap::new();
+        for address in 1..rom_len {
+            let is_jump = cpu.rom(address) & 0xe007 == 0xe007;
+            let loads_target = cpu.rom(address - 1) & 0x8000 == 0;
+            if !is_jump || !loads_target || !returns.contains(&(address + 1)) {
+                continue;
+            }
+            let target = cpu.rom(address - 1) as usize;
+            if entries.contains_key(&target) {
+                continue;
+            }
+            let name = labels
+                .iter()
+                .find(|(label, label_address)| *label_address == target && !is_return(label))
+                .map_or_else(|| format!("ROM {}", target), |(label, _)| label.to_string());
+            function_names.push(name);
+            entries.insert(target, function_names.len() - 1);
+        }
+
+        Profiler {
+            label_cycles: vec![0; label_names.len()],
+            label_names,
+            label_at,
+            function_names,
+            entries,
+            returns,
+            nodes: vec![Node {
+                function: None,
+                parent: 0,
+                depth: 0,
+                children: HashMap::new(),
+                cycles: 0,
+            }],
+            node: 0,
+            skipped: 0,
+            cycles: 0,
+        }
+    }
+
+    // one executed instruction at `pc` that left the cpu at `next_pc`
+    pub fn record(&mut self, pc: usize, next_pc: usize) {
+        self.cycles += 1;
+        self.nodes[self.node].cycles += 1;
+        if let Some(Some(label)) = self.label_at.get(pc) {
+            self.label_cycles[*label] += 1;
+        }
+        if next_pc == pc + 1 {
+            return;
+        }
+        // a loop back to the top of a function is not a call; calls are the
+        // jumps that have a return label right behind them
+        if self.returns.contains(&(pc + 1)) {
+            if let Some(function) = self.entries.get(&next_pc) {
+                if self.nodes[self.node].depth == MAX_DEPTH {
+                    self.skipped += 1;
+                } else {
+                    self.node = self.child(*function);
+                }
+            }
+        } else if self.returns.contains(&next_pc) {
+            if self.skipped > 0 {
+                self.skipped -= 1;
+            } else if self.node != 0 {
+                self.node = self.nodes[self.node].parent;
+            }
+        }
+    }
+
+    // Cpu::run while recording every instruction
+    pub fn run(&mut self, cpu: &mut Cpu, max_cycles: usize) -> usize {
+        let start = cpu.cycles();
+        while cpu.cycles() - start < max_cycles {
+            let pc = cpu.pc();
+            let before = cpu.cycles();
+            let running = cpu.step();
+            if cpu.cycles() > before {
+                self.record(pc, cpu.pc());
+            }
+            if !running {
+                break;
+            }
+        }
+        cpu.cycles() - start
+    }
+
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    // cycles per label and per function (self and with callees), busiest first
+    pub fn flat(&self) -> String {
+        let mut function_self = vec![0; self.function_names.len()];
+        let mut outside = 0;
+        for node in &self.nodes {
+            match node.function {
+                Some(function) => function_self[function] += node.cycles,
+                None => outside += node.cycles,
+            }
+        }
+
+        // children are always created after their parent
+        let mut subtree: Vec<usize> = self.nodes.iter().map(|node| node.cycles).collect();
+        for idx in (1..self.nodes.len()).rev() {
+            subtree[self.nodes[idx].parent] += subtree[idx];
+        }
+        // a function's total is the subtrees where it first appears on the path,
+        // so recursion is not counted twice
+        let mut function_total = vec![0; self.function_names.len()];
+        let mut on_path = vec![0; self.function_names.len()];
+        let mut stack = vec![(0, true)];
+        while let Some((idx, entering)) = stack.pop() {
+            let function = self.nodes[idx].function;
+            if !entering {
+                if let Some(function) = function {
+                    on_path[function] -= 1;
+                }
+                continue;
+            }
+            if let Some(function) = function {
+                if on_path[function] == 0 {
+                    function_total[function] += subtree[idx];
+                }
+                on_path[function] += 1;
+            }
+            stack.push((idx, false));
+            stack.extend(
+                self.nodes[idx]
+                    .children
+                    .values()
+                    .map(|child| (*child, true)),
+            );
+        }
+
+        let mut text = String::new();
+        if !self.function_names.is_empty() {
+            text += "    self   self%     total  total%  function\n";
+            let mut order: Vec<usize> = (0..self.function_names.len()).collect();
+            order.sort_by_key(|function| std::cmp::Reverse(function_self[*function]));
+            for function in order.into_iter().filter(|f| function_total[*f] > 0) {
+                text += &format!(
+                    "{:>8} {:6.2}% {:>9} {:6.2}%  {}\n",
+                    function_self[function],
+                    self.percent(function_self[function]),
+                    function_total[function],
+                    self.percent(function_total[function]),
+                    self.function_names[function]
+                );
+            }
+            if outside > 0 {
+                text += &format!(
+                    "{:>8} {:6.2}% {:>9} {:6.2}%  (outside functions)\n",
+                    outside,
+                    self.percent(outside),
+                    outside,
+                    self.percent(outside)
+                );
+            }
+            text += "\n";
+        }
+
+        text += "  cycles       %  label\n";
+        let mut order: Vec<usize> = (0..self.label_names.len()).collect();
+        order.sort_by_key(|label| std::cmp::Reverse(self.label_cycles[*label]));
+        for label in order.into_iter().filter(|l| self.label_cycles[*l] > 0) {
+            text += &format!(
+                "{:>8} {:6.2}%  {}\n",
+                self.label_cycles[label],
+                self.percent(self.label_cycles[label]),
+                self.label_names[label]
+            );
+        }
+        let unlabelled = self.cycles - self.label_cycles.iter().sum::<usize>();
+        if unlabelled > 0 {
+            text += &format!(
+                "{:>8} {:6.2}%  (before the first label)\n",
+                unlabelled,
+                self.percent(unlabelled)
+            );
+        }
+        text
+    }
+
+    // `Sys.init;Main.main;Math.multiply 1234` per call path, as flamegraph.pl reads it
+    pub fn collapsed(&self) -> String {
+        let mut lines = Vec::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            let mut path = Vec::new();
+            let mut walk = idx;
+            while walk != 0 {
+                let function = self.nodes[walk].function.unwrap();
+                path.push(self.function_names[function].as_str());
+                walk = self.nodes[walk].parent;
+            }
+            if path.is_empty() {
+                path.push("(outside functions)");
+            }
+            path.reverse();
+            lines.push(format!("{} {}\n", path.join(";"), node.cycles));
+        }
+        lines.sort();
+        lines.concat()
+    }
+
+    fn child(&mut self, function: usize) -> usize {
+        if let Some(child) = self.nodes[self.node].children.get(&function) {
+            return *child;
+        }
+        let child = self.nodes.len();
+        self.nodes.push(Node {
+            function: Some(function),
+            parent: self.node,
+            depth: self.nodes[self.node].depth + 1,
+            children: HashMap::new(),
+            cycles: 0,
+        });
+        self.nodes[self.node].children.insert(function, child);
+        child
+    }
+
+    fn percent(&self, cycles: usize) -> f64 {
+        if self.cycles == 0 {
+            0.0
+        } else {
+            cycles as f64 * 100.0 / self.cycles as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    // Sys.f counts D down from 3 and calls Sys.g; return addresses go in
+    // R14 and R15
+    const TWO_FUNCTIONS: &str = "(START)
+@return-address.0
+D=A
+@R14
+M=D
+@Sys.f
+0;JMP
+(return-address.0)
+(END)
+@END
+0;JMP
+(Sys.f)
+@3
+D=A
+(f.LOOP)
+D=D-1
+@f.LOOP
+D;JGT
+@return-address.1
+D=A
+@R15
+M=D
+@Sys.g
+0;JMP
+(return-address.1)
+@R14
+A=M
+0;JMP
+(Sys.g)
+@R15
+A=M
+0;JMP
+";
+
+    // Sys.r calls itself until RAM[0] counts down to 0, return addresses
+    // on a stack from RAM[1]
+    const RECURSION: &str = "@1100
+D=A
+@R0
+M=D
+@256
+D=A
+@R1
+M=D
+@return-address.0
+D=A
+@R1
+AM=M+1
+M=D
+@Sys.r
+0;JMP
+(return-address.0)
+(END)
+@END
+0;JMP
+(Sys.r)
+@R0
+MD=M-1
+@Sys.r.call
+D;JNE
+(Sys.r.return)
+@R1
+M=M-1
+A=M+1
+A=M
+0;JMP
+(Sys.r.call)
+@return-address.1
+D=A
+@R1
+AM=M+1
+M=D
+@Sys.r
+0;JMP
+(return-address.1)
+@Sys.r.return
+0;JMP
+";
+
+    fn profile(name: &str, asm: &str, max_cycles: usize) -> Profiler {
+        let path =
+            std::env::temp_dir().join(format!("profiler_{}_{}.asm", name, std::process::id()));
+        fs::write(&path, asm).unwrap();
+        let mut cpu = Cpu::new();
+        let labels = cpu.load_asm(path.to_str().unwrap()).labels();
+        fs::remove_file(&path).ok();
+        let mut profiler = Profiler::new(&labels, &cpu);
+        profiler.run(&mut cpu, max_cycles);
+        profiler
+    }
+
+    #[test]
+    fn cycles_per_label_and_function() {
+        // START 6, Sys.f 2, f.LOOP 3 rounds of 3 and 6 to call Sys.g,
+        // Sys.g 3, f.LOOP 3 to return, then END until the cpu sees it halt
+        let profiler = profile("two_functions", TWO_FUNCTIONS, 1000);
+        assert_eq!(profiler.cycles(), 31);
+        assert_eq!(
+            profiler.flat(),
+            "    self   self%     total  total%  function
+      20  64.52%        23  74.19%  Sys.f
+       3   9.68%         3   9.68%  Sys.g
+       8  25.81%         8  25.81%  (outside functions)
+
+  cycles       %  label
+      18  58.06%  f.LOOP
+       6  19.35%  START
+       3   9.68%  Sys.g
+       2   6.45%  END
+       2   6.45%  Sys.f
+"
+        );
+    }
+
+    #[test]
+    fn collapsed_stacks() {
+        let profiler = profile("collapsed", TWO_FUNCTIONS, 1000);
+        assert_eq!(
+            profiler.collapsed(),
+            "(outside functions) 8\nSys.f 20\nSys.f;Sys.g 3\n"
+        );
+    }
+
+    #[test]
+    fn returns_past_max_depth_do_not_pop() {
+        // 1100 calls deep; Sys.r runs 11 instructions on the way down and 7
+        // on the way back, the last call 9 in all
+        let profiler = profile("recursion", RECURSION, 1_000_000);
+        assert_eq!(profiler.cycles(), 15 + 1099 * 18 + 9 + 2);
+        let collapsed = profiler.collapsed();
+        let lines: Vec<&str> = collapsed.lines().collect();
+        assert_eq!(lines.len(), MAX_DEPTH + 1);
+        // main and END, none of the unwinding
+        assert_eq!(lines[0], "(outside functions) 17");
+        assert_eq!(lines[1], "Sys.r 18");
+        // the calls past MAX_DEPTH count towards the deepest node
+        let deepest = vec!["Sys.r"; MAX_DEPTH].join(";");
+        let deepest_cycles = (1100 - MAX_DEPTH) * 18 + 9;
+        assert!(lines.contains(&format!("{} {}", deepest, deepest_cycles).as_str()));
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs;
+
+use crate::cpu::SCREEN_SIZE;
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+const WORDS_PER_ROW: usize = WIDTH / 16;
+
+// pixel (x, y) is bit x % 16 of word y * 32 + x / 16; the lowest bit is the leftmost pixel
+pub fn pixel(screen: &[i16], x: usize, y: usize) -> bool {
+    let word = screen[y * WORDS_PER_ROW + x / 16] as u16;
+    word >> (x % 16) & 1 == 1
+}
+
+// one bit per pixel, set for black, as PBM wants it
+fn packed_rows(screen: &[i16]) -> Vec<u8> {
+    assert_eq!(
+        screen.len(),
+        SCREEN_SIZE,
+        "ERROR: screen must be {} words",
+        SCREEN_SIZE
+    );
+    let mut bytes = Vec::with_capacity(WIDTH / 8 * HEIGHT);
+    for word in screen {
+        let word = *word as u16;
+        // reverse each word so the leftmost pixel becomes the highest bit
+        let word = word.reverse_bits();
+        bytes.push((word >> 8) as u8);
+        bytes.push(word as u8);
+    }
+    bytes
+}
+
+// binary PBM (P4)
+pub fn to_pbm(screen: &[i16]) -> Vec<u8> {
+    let mut bytes = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+    bytes.extend(packed_rows(screen));
+    bytes
+}
+
+// 1-bit grayscale PNG, where 0 is black
+pub fn to_png(screen: &[i16]) -> Vec<u8> {
+    let data: Vec<u8> = packed_rows(screen).iter().map(|byte| !byte).collect();
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+    let mut writer = encoder.write_header().expect("Cannot write png header!");
+    writer
+        .write_image_data(&data)
+        .expect("Cannot write png data!");
+    writer.finish().expect("Cannot finish png!");
+    bytes
+}
+
+// the format follows the extension, .png or .pbm
+pub fn save(screen: &[i16], path: &str) {
+    let bytes = if path.ends_with(".png") {
+        to_png(screen)
+    } else if path.ends_with(".pbm") {
+        to_pbm(screen)
+    } else {
+        panic!("ERROR: screen snapshots are .png or .pbm, not {}", path);
+    };
+    fs::write(path, bytes).expect("Cannot write screen snapshot!");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROW_BYTES: usize = WIDTH / 8;
+
+    // (0, 0), (31, 0), (0, 1) and (1, 1) black, and the last 16 pixels
+    fn fixture() -> Vec<i16> {
+        let mut screen = vec![0; SCREEN_SIZE];
+        screen[0] = 1;
+        screen[1] = i16::MIN;
+        screen[WORDS_PER_ROW] = 0b11;
+        screen[SCREEN_SIZE - 1] = -1;
+        screen
+    }
+
+    // packed rows with the leftmost pixel in the highest bit of each byte
+    fn expected_rows() -> Vec<u8> {
+        let mut rows = vec![0; ROW_BYTES * HEIGHT];
+        rows[0] = 0b1000_0000;
+        rows[3] = 0b0000_0001;
+        rows[ROW_BYTES] = 0b1100_0000;
+        rows[ROW_BYTES * HEIGHT - 2] = 0xff;
+        rows[ROW_BYTES * HEIGHT - 1] = 0xff;
+        rows
+    }
+
+    #[test]
+    fn pixels_follow_the_memory_map() {
+        let screen = fixture();
+        assert!(pixel(&screen, 0, 0));
+        assert!(!pixel(&screen, 1, 0));
+        assert!(pixel(&screen, 31, 0));
+        assert!(!pixel(&screen, 16, 0));
+        assert!(pixel(&screen, 0, 1));
+        assert!(pixel(&screen, 1, 1));
+        assert!(pixel(&screen, WIDTH - 16, HEIGHT - 1));
+        assert!(!pixel(&screen, WIDTH - 17, HEIGHT - 1));
+    }
+
+    #[test]
+    fn pbm_bytes() {
+        let mut expected = b"P4\n512 256\n".to_vec();
+        expected.extend(expected_rows());
+        assert_eq!(to_pbm(&fixture()), expected);
+    }
+
+    #[test]
+    fn png_pixels() {
+        let png = to_png(&fixture());
+        let decoder = png::Decoder::new(&png[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (WIDTH as u32, HEIGHT as u32));
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        assert_eq!(info.bit_depth, png::BitDepth::One);
+        // white is 1 in the PNG
+        let expected: Vec<u8> = expected_rows().iter().map(|byte| !byte).collect();
+        assert_eq!(data, expected);
+    }
+}
@@ -0,0 +1,627 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::Cpu;
+
+// What a test script drives: the CPU emulator here, a chip in hdl_simulator.
+pub trait Target {
+    // `load file`, resolved against the script's directory
+    fn load(&mut self, path: &Path);
+    // `load` on its own
+    fn load_default(&mut self) {}
+    fn get(&self, name: &str) -> i16;
+    fn set(&mut self, name: &str, value: i16);
+    fn eval(&mut self) {}
+    fn tick(&mut self);
+    fn tock(&mut self);
+    // what `time` shows
+    fn time(&self) -> String;
+}
+
+// Runs the test scripts of the course (.tst), writing the .out file and
+// comparing it with the .cmp file line by line. Every file the script names
+// (load, output-file, compare-to) is relative to the script's directory, so
+// the .out file is written where `output-file` says, not to the working
+// directory.
+pub struct TestScript<T: Target> {
+    dir: PathBuf,
+    commands: Vec<Command>,
+    target: T,
+    output_list: Vec<Column>,
+    output_file: Option<PathBuf>,
+    compare: Option<Vec<String>>,
+    lines: Vec<String>,
+    mismatch: Option<Mismatch>,
+}
+
+// first .out line that differs from the .cmp file
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+    // (column, expected, actual) for the columns that differ
+    pub columns: Vec<(String, String, String)>,
+}
+
+#[derive(Debug, Clone)]
+enum Command {
+    Load(Option<String>),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(String, i16),
+    Eval,
+    Tick,
+    Tock,
+    TickTock,
+    Output,
+    Echo(String),
+    ClearEcho,
+    Repeat(Option<usize>, Vec<Command>),
+    While(Condition, Vec<Command>),
+}
+
+// `RAM[2]%D2.6.2`: variable, then format, left padding, width and right padding
+#[derive(Debug, Clone)]
+struct Column {
+    name: String,
+    format: char,
+    pad_left: usize,
+    width: usize,
+    pad_right: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    name: String,
+    op: String,
+    value: i16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Comma,
+    Semicolon,
+    Open,
+    Close,
+}
+
+impl<T: Target> TestScript<T> {
+    pub fn new(path: &str, target: T) -> Self {
+        let text = fs::read_to_string(path).expect("Not found test script!");
+        let dir = Path::new(path)
+            .parent()
+            .map(|dir| dir.to_path_buf())
+            .unwrap_or_default();
+        let tokens = tokenize(&text);
+        let mut idx = 0;
+        let commands = parse_block(&tokens, &mut idx);
+        if idx < tokens.len() {
+            panic!("ERROR: unexpected {:?} in {}", tokens[idx], path);
+        }
+
+        TestScript {
+            dir,
+            commands,
+            target,
+            output_list: Vec::new(),
+            output_file: None,
+            compare: None,
+            lines: Vec::new(),
+            mismatch: None,
+        }
+    }
+
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+
+    // lines written by `output` so far, the header included
+    pub fn output(&self) -> &[String] {
+        &self.lines
+    }
+
+    // runs the whole script, stopping at the first comparison failure;
+    // the .out file is written either way
+    pub fn run(&mut self) -> Result<(), Mismatch> {
+        let commands = self.commands.clone();
+        self.execute(&commands);
+        if let Some(path) = &self.output_file {
+            let mut text = self.lines.join("\n");
+            text.push('\n');
+            fs::write(path, text).expect("Cannot write output file!");
+        }
+        match &self.mismatch {
+            Some(mismatch) => Err(mismatch.clone()),
+            None => Ok(()),
+        }
+    }
+
+    fn execute(&mut self, commands: &[Command]) {
+        for command in commands {
+            if self.mismatch.is_some() {
+                return;
+            }
+            match command {
+                Command::Load(file_name) => match file_name {
+                    Some(file_name) => self.target.load(&self.dir.join(file_name)),
+                    None => self.target.load_default(),
+                },
+                Command::OutputFile(file_name) => self.output_file = Some(self.dir.join(file_name)),
+                Command::CompareTo(file_name) => {
+                    let text = fs::read_to_string(self.dir.join(file_name))
+                        .expect("Not found compare file!");
+                    self.compare = Some(text.lines().map(|line| line.to_string()).collect());
+                }
+                Command::OutputList(columns) => {
+                    self.output_list = columns.clone();
+                    let header = columns
+                        .iter()
+                        .map(|column| column.header())
+                        .collect::<Vec<String>>();
+                    self.write_line(format!("|{}|", header.join("|")));
+                }
+                Command::Set(name, value) => self.target.set(name, *value),
+                Command::Eval => self.target.eval(),
+                Command::Tick => self.target.tick(),
+                Command::Tock => self.target.tock(),
+                Command::TickTock => {
+                    self.target.tick();
+                    self.target.tock();
+                }
+                Command::Output => {
+                    let values = self
+                        .output_list
+                        .iter()
+                        .map(|column| column.value(&self.target))
+                        .collect::<Vec<String>>();
+                    self.write_line(format!("|{}|", values.join("|")));
+                }
+                Command::Echo(text) => println!("{}", text),
+                Command::ClearEcho => {}
+                Command::Repeat(count, body) => {
+                    let mut n = 0;
+                    // not is_none_or, which needs Rust 1.82
+                    #[allow(clippy::unnecessary_map_or)]
+                    while count.map_or(true, |count| n < count) && self.mismatch.is_none() {
+                        self.execute(body);
+                        n += 1;
+                    }
+                }
+                Command::While(condition, body) => {
+                    while condition.holds(self.target.get(&condition.name))
+                        && self.mismatch.is_none()
+                    {
+                        self.execute(body);
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_line(&mut self, line: String) {
+        if let Some(compare) = &self.compare {
+            let expected = compare
+                .get(self.lines.len())
+                .map_or("", |line| line.trim_end());
+            if !matches_line(expected, &line) {
+                let columns = self
+                    .output_list
+                    .iter()
+                    .zip(expected.split('|').skip(1).zip(line.split('|').skip(1)))
+                    .filter(|(_, (expected, actual))| !matches_line(expected, actual))
+                    .map(|(column, (expected, actual))| {
+                        let trim = |value: &str| value.trim().to_string();
+                        (column.name.to_string(), trim(expected), trim(actual))
+                    })
+                    .collect();
+                self.mismatch = Some(Mismatch {
+                    line: self.lines.len() + 1,
+                    expected: expected.to_string(),
+                    actual: line.clone(),
+                    columns,
+                });
+            }
+        }
+        self.lines.push(line);
+    }
+}
+
+// one instruction takes a whole tick-tock cycle
+impl Target for Cpu {
+    // .hack files are loaded as they are, .asm files are assembled first
+    fn load(&mut self, path: &Path) {
+        let path = path.to_str().unwrap();
+        if path.ends_with(".asm") {
+            self.load_asm(path);
+        } else {
+            Cpu::load(self, &fs::read_to_string(path).expect("Not found program!"));
+        }
+    }
+
+    fn get(&self, name: &str) -> i16 {
+        match name {
+            "A" => self.a(),
+            "D" => self.d(),
+            "PC" => self.pc() as i16,
+            "time" => self.cycles() as i16,
+            _ => match indexed(name) {
+                Some(("RAM", address)) => self.ram(address),
+                Some(("ROM", address)) => self.rom(address) as i16,
+                _ => panic!("ERROR: unknown variable {}", name),
+            },
+        }
+    }
+
+    fn set(&mut self, name: &str, value: i16) {
+        match name {
+            "A" => self.set_a(value),
+            "D" => self.set_d(value),
+            "PC" => self.set_pc(value as u16 as usize),
+            _ => match indexed(name) {
+                Some(("RAM", address)) => self.set_ram(address, value),
+                _ => panic!("ERROR: cannot set {}", name),
+            },
+        }
+    }
+
+    fn tick(&mut self) {}
+
+    fn tock(&mut self) {
+        self.step();
+    }
+
+    fn time(&self) -> String {
+        self.cycles().to_string()
+    }
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Comparison failure at line {}", self.line)?;
+        writeln!(f, "expected: {}", self.expected)?;
+        writeln!(f, "actual:   {}", self.actual)?;
+        for (name, expected, actual) in &self.columns {
+            writeln!(f, "  {}: expected {}, got {}", name, expected, actual)?;
+        }
+        Ok(())
+    }
+}
+
+impl Column {
+    fn new(word: &str) -> Self {
+        let (name, format) = match word.find('%') {
+            Some(idx) => (&word[..idx], &word[idx + 1..]),
+            None => (word, "D1.6.1"),
+        };
+        let mut chars = format.chars();
+        let kind = chars.next().expect("ERROR: empty output format");
+        let sizes = chars
+            .as_str()
+            .split('.')
+            .map(|n| n.parse::<usize>().expect("ERROR: bad output format"))
+            .collect::<Vec<usize>>();
+        if !"BDXS".contains(kind) || sizes.len() != 3 {
+            panic!("ERROR: bad output format {}", word);
+        }
+        Column {
+            name: name.to_string(),
+            format: kind,
+            pad_left: sizes[0],
+            width: sizes[1],
+            pad_right: sizes[2],
+        }
+    }
+
+    // the variable name centred over the whole column
+    fn header(&self) -> String {
+        let space = self.pad_left + self.width + self.pad_right;
+        let name: String = self.name.chars().take(space).collect();
+        let left = (space - name.len()) / 2;
+        let right = space - left - name.len();
+        format!("{}{}{}", " ".repeat(left), name, " ".repeat(right))
+    }
+
+    fn value<T: Target>(&self, target: &T) -> String {
+        let text = match self.format {
+            _ if self.name == "time" => target.time(),
+            'B' => format!("{:016b}", target.get(&self.name) as u16),
+            'X' => format!("{:04X}", target.get(&self.name) as u16),
+            _ => target.get(&self.name).to_string(),
+        };
+        // binary and hex keep the low digits, decimal is right-aligned
+        let text = match self.format {
+            'B' | 'X' if text.len() > self.width => text[text.len() - self.width..].to_string(),
+            'S' => format!("{:<width$}", text, width = self.width),
+            _ => format!("{:>width$}", text, width = self.width),
+        };
+        format!(
+            "{}{}{}",
+            " ".repeat(self.pad_left),
+            text,
+            " ".repeat(self.pad_right)
+        )
+    }
+}
+
+impl Condition {
+    fn holds(&self, value: i16) -> bool {
+        match self.op.as_str() {
+            "=" => value == self.value,
+            "<>" => value != self.value,
+            "<" => value < self.value,
+            ">" => value > self.value,
+            "<=" => value <= self.value,
+            ">=" => value >= self.value,
+            _ => panic!("ERROR: unknown comparison {}", self.op),
+        }
+    }
+}
+
+// `*` in the .cmp file matches any character
+fn matches_line(expected: &str, actual: &str) -> bool {
+    expected.chars().count() == actual.chars().count()
+        && expected
+            .chars()
+            .zip(actual.chars())
+            .all(|(e, a)| e == '*' || e == a)
+}
+
+// `RAM[16]` -> ("RAM", 16)
+fn indexed(name: &str) -> Option<(&str, usize)> {
+    let open = name.find('[')?;
+    let index = name[open + 1..].strip_suffix(']')?.parse().ok()?;
+    Some((&name[..open], index))
+}
+
+// decimal, or %D / %X / %B prefixed
+fn parse_value(word: &str) -> i16 {
+    let value = match word.get(..2) {
+        Some("%D") => word[2..].parse::<i32>().ok(),
+        Some("%X") => i32::from_str_radix(&word[2..], 16).ok(),
+        Some("%B") => i32::from_str_radix(&word[2..], 2).ok(),
+        _ => word.parse::<i32>().ok(),
+    };
+    match value {
+        Some(value) => value as i16,
+        None => panic!("ERROR: bad value {}", word),
+    }
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        let next = chars.get(idx + 1).copied();
+        if c == '/' && next == Some('/') {
+            while idx < chars.len() && chars[idx] != '\n' {
+                idx += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            idx += 2;
+            while idx < chars.len() && !(chars[idx] == '*' && chars.get(idx + 1) == Some(&'/')) {
+                idx += 1;
+            }
+            idx += 2;
+        } else if c.is_whitespace() {
+            idx += 1;
+        } else if c == '"' {
+            let start = idx + 1;
+            idx = start;
+            while idx < chars.len() && chars[idx] != '"' {
+                idx += 1;
+            }
+            tokens.push(Token::Text(chars[start..idx].iter().collect()));
+            idx += 1;
+        } else if ",;{}".contains(c) {
+            tokens.push(match c {
+                ',' => Token::Comma,
+                ';' => Token::Semicolon,
+                '{' => Token::Open,
+                _ => Token::Close,
+            });
+            idx += 1;
+        } else {
+            let start = idx;
+            let ends_word = |idx: usize| {
+                chars[idx].is_whitespace()
+                    || ",;{}\"".contains(chars[idx])
+                    || (chars[idx] == '/' && matches!(chars.get(idx + 1), Some('/') | Some('*')))
+            };
+            while idx < chars.len() && !ends_word(idx) {
+                idx += 1;
+            }
+            tokens.push(Token::Word(chars[start..idx].iter().collect()));
+        }
+    }
+    tokens
+}
+
+// commands up to a closing brace or the end of the script
+fn parse_block(tokens: &[Token], idx: &mut usize) -> Vec<Command> {
+    let mut commands = Vec::new();
+    while *idx < tokens.len() {
+        match &tokens[*idx] {
+            Token::Close => break,
+            Token::Comma | Token::Semicolon => *idx += 1,
+            _ => commands.push(parse_command(tokens, idx)),
+        }
+    }
+    commands
+}
+
+fn parse_command(tokens: &[Token], idx: &mut usize) -> Command {
+    let mut words = Vec::new();
+    let mut text = String::new();
+    while *idx < tokens.len() {
+        match &tokens[*idx] {
+            Token::Word(word) => words.push(word.as_str()),
+            Token::Text(s) => text = s.to_string(),
+            Token::Open => {
+                *idx += 1;
+                let body = parse_block(tokens, idx);
+                if tokens.get(*idx) != Some(&Token::Close) {
+                    panic!("ERROR: missing }} after {}", words.join(" "));
+                }
+                *idx += 1;
+                return match words.as_slice() {
+                    ["repeat"] => Command::Repeat(None, body),
+                    ["repeat", n] => Command::Repeat(
+                        Some(n.parse().expect("ERROR: repeat count must be a number")),
+                        body,
+                    ),
+                    ["while", name, op, value] => Command::While(
+                        Condition {
+                            name: name.to_string(),
+                            op: op.to_string(),
+                            value: parse_value(value),
+                        },
+                        body,
+                    ),
+                    _ => panic!("ERROR: unknown block {}", words.join(" ")),
+                };
+            }
+            _ => break,
+        }
+        *idx += 1;
+    }
+
+    match words.as_slice() {
+        ["load"] => Command::Load(None),
+        ["load", file_name] => Command::Load(Some(file_name.to_string())),
+        ["output-file", file_name] => Command::OutputFile(file_name.to_string()),
+        ["compare-to", file_name] => Command::CompareTo(file_name.to_string()),
+        ["output-list", columns @ ..] => {
+            Command::OutputList(columns.iter().map(|word| Column::new(word)).collect())
+        }
+        ["set", name, value] => Command::Set(name.to_string(), parse_value(value)),
+        ["eval"] => Command::Eval,
+        ["tick"] => Command::Tick,
+        ["tock"] => Command::Tock,
+        ["ticktock"] => Command::TickTock,
+        ["output"] => Command::Output,
+        ["echo"] => Command::Echo(text),
+        ["clear-echo"] => Command::ClearEcho,
+        _ => panic!("ERROR: unknown command {}", words.join(" ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory holding `files`
+    fn script_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("test_script_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        for (file_name, text) in files {
+            fs::write(dir.join(file_name), text).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn mult_tst_passes() {
+        let mult = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../04/mult");
+        let read = |file_name: &str| fs::read_to_string(mult.join(file_name)).unwrap();
+        let (tst, asm, cmp) = (read("Mult.tst"), read("Mult.asm"), read("Mult.cmp"));
+        let dir = script_dir(
+            "mult",
+            &[("Mult.tst", &tst), ("Mult.asm", &asm), ("Mult.cmp", &cmp)],
+        );
+
+        let path = dir.join("Mult.tst");
+        let mut script = TestScript::new(path.to_str().unwrap(), Cpu::new());
+        if let Err(mismatch) = script.run() {
+            panic!("{}", mismatch);
+        }
+        assert_eq!(script.output().len(), 7);
+        assert_eq!(script.target().ram(2), 42);
+        // written next to the script, as output-file names it
+        let out = fs::read_to_string(dir.join("Mult.out")).unwrap();
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            cmp.lines().collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn output_list_formats() {
+        let dir = script_dir(
+            "formats",
+            &[(
+                "Format.tst",
+                "output-list RAM[0] RAM[1]%X2.4.2 RAM[2]%B1.16.1 RAM[3]%D2.6.2 time%S1.4.1;
+                 set RAM[0] -5, set RAM[1] 255, set RAM[2] 5, set RAM[3] %XFFFF;
+                 output;",
+            )],
+        );
+        let path = dir.join("Format.tst");
+        let mut script = TestScript::new(path.to_str().unwrap(), Cpu::new());
+        script.run().unwrap();
+        // RAM[0] without a format is %D1.6.1
+        assert_eq!(
+            script.output(),
+            &[
+                "| RAM[0] | RAM[1] |      RAM[2]      |  RAM[3]  | time |",
+                "|     -5 |  00FF  | 0000000000000101 |      -1  | 0    |",
+            ]
+        );
+        // no output-file, so nothing is written
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn mismatch_reports_the_line() {
+        let dir = script_dir(
+            "mismatch",
+            &[
+                (
+                    "Count.tst",
+                    "output-file out/Count.out, compare-to Count.cmp,
+                     output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2;
+                     set RAM[0] 1, output;
+                     set RAM[0] 2, set RAM[1] 7, output;
+                     set RAM[0] 3, output;",
+                ),
+                (
+                    "Count.cmp",
+                    "|  RAM[0]  |  RAM[1]  |\n\
+                     |       1  |       0  |\n\
+                     |       2  |       9  |\n\
+                     |       3  |       7  |\n",
+                ),
+            ],
+        );
+        fs::create_dir(dir.join("out")).unwrap();
+        let path = dir.join("Count.tst");
+        let mut script = TestScript::new(path.to_str().unwrap(), Cpu::new());
+        let mismatch = script.run().unwrap_err();
+        assert_eq!(mismatch.line, 3);
+        assert_eq!(mismatch.expected, "|       2  |       9  |");
+        assert_eq!(mismatch.actual, "|       2  |       7  |");
+        assert_eq!(
+            mismatch.columns,
+            vec![("RAM[1]".to_string(), "9".to_string(), "7".to_string())]
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "Comparison failure at line 3\n\
+             expected: |       2  |       9  |\n\
+             actual:   |       2  |       7  |\n  \
+             RAM[1]: expected 9, got 7\n"
+        );
+        // the run stops there, and the .out so far is still written
+        let out = fs::read_to_string(dir.join("out/Count.out")).unwrap();
+        assert_eq!(out.lines().count(), 3);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use std::io::Write;
+
+use crate::cpu::{address_of, Cpu};
+
+// One executed instruction, one line:
+//   <cycle> <pc> <instruction> A=<a> D=<d> [RAM[<address>]=<value>]
+// cycle counts from 1, A and D are the values after the instruction and the
+// RAM write is only there when the instruction has M in its dest, e.g.
+//   7 6 1110001100001000 A=17 D=5 RAM[17]=5
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEntry {
+    pub cycle: usize,
+    pub pc: usize,
+    pub instruction: u16,
+    pub a: i16,
+    pub d: i16,
+    pub write: Option<(usize, i16)>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:016b} A={} D={}",
+            self.cycle, self.pc, self.instruction, self.a, self.d
+        )?;
+        if let Some((address, value)) = self.write {
+            write!(f, " RAM[{}]={}", address, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Cpu {
+    // Cpu::step that also reports what the instruction did; None when nothing ran
+    pub fn step_traced(&mut self) -> Option<TraceEntry> {
+        let pc = self.pc;
+        let before = self.cycles;
+        let address = address_of(self.a);
+        self.step();
+        if self.cycles == before {
+            return None;
+        }
+        let instruction = self.rom(pc);
+        let writes_m = instruction & 0x8000 != 0 && instruction & 0x08 != 0;
+        Some(TraceEntry {
+            cycle: self.cycles,
+            pc,
+            instruction,
+            a: self.a,
+            d: self.d,
+            write: if writes_m {
+                Some((address, self.ram[address]))
+            } else {
+                None
+            },
+        })
+    }
+
+    // Cpu::run with one trace line per instruction written to `out`
+    pub fn run_traced(&mut self, max_cycles: usize, out: &mut dyn Write) -> usize {
+        let start = self.cycles;
+        while self.cycles - start < max_cycles {
+            match self.step_traced() {
+                Some(entry) => writeln!(out, "{}", entry).expect("Cannot write trace!"),
+                None => break,
+            }
+            if self.halted {
+                break;
+            }
+        }
+        self.cycles - start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn trace_lines() {
+        let path = std::env::temp_dir().join(format!("trace_{}.asm", std::process::id()));
+        fs::write(&path, "@17\nD=A\n@5\nD=D+A\n@17\nM=D\n(END)\n@END\n0;JMP\n").unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_asm(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+
+        let mut out = Vec::new();
+        assert_eq!(cpu.run_traced(100, &mut out), 8);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1 0 0000000000010001 A=17 D=0
+2 1 1110110000010000 A=17 D=17
+3 2 0000000000000101 A=5 D=17
+4 3 1110000010010000 A=5 D=22
+5 4 0000000000010001 A=17 D=22
+6 5 1110001100001000 A=17 D=22 RAM[17]=22
+7 6 0000000000000110 A=6 D=22
+8 7 1110101010000111 A=6 D=22
+"
+        );
+        // nothing runs once the cpu has halted
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.step_traced(), None);
+    }
+}
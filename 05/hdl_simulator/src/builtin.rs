@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use crate::hdl::ChipDef;
+
+// Interfaces of the chips simulated natively, written the way the official
+// tools' builtInChips declare them. CLOCKED inputs are only read at the clock
+// edge; the other inputs of a clocked chip still change its output at once.
+const CHIPS: [(&str, &str); 13] = [
+    ("Nand", "CHIP Nand { IN a, b; OUT out; BUILTIN Nand; }"),
+    (
+        "DFF",
+        "CHIP DFF { IN in; OUT out; BUILTIN DFF; CLOCKED in; }",
+    ),
+    (
+        "Register",
+        "CHIP Register { IN in[16], load; OUT out[16]; BUILTIN Register; CLOCKED in, load; }",
+    ),
+    (
+        "ARegister",
+        "CHIP ARegister { IN in[16], load; OUT out[16]; BUILTIN ARegister; CLOCKED in, load; }",
+    ),
+    (
+        "DRegister",
+        "CHIP DRegister { IN in[16], load; OUT out[16]; BUILTIN DRegister; CLOCKED in, load; }",
+    ),
+    (
+        "RAM8",
+        "CHIP RAM8 { IN in[16], load, address[3]; OUT out[16]; BUILTIN RAM8; CLOCKED in, load; }",
+    ),
+    (
+        "RAM64",
+        "CHIP RAM64 { IN in[16], load, address[6]; OUT out[16]; BUILTIN RAM64; CLOCKED in, load; }",
+    ),
+    (
+        "RAM512",
+        "CHIP RAM512 { IN in[16], load, address[9]; OUT out[16]; BUILTIN RAM512; CLOCKED in, load; }",
+    ),
+    (
+        "RAM4K",
+        "CHIP RAM4K { IN in[16], load, address[12]; OUT out[16]; BUILTIN RAM4K; CLOCKED in, load; }",
+    ),
+    (
+        "RAM16K",
+        "CHIP RAM16K { IN in[16], load, address[14]; OUT out[16]; BUILTIN RAM16K; CLOCKED in, load; }",
+    ),
+    (
+        "ROM32K",
+        "CHIP ROM32K { IN address[15]; OUT out[16]; BUILTIN ROM32K; }",
+    ),
+    (
+        "Screen",
+        "CHIP Screen { IN in[16], load, address[13]; OUT out[16]; BUILTIN Screen; CLOCKED in, load; }",
+    ),
+    ("Keyboard", "CHIP Keyboard { OUT out[16]; BUILTIN Keyboard; }"),
+];
+
+pub fn chip(name: &str) -> Option<ChipDef> {
+    CHIPS
+        .iter()
+        .find(|(chip, _)| *chip == name)
+        .map(|(chip, hdl)| {
+            ChipDef::parse(hdl, Path::new(&format!("<builtin {}>", chip)))
+                .unwrap_or_else(|error| panic!("ERROR: {}", error))
+        })
+}
+
+pub fn names() -> Vec<&'static str> {
+    CHIPS.iter().map(|(chip, _)| *chip).collect()
+}
+
+// The state of one built-in memory chip: registers are one word, RAMs, the
+// ROM and the screen one word per address, the keyboard the key held down.
+// Nand and DFF are gates of the netlist and have no Builtin.
+#[derive(Debug, Clone)]
+pub struct Builtin {
+    pub name: String,
+    pub memory: Vec<i16>,
+    // (address, value) sampled at tick, written at tock
+    pending: Option<(usize, i16)>,
+}
+
+impl Builtin {
+    pub fn new(name: &str) -> Self {
+        let words = match name {
+            "Register" | "ARegister" | "DRegister" | "Keyboard" => 1,
+            "RAM8" => 8,
+            "RAM64" => 64,
+            "RAM512" => 512,
+            "RAM4K" => 4096,
+            "RAM16K" => 16384,
+            "ROM32K" => 32768,
+            "Screen" => 8192,
+            _ => panic!("ERROR: there is no built-in {}", name),
+        };
+        Builtin {
+            name: name.to_string(),
+            memory: vec![0; words],
+            pending: None,
+        }
+    }
+
+    // `inputs` are the IN pins in declaration order, the result the OUT pins
+    pub fn eval(&self, inputs: &[i16]) -> Vec<i16> {
+        vec![self.memory[self.address(inputs)]]
+    }
+
+    // rising edge: in, load and address are read
+    pub fn tick(&mut self, inputs: &[i16]) {
+        self.pending = match inputs {
+            [value, load, ..] if *load != 0 => Some((self.address(inputs), *value)),
+            _ => None,
+        };
+    }
+
+    // falling edge: the sampled write shows up on out
+    pub fn tock(&mut self) {
+        if let Some((address, value)) = self.pending.take() {
+            self.memory[address] = value;
+        }
+    }
+
+    fn address(&self, inputs: &[i16]) -> usize {
+        match (self.name.as_str(), inputs) {
+            ("ROM32K", [address]) => *address as u16 as usize,
+            (_, [_, _, address]) => *address as u16 as usize,
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,645 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::hdl::{ChipDef, ParseError, PinDecl, PinRef, Signal};
+use crate::library::Library;
+
+// A mistake in a .hdl file, found without building the netlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // line 0 is a file that could not be read
+        match self.line {
+            0 => write!(f, "{}: {}", self.path.display(), self.message),
+            line => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+        }
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Self {
+        Diagnostic {
+            path: error.path,
+            line: error.line,
+            message: error.message,
+        }
+    }
+}
+
+// for every input bit of a chip, the output bits it changes at once, i.e.
+// without a DFF or a clocked input in between
+type Paths = Vec<Vec<usize>>;
+
+// Checks `chip` and every chip it uses. All the problems are reported, not
+// just the first, sorted by file and line.
+pub fn check(library: &mut Library, chip: &Rc<ChipDef>) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        library,
+        paths: HashMap::new(),
+        diagnostics: Vec::new(),
+    };
+    checker.chip(chip);
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+    diagnostics.dedup();
+    diagnostics
+}
+
+struct Checker<'a> {
+    library: &'a mut Library,
+    // None while the chip itself is being checked
+    paths: HashMap<String, Option<Rc<Paths>>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+// Pin bits as nodes, with an edge wherever a bit changes another one at once.
+// A cycle is a combinational loop.
+#[derive(Default)]
+struct Graph {
+    edges: Vec<Vec<usize>>,
+    // pin name of a chip or internal pin bit, line of a part's pin bit
+    names: Vec<Option<String>>,
+    lines: Vec<usize>,
+}
+
+impl Checker<'_> {
+    // None when the chip is already being checked further up, so it contains itself
+    fn chip(&mut self, chip: &Rc<ChipDef>) -> Option<Rc<Paths>> {
+        if let Some(paths) = self.paths.get(&chip.name) {
+            return paths.clone();
+        }
+        self.paths.insert(chip.name.to_string(), None);
+        let paths = Rc::new(if chip.builtin.is_some() {
+            builtin_paths(chip)
+        } else {
+            self.parts(chip)
+        });
+        self.paths
+            .insert(chip.name.to_string(), Some(Rc::clone(&paths)));
+        Some(paths)
+    }
+
+    fn parts(&mut self, chip: &ChipDef) -> Paths {
+        let mut graph = Graph::default();
+        let mut nets: HashMap<String, Vec<usize>> = HashMap::new();
+        for decl in chip.inputs.iter().chain(&chip.outputs) {
+            let nodes = graph.nets(decl, 0);
+            nets.insert(decl.name.to_string(), nodes);
+        }
+
+        let mut defs = Vec::new();
+        // every part's pins, with its input bits and output bits in order
+        let mut part_pins: Vec<HashMap<String, Vec<usize>>> = Vec::new();
+        for part in &chip.parts {
+            let def = match self.library.try_chip(&part.chip) {
+                Ok(None) => {
+                    self.error(chip, part.line, format!("unknown chip {}", part.chip));
+                    None
+                }
+                Ok(def) => def,
+                Err(error) => {
+                    self.diagnostics.push(Diagnostic::from(error));
+                    None
+                }
+            };
+            let mut pins = HashMap::new();
+            if let Some(def) = &def {
+                let paths = match self.chip(def) {
+                    Some(paths) => paths,
+                    None => {
+                        self.error(chip, part.line, format!("{} contains itself", def.name));
+                        Rc::new(Vec::new())
+                    }
+                };
+                for decl in def.inputs.iter().chain(&def.outputs) {
+                    pins.insert(decl.name.to_string(), graph.nodes(decl.width, part.line));
+                }
+                let inputs: Vec<usize> = def
+                    .inputs
+                    .iter()
+                    .flat_map(|d| pins[&d.name].to_vec())
+                    .collect();
+                let outputs: Vec<usize> = def
+                    .outputs
+                    .iter()
+                    .flat_map(|d| pins[&d.name].to_vec())
+                    .collect();
+                for (input, reach) in inputs.iter().zip(paths.iter()) {
+                    for output in reach {
+                        graph.edges[*input].push(outputs[*output]);
+                    }
+                }
+            }
+            defs.push(def);
+            part_pins.push(pins);
+        }
+
+        // what the parts drive: chip outputs bit by bit, internal pins whole
+        let mut drivers: HashMap<String, Vec<Option<usize>>> = chip
+            .outputs
+            .iter()
+            .map(|decl| (decl.name.to_string(), vec![None; decl.width]))
+            .collect();
+        for ((part, def), pins) in chip.parts.iter().zip(&defs).zip(&part_pins) {
+            let def = match def {
+                Some(def) => def,
+                None => continue,
+            };
+            for connection in &part.connections {
+                let line = connection.line;
+                let decl = match def.output(&connection.pin.name) {
+                    Some(decl) => decl,
+                    None => continue,
+                };
+                let (lo, hi) = match self.bits(chip, line, &connection.pin, decl.width) {
+                    Some(bits) => bits,
+                    None => continue,
+                };
+                let target = match &connection.signal {
+                    Signal::Const(_) => {
+                        self.error(chip, line, "cannot drive a constant".to_string());
+                        continue;
+                    }
+                    Signal::Pin(target) => target,
+                };
+                let (t_lo, t_hi) = if chip.input(&target.name).is_some() {
+                    self.error(
+                        chip,
+                        line,
+                        format!("cannot drive input pin {}", target.name),
+                    );
+                    continue;
+                } else if let Some(decl) = chip.output(&target.name) {
+                    match self.bits(chip, line, target, decl.width) {
+                        Some(bits) => bits,
+                        None => continue,
+                    }
+                } else if target.bits.is_some() {
+                    self.error(chip, line, format!("sub-bus of internal pin {}", target));
+                    continue;
+                } else {
+                    match nets.get(&target.name) {
+                        Some(nodes) if nodes.len() != hi - lo + 1 => {
+                            self.error(
+                                chip,
+                                line,
+                                width_message(&connection.pin, hi - lo + 1, target, nodes.len()),
+                            );
+                            continue;
+                        }
+                        Some(_) => {}
+                        None => {
+                            let decl = PinDecl {
+                                name: target.name.to_string(),
+                                width: hi - lo + 1,
+                                line,
+                            };
+                            nets.insert(target.name.to_string(), graph.nets(&decl, 0));
+                            drivers.insert(target.name.to_string(), vec![None; decl.width]);
+                        }
+                    }
+                    (0, hi - lo)
+                };
+                if t_hi - t_lo != hi - lo {
+                    self.error(
+                        chip,
+                        line,
+                        width_message(&connection.pin, hi - lo + 1, target, t_hi - t_lo + 1),
+                    );
+                    continue;
+                }
+                let driven = drivers.get_mut(&target.name).unwrap();
+                if let Some(first) = driven[t_lo..=t_hi].iter().find_map(|driver| *driver) {
+                    self.error(
+                        chip,
+                        line,
+                        format!("{} is already driven at line {}", target, first),
+                    );
+                    continue;
+                }
+                for bit in 0..=hi - lo {
+                    driven[t_lo + bit] = Some(line);
+                    let from = pins[&decl.name][lo + bit];
+                    graph.edges[from].push(nets[&target.name][t_lo + bit]);
+                }
+            }
+        }
+
+        for decl in &chip.outputs {
+            let driven = &drivers[&decl.name];
+            if let Some(lo) = driven.iter().position(|driver| driver.is_none()) {
+                let hi = (lo..decl.width)
+                    .take_while(|bit| driven[*bit].is_none())
+                    .last()
+                    .unwrap();
+                let pin = PinRef {
+                    name: decl.name.to_string(),
+                    bits: if decl.width == 1 {
+                        None
+                    } else {
+                        Some((lo, hi))
+                    },
+                };
+                self.error(chip, decl.line, format!("output {} is not connected", pin));
+            }
+        }
+
+        for ((part, def), pins) in chip.parts.iter().zip(&defs).zip(&part_pins) {
+            let def = match def {
+                Some(def) => def,
+                None => continue,
+            };
+            for connection in &part.connections {
+                let line = connection.line;
+                let decl = match def.pin(&connection.pin.name) {
+                    Some(decl) => decl,
+                    None => {
+                        let message = format!("{} has no pin {}", def.name, connection.pin.name);
+                        self.error(chip, line, message);
+                        continue;
+                    }
+                };
+                let signal = match (def.input(&decl.name), &connection.signal) {
+                    (Some(_), Signal::Pin(signal)) => signal,
+                    _ => continue,
+                };
+                let (lo, hi) = match self.bits(chip, line, &connection.pin, decl.width) {
+                    Some(bits) => bits,
+                    None => continue,
+                };
+                let nodes = if chip.output(&signal.name).is_some() {
+                    let message = format!("output pin {} cannot feed a part", signal.name);
+                    self.error(chip, line, message);
+                    continue;
+                } else if let Some(decl) = chip.input(&signal.name) {
+                    match self.bits(chip, line, signal, decl.width) {
+                        Some((s_lo, s_hi)) => nets[&signal.name][s_lo..=s_hi].to_vec(),
+                        None => continue,
+                    }
+                } else if let Some(nodes) = nets.get(&signal.name) {
+                    if signal.bits.is_some() {
+                        self.error(chip, line, format!("sub-bus of internal pin {}", signal));
+                        continue;
+                    }
+                    nodes.to_vec()
+                } else {
+                    let message = format!("undeclared pin {}: no part drives it", signal.name);
+                    self.error(chip, line, message);
+                    continue;
+                };
+                if nodes.len() != hi - lo + 1 {
+                    let message = width_message(&connection.pin, hi - lo + 1, signal, nodes.len());
+                    self.error(chip, line, message);
+                    continue;
+                }
+                for (bit, node) in nodes.into_iter().enumerate() {
+                    graph.edges[node].push(pins[&decl.name][lo + bit]);
+                }
+            }
+        }
+
+        for (line, names) in graph.loops() {
+            let message = format!("combinational loop through {}", names.join(", "));
+            self.error(chip, line, message);
+        }
+
+        let outputs: Vec<usize> = chip
+            .outputs
+            .iter()
+            .flat_map(|decl| nets[&decl.name].to_vec())
+            .collect();
+        chip.inputs
+            .iter()
+            .flat_map(|decl| nets[&decl.name].to_vec())
+            .map(|input| {
+                let reached = graph.reachable(input);
+                (0..outputs.len())
+                    .filter(|bit| reached[outputs[*bit]])
+                    .collect()
+            })
+            .collect()
+    }
+
+    // first and last bit of `pin`, or None after reporting that it is too wide
+    fn bits(
+        &mut self,
+        chip: &ChipDef,
+        line: usize,
+        pin: &PinRef,
+        width: usize,
+    ) -> Option<(usize, usize)> {
+        let (lo, hi) = pin.range(width);
+        if hi >= width {
+            self.error(chip, line, format!("{} is only {} bits wide", pin, width));
+            return None;
+        }
+        Some((lo, hi))
+    }
+
+    fn error(&mut self, chip: &ChipDef, line: usize, message: String) {
+        self.diagnostics.push(Diagnostic {
+            path: chip.path.to_path_buf(),
+            line,
+            message,
+        });
+    }
+}
+
+// part pin `out` (1 bit) is connected to `out` (2 bits)
+pub(crate) fn width_message(
+    part_pin: &PinRef,
+    part_width: usize,
+    pin: &PinRef,
+    width: usize,
+) -> String {
+    let bits = |width: usize| match width {
+        1 => "1 bit".to_string(),
+        _ => format!("{} bits", width),
+    };
+    format!(
+        "part pin `{}` ({}) is connected to `{}` ({})",
+        part_pin,
+        bits(part_width),
+        pin,
+        bits(width)
+    )
+}
+
+// a built-in chip's unclocked inputs change all of its outputs
+fn builtin_paths(chip: &ChipDef) -> Paths {
+    let outputs: usize = chip.outputs.iter().map(|decl| decl.width).sum();
+    chip.inputs
+        .iter()
+        .flat_map(|decl| {
+            let reach: Vec<usize> = if chip.clocked.contains(&decl.name) {
+                Vec::new()
+            } else {
+                (0..outputs).collect()
+            };
+            vec![reach; decl.width]
+        })
+        .collect()
+}
+
+impl Graph {
+    fn nodes(&mut self, width: usize, line: usize) -> Vec<usize> {
+        let start = self.edges.len();
+        for _ in 0..width {
+            self.edges.push(Vec::new());
+            self.names.push(None);
+            self.lines.push(line);
+        }
+        (start..start + width).collect()
+    }
+
+    // the bits of a chip or internal pin, named for loop messages
+    fn nets(&mut self, decl: &PinDecl, line: usize) -> Vec<usize> {
+        let nodes = self.nodes(decl.width, line);
+        for (bit, node) in nodes.iter().enumerate() {
+            self.names[*node] = Some(if decl.width == 1 {
+                decl.name.to_string()
+            } else {
+                format!("{}[{}]", decl.name, bit)
+            });
+        }
+        nodes
+    }
+
+    fn reachable(&self, start: usize) -> Vec<bool> {
+        let mut seen = vec![false; self.edges.len()];
+        let mut stack = vec![start];
+        seen[start] = true;
+        while let Some(node) = stack.pop() {
+            for next in &self.edges[node] {
+                if !seen[*next] {
+                    seen[*next] = true;
+                    stack.push(*next);
+                }
+            }
+        }
+        seen
+    }
+
+    // (line of a part on the loop, pins on the loop) for every loop found
+    // by a depth-first search
+    fn loops(&self) -> Vec<(usize, Vec<String>)> {
+        // 0 unvisited, 1 on the current path, 2 done
+        let mut state = vec![0u8; self.edges.len()];
+        let mut loops = Vec::new();
+        for start in 0..self.edges.len() {
+            if state[start] != 0 {
+                continue;
+            }
+            let mut path = vec![(start, 0)];
+            state[start] = 1;
+            while let Some((node, edge)) = path.last_mut() {
+                let node = *node;
+                match self.edges[node].get(*edge) {
+                    Some(next) => {
+                        *edge += 1;
+                        match state[*next] {
+                            0 => {
+                                state[*next] = 1;
+                                path.push((*next, 0));
+                            }
+                            1 => {
+                                let from = path.iter().position(|(n, _)| n == next).unwrap();
+                                let nodes: Vec<usize> =
+                                    path[from..].iter().map(|(n, _)| *n).collect();
+                                let line = nodes
+                                    .iter()
+                                    .map(|n| self.lines[*n])
+                                    .filter(|line| *line > 0)
+                                    .min()
+                                    .unwrap_or(0);
+                                let mut names: Vec<String> = nodes
+                                    .iter()
+                                    .filter_map(|n| self.names[*n].clone())
+                                    .collect();
+                                names.dedup();
+                                loops.push((line, names));
+                            }
+                            _ => {}
+                        }
+                    }
+                    None => {
+                        state[node] = 2;
+                        path.pop();
+                    }
+                }
+            }
+        }
+        loops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    // checks the first chip with the others in its library, as `line: message`
+    fn check_hdl(chips: &[&str]) -> Vec<String> {
+        let mut library = Library::new();
+        let chips: Vec<Rc<ChipDef>> = chips
+            .iter()
+            .map(|text| library.add(ChipDef::parse(text, Path::new("Test.hdl")).unwrap()))
+            .collect();
+        check(&mut library, &chips[0])
+            .iter()
+            .map(|diagnostic| format!("{}: {}", diagnostic.line, diagnostic.message))
+            .collect()
+    }
+
+    #[test]
+    fn a_correct_chip_has_no_diagnostics() {
+        let and = "CHIP And {
+            IN a, b;
+            OUT out;
+            PARTS:
+            Nand(a=a, b=b, out=x);
+            Nand(a=x, b=true, out=out);
+        }";
+        assert!(check_hdl(&[and]).is_empty());
+    }
+
+    #[test]
+    fn combinational_loop() {
+        let looped = "CHIP Loop {
+            IN a;
+            OUT out;
+            PARTS:
+            Nand(a=a, b=x, out=y);
+            Nand(a=y, b=y, out=x, out=out);
+        }";
+        assert_eq!(
+            check_hdl(&[looped]),
+            vec!["5: combinational loop through y, x"]
+        );
+
+        // a DFF in the loop breaks it
+        let latched = "CHIP Latch {
+            IN a;
+            OUT out;
+            PARTS:
+            Nand(a=a, b=x, out=y);
+            DFF(in=y, out=x, out=out);
+        }";
+        assert!(check_hdl(&[latched]).is_empty());
+    }
+
+    #[test]
+    fn undriven_outputs() {
+        let chip = "CHIP Partial {
+            IN a;
+            OUT out, unused, w[4];
+            PARTS:
+            Nand(a=a, b=a, out=out, out=w[0]);
+        }";
+        assert_eq!(
+            check_hdl(&[chip]),
+            vec![
+                "3: output unused is not connected",
+                "3: output w[1..3] is not connected"
+            ]
+        );
+    }
+
+    #[test]
+    fn double_driver() {
+        let chip = "CHIP Twice {
+            IN a, b;
+            OUT out;
+            PARTS:
+            Nand(a=a, b=a, out=out);
+            Nand(a=b, b=b, out=out);
+        }";
+        assert_eq!(
+            check_hdl(&[chip]),
+            vec!["6: out is already driven at line 5"]
+        );
+    }
+
+    #[test]
+    fn width_mismatch() {
+        let chip = "CHIP Wide {
+            IN a[16];
+            OUT out[2];
+            PARTS:
+            Nand(a=a, b=true, out=out);
+        }";
+        assert_eq!(
+            check_hdl(&[chip]),
+            vec![
+                // the part's output is not connected, so neither is the chip's
+                "3: output out[0..1] is not connected",
+                "5: part pin `out` (1 bit) is connected to `out` (2 bits)",
+                "5: part pin `a` (1 bit) is connected to `a` (16 bits)"
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_chip() {
+        let chip = "CHIP Uses {
+            IN a;
+            OUT out;
+            PARTS:
+            Nand(a=a, b=a, out=x);
+            Missing(in=x, out=out);
+        }";
+        // its pins are unknown too, so out counts as undriven
+        assert_eq!(
+            check_hdl(&[chip]),
+            vec!["3: output out is not connected", "6: unknown chip Missing"]
+        );
+    }
+
+    #[test]
+    fn undeclared_internal_pin() {
+        let chip = "CHIP Typo {
+            IN a;
+            OUT out;
+            PARTS:
+            Nand(a=a, b=a, out=nand);
+            Nand(a=nnad, b=nand, out=out);
+        }";
+        assert_eq!(
+            check_hdl(&[chip]),
+            vec!["6: undeclared pin nnad: no part drives it"]
+        );
+    }
+
+    #[test]
+    fn contains_itself() {
+        let own = "CHIP Own {
+            IN a;
+            OUT out;
+            PARTS:
+            Own(a=a, out=out);
+        }";
+        assert_eq!(check_hdl(&[own]), vec!["5: Own contains itself"]);
+
+        // through another chip, reported where the cycle closes
+        let outer = "CHIP Outer {
+            IN a;
+            OUT out;
+            PARTS:
+            Inner(a=a, out=out);
+        }";
+        let inner = "CHIP Inner {
+            IN a;
+            OUT out;
+            PARTS:
+            Outer(a=a, out=out);
+        }";
+        assert_eq!(check_hdl(&[outer, inner]), vec!["5: Outer contains itself"]);
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use hack_cpu::cpu::Cpu;
+
+use crate::library::Library;
+use crate::simulator::Simulator;
+
+const SCREEN: usize = 16384;
+const KBD: usize = 24576;
+
+// a word the simulated chip and the CPU emulator disagree on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub name: String,
+    pub hardware: i16,
+    pub emulator: i16,
+}
+
+// Computer.hdl, or any chip with ROM32K, RAM16K and Screen parts, running a
+// program. ROM32K, RAM16K, Screen and Keyboard are always the built-in chips,
+// even where the project has .hdl files for them, so the program can be
+// loaded and the RAM read back; everything else comes from the .hdl files.
+pub struct Computer {
+    simulator: Simulator,
+}
+
+impl Computer {
+    // `builtins` are simulated natively too, on top of the four memory chips
+    pub fn new(path: &Path, builtins: &[&str]) -> Self {
+        let mut library = Library::for_file(path);
+        for name in ["ROM32K", "RAM16K", "Screen", "Keyboard"]
+            .iter()
+            .chain(builtins)
+        {
+            library.prefer_builtin(name);
+        }
+        let simulator = Simulator::with_library(&mut library, path);
+        for name in &["ROM32K", "RAM16K", "Screen"] {
+            if simulator.memory(name).is_none() {
+                panic!(
+                    "ERROR: {} has no {} part",
+                    simulator.netlist().chip.name,
+                    name
+                );
+            }
+        }
+        Computer { simulator }
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+
+    pub fn simulator_mut(&mut self) -> &mut Simulator {
+        &mut self.simulator
+    }
+
+    // the program already loaded into the emulator, and its RAM
+    pub fn load(&mut self, cpu: &Cpu) {
+        let rom = self.simulator.memory_mut("ROM32K").unwrap();
+        for (address, word) in rom.iter_mut().enumerate().take(cpu.rom_len()) {
+            *word = cpu.rom(address) as i16;
+        }
+        for address in 0..KBD {
+            self.set_ram(address, cpu.ram(address));
+        }
+        self.simulator.eval();
+    }
+
+    // one instruction per cycle, like Cpu::step
+    pub fn run(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.simulator.tick();
+            self.simulator.tock();
+        }
+    }
+
+    // the data memory as the CPU addresses it: RAM16K, then the screen
+    pub fn ram(&self, address: usize) -> i16 {
+        match address {
+            _ if address < SCREEN => self.simulator.memory("RAM16K").unwrap()[address],
+            _ if address < KBD => self.simulator.memory("Screen").unwrap()[address - SCREEN],
+            _ => panic!("ERROR: no RAM at {}", address),
+        }
+    }
+
+    pub fn set_ram(&mut self, address: usize, value: i16) {
+        let (chip, address) = if address < SCREEN {
+            ("RAM16K", address)
+        } else {
+            ("Screen", address - SCREEN)
+        };
+        self.simulator.memory_mut(chip).unwrap()[address] = value;
+    }
+
+    // RAM words that differ, and A and D when the registers are built-in
+    pub fn compare(&self, cpu: &Cpu) -> Vec<Difference> {
+        let mut differences: Vec<Difference> = (0..KBD)
+            .filter(|address| self.ram(*address) != cpu.ram(*address))
+            .map(|address| Difference {
+                name: format!("RAM[{}]", address),
+                hardware: self.ram(address),
+                emulator: cpu.ram(address),
+            })
+            .collect();
+        for (chip, name, value) in &[("ARegister", "A", cpu.a()), ("DRegister", "D", cpu.d())] {
+            if let Some(register) = self.simulator.memory(chip) {
+                if register[0] != *value {
+                    differences.push(Difference {
+                        name: name.to_string(),
+                        hardware: register[0],
+                        emulator: *value,
+                    });
+                }
+            }
+        }
+        differences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project_file(name: &str) -> String {
+        format!("{}/../../{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn mult_matches_the_emulator() {
+        let mut cpu = Cpu::new();
+        cpu.load_asm(&project_file("04/mult/Mult.asm"));
+        cpu.set_ram(0, 6);
+        cpu.set_ram(1, 7);
+
+        let mut computer = Computer::new(Path::new(&project_file("05/Computer.hdl")), &[]);
+        computer.load(&cpu);
+        assert_eq!(computer.ram(0), 6);
+        assert_eq!(computer.ram(1), 7);
+        assert_eq!(cpu.run(90), 90);
+        computer.run(90);
+        assert_eq!(computer.compare(&cpu), vec![]);
+        // 7 rounds of the loop, and the last test of R1 jumps to END
+        assert_eq!(computer.ram(2), 42);
+    }
+}
@@ -0,0 +1,570 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// `IN a, b[16];` declares a and b, one and 16 bits wide
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinDecl {
+    pub name: String,
+    pub width: usize,
+    pub line: usize,
+}
+
+// `out`, `out[3]` or `out[0..7]`; bits is None when the whole pin is meant
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinRef {
+    pub name: String,
+    pub bits: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signal {
+    Pin(PinRef),
+    Const(bool),
+}
+
+// `pin=signal` in a part: the part's pin on the left, the chip's on the right
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connection {
+    pub pin: PinRef,
+    pub signal: Signal,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub chip: String,
+    pub connections: Vec<Connection>,
+    pub line: usize,
+}
+
+// One CHIP of a .hdl file. Chips with `BUILTIN` have no parts and are
+// simulated natively.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChipDef {
+    pub name: String,
+    pub path: PathBuf,
+    pub inputs: Vec<PinDecl>,
+    pub outputs: Vec<PinDecl>,
+    pub parts: Vec<Part>,
+    pub builtin: Option<String>,
+    pub clocked: Vec<String>,
+}
+
+// where and why a .hdl file could not be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub path: PathBuf,
+    // 0 when the file could not be read at all
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Symbol(&'static str),
+}
+
+impl ChipDef {
+    pub fn from_file(path: &Path) -> Result<Self, ParseError> {
+        let text = fs::read_to_string(path).map_err(|error| ParseError {
+            path: path.to_path_buf(),
+            line: 0,
+            message: format!("cannot read the file: {}", error),
+        })?;
+        ChipDef::parse(&text, path)
+    }
+
+    // `path` is only used in error messages and to find the file again
+    pub fn parse(text: &str, path: &Path) -> Result<Self, ParseError> {
+        let tokens = tokenize(text, path)?;
+        let mut parser = ChipParser {
+            tokens,
+            idx: 0,
+            path,
+        };
+        let chip = parser.chip()?;
+        if parser.idx < parser.tokens.len() {
+            return parser.unexpected("expected the end of the file");
+        }
+        Ok(chip)
+    }
+
+    pub fn input(&self, name: &str) -> Option<&PinDecl> {
+        self.inputs.iter().find(|pin| pin.name == name)
+    }
+
+    pub fn output(&self, name: &str) -> Option<&PinDecl> {
+        self.outputs.iter().find(|pin| pin.name == name)
+    }
+
+    pub fn pin(&self, name: &str) -> Option<&PinDecl> {
+        self.input(name).or_else(|| self.output(name))
+    }
+}
+
+impl PinRef {
+    // first and last bit of a pin `width` bits wide
+    pub fn range(&self, width: usize) -> (usize, usize) {
+        self.bits.unwrap_or((0, width.max(1) - 1))
+    }
+}
+
+impl std::fmt::Display for PinRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.bits {
+            None => write!(f, "{}", self.name),
+            Some((lo, hi)) if lo == hi => write!(f, "{}[{}]", self.name, lo),
+            Some((lo, hi)) => write!(f, "{}[{}..{}]", self.name, lo, hi),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.line {
+            0 => write!(f, "{}: {}", self.path.display(), self.message),
+            line => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+        }
+    }
+}
+
+impl std::fmt::Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Signal::Pin(pin) => write!(f, "{}", pin),
+            Signal::Const(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// (token, line) pairs; comments are dropped
+fn tokenize(text: &str, path: &Path) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    let mut line = 1;
+    while idx < chars.len() {
+        let c = chars[idx];
+        let next = chars.get(idx + 1).copied();
+        if c == '\n' {
+            line += 1;
+            idx += 1;
+        } else if c.is_whitespace() {
+            idx += 1;
+        } else if c == '/' && next == Some('/') {
+            while idx < chars.len() && chars[idx] != '\n' {
+                idx += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            idx += 2;
+            while idx < chars.len() && !(chars[idx] == '*' && chars.get(idx + 1) == Some(&'/')) {
+                if chars[idx] == '\n' {
+                    line += 1;
+                }
+                idx += 1;
+            }
+            idx += 2;
+        } else if c == '.' && next == Some('.') {
+            tokens.push((Token::Symbol(".."), line));
+            idx += 2;
+        } else if let Some(symbol) = ["{", "}", "(", ")", "[", "]", ",", ";", "=", ":"]
+            .iter()
+            .find(|symbol| symbol.starts_with(c))
+        {
+            tokens.push((Token::Symbol(symbol), line));
+            idx += 1;
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = idx;
+            while idx < chars.len() && (chars[idx].is_alphanumeric() || chars[idx] == '_') {
+                idx += 1;
+            }
+            tokens.push((Token::Word(chars[start..idx].iter().collect()), line));
+        } else {
+            return Err(ParseError {
+                path: path.to_path_buf(),
+                line,
+                message: format!("unexpected {}", c),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+struct ChipParser<'a> {
+    tokens: Vec<(Token, usize)>,
+    idx: usize,
+    path: &'a Path,
+}
+
+impl ChipParser<'_> {
+    fn chip(&mut self) -> Result<ChipDef, ParseError> {
+        self.keyword("CHIP")?;
+        let name = self.word()?;
+        self.symbol("{")?;
+        let mut chip = ChipDef {
+            name,
+            path: self.path.to_path_buf(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            parts: Vec::new(),
+            builtin: None,
+            clocked: Vec::new(),
+        };
+
+        if self.peek_word("IN") {
+            self.idx += 1;
+            chip.inputs = self.pin_decls()?;
+        }
+        if self.peek_word("OUT") {
+            self.idx += 1;
+            chip.outputs = self.pin_decls()?;
+        }
+
+        if self.peek_word("BUILTIN") {
+            self.idx += 1;
+            chip.builtin = Some(self.word()?);
+            self.symbol(";")?;
+            if self.peek_word("CLOCKED") {
+                self.idx += 1;
+                chip.clocked.push(self.word()?);
+                while self.peek_symbol(",") {
+                    self.idx += 1;
+                    chip.clocked.push(self.word()?);
+                }
+                self.symbol(";")?;
+            }
+        } else {
+            self.keyword("PARTS")?;
+            self.symbol(":")?;
+            while !self.peek_symbol("}") {
+                chip.parts.push(self.part()?);
+            }
+        }
+        self.symbol("}")?;
+        Ok(chip)
+    }
+
+    fn pin_decls(&mut self) -> Result<Vec<PinDecl>, ParseError> {
+        let mut pins = Vec::new();
+        loop {
+            let line = self.line();
+            let name = self.word()?;
+            let width = if self.peek_symbol("[") {
+                self.idx += 1;
+                let width = self.number()?;
+                self.symbol("]")?;
+                width
+            } else {
+                1
+            };
+            if width == 0 || width > 16 {
+                return self.error(&format!("{} must be 1 to 16 bits wide", name));
+            }
+            pins.push(PinDecl { name, width, line });
+            if !self.peek_symbol(",") {
+                break;
+            }
+            self.idx += 1;
+        }
+        self.symbol(";")?;
+        Ok(pins)
+    }
+
+    fn part(&mut self) -> Result<Part, ParseError> {
+        let line = self.line();
+        let chip = self.word()?;
+        self.symbol("(")?;
+        let mut connections = Vec::new();
+        loop {
+            let line = self.line();
+            let pin = self.pin_ref()?;
+            self.symbol("=")?;
+            let signal = if self.peek_word("true") {
+                self.idx += 1;
+                Signal::Const(true)
+            } else if self.peek_word("false") {
+                self.idx += 1;
+                Signal::Const(false)
+            } else {
+                Signal::Pin(self.pin_ref()?)
+            };
+            connections.push(Connection { pin, signal, line });
+            if !self.peek_symbol(",") {
+                break;
+            }
+            self.idx += 1;
+        }
+        self.symbol(")")?;
+        self.symbol(";")?;
+        Ok(Part {
+            chip,
+            connections,
+            line,
+        })
+    }
+
+    fn pin_ref(&mut self) -> Result<PinRef, ParseError> {
+        let name = self.word()?;
+        let bits = if self.peek_symbol("[") {
+            self.idx += 1;
+            let lo = self.number()?;
+            let hi = if self.peek_symbol("..") {
+                self.idx += 1;
+                self.number()?
+            } else {
+                lo
+            };
+            self.symbol("]")?;
+            if hi < lo {
+                return self.error(&format!("{}[{}..{}] runs backwards", name, lo, hi));
+            }
+            Some((lo, hi))
+        } else {
+            None
+        };
+        Ok(PinRef { name, bits })
+    }
+
+    fn word(&mut self) -> Result<String, ParseError> {
+        match self.tokens.get(self.idx) {
+            Some((Token::Word(word), _)) => {
+                self.idx += 1;
+                Ok(word.to_string())
+            }
+            _ => self.unexpected("expected a name"),
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, ParseError> {
+        let word = self.word()?;
+        match word.parse() {
+            Ok(number) => Ok(number),
+            Err(_) => self.error(&format!("expected a number, not {}", word)),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if !self.peek_word(keyword) {
+            return self.unexpected(&format!("expected {}", keyword));
+        }
+        self.idx += 1;
+        Ok(())
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Result<(), ParseError> {
+        if !self.peek_symbol(symbol) {
+            return self.unexpected(&format!("expected {}", symbol));
+        }
+        self.idx += 1;
+        Ok(())
+    }
+
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.tokens.get(self.idx), Some((Token::Word(w), _)) if w == word)
+    }
+
+    fn peek_symbol(&self, symbol: &str) -> bool {
+        matches!(self.tokens.get(self.idx), Some((Token::Symbol(s), _)) if *s == symbol)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.idx)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        Err(ParseError {
+            path: self.path.to_path_buf(),
+            line: self.line(),
+            message: message.to_string(),
+        })
+    }
+
+    // `message` followed by the token that is there instead
+    fn unexpected<T>(&self, message: &str) -> Result<T, ParseError> {
+        let found = match self.tokens.get(self.idx) {
+            Some((Token::Word(word), _)) => word.to_string(),
+            Some((Token::Symbol(symbol), _)) => symbol.to_string(),
+            None => "the end of the file".to_string(),
+        };
+        self.error(&format!("{}, found {}", message, found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project_file(name: &str) -> ChipDef {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../..")
+            .join(name);
+        ChipDef::from_file(&path).unwrap()
+    }
+
+    fn parse(text: &str) -> Result<ChipDef, ParseError> {
+        ChipDef::parse(text, Path::new("Test.hdl"))
+    }
+
+    // the part's connections as `pin=signal`
+    fn connections(part: &Part) -> Vec<String> {
+        part.connections
+            .iter()
+            .map(|c| format!("{}={}", c.pin, c.signal))
+            .collect()
+    }
+
+    #[test]
+    fn and_from_project_01() {
+        let chip = project_file("01/And.hdl");
+        assert_eq!(chip.name, "And");
+        assert_eq!(
+            chip.inputs,
+            vec![
+                PinDecl {
+                    name: "a".to_string(),
+                    width: 1,
+                    line: 13
+                },
+                PinDecl {
+                    name: "b".to_string(),
+                    width: 1,
+                    line: 13
+                },
+            ]
+        );
+        assert_eq!(chip.outputs.len(), 1);
+        assert_eq!(chip.outputs[0].name, "out");
+        assert_eq!(chip.builtin, None);
+
+        let parts: Vec<(&str, usize)> = chip
+            .parts
+            .iter()
+            .map(|part| (part.chip.as_str(), part.line))
+            .collect();
+        assert_eq!(parts, vec![("Nand", 18), ("Not", 19)]);
+        assert_eq!(connections(&chip.parts[0]), vec!["a=a", "b=b", "out=x"]);
+        assert_eq!(connections(&chip.parts[1]), vec!["in=x", "out=out"]);
+    }
+
+    #[test]
+    fn bit_from_project_03() {
+        let chip = project_file("03/a/Bit.hdl");
+        assert_eq!(chip.name, "Bit");
+        let inputs: Vec<&str> = chip.inputs.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(inputs, vec!["in", "load"]);
+        assert_eq!(chip.parts.len(), 2);
+        assert_eq!(
+            connections(&chip.parts[0]),
+            vec!["a=a", "b=in", "sel=load", "out=b"]
+        );
+        // one output pin may feed several signals
+        assert_eq!(
+            connections(&chip.parts[1]),
+            vec!["in=b", "out=a", "out=out"]
+        );
+        assert_eq!(chip.parts[1].line, 19);
+    }
+
+    #[test]
+    fn sub_buses_and_constants() {
+        let chip = parse(
+            "CHIP Wide {
+                IN a[16], sel;
+                OUT out[8], low;
+                PARTS:
+                Mux8(a=a[0..7], b[0]=true, b[1..7]=false, sel=sel, out=out, out[3]=low);
+            }",
+        )
+        .unwrap();
+        assert_eq!(chip.inputs[0].width, 16);
+        assert_eq!(chip.inputs[1].width, 1);
+        assert_eq!(chip.outputs[0].width, 8);
+        let part = &chip.parts[0];
+        assert_eq!(part.line, 5);
+        assert_eq!(
+            part.connections[0].signal,
+            Signal::Pin(PinRef {
+                name: "a".to_string(),
+                bits: Some((0, 7))
+            })
+        );
+        assert_eq!(part.connections[1].pin.bits, Some((0, 0)));
+        assert_eq!(part.connections[1].signal, Signal::Const(true));
+        assert_eq!(part.connections[2].pin.bits, Some((1, 7)));
+        assert_eq!(part.connections[2].signal, Signal::Const(false));
+        assert_eq!(part.connections[4].pin.bits, None);
+        assert_eq!(part.connections[5].pin.bits, Some((3, 3)));
+    }
+
+    #[test]
+    fn builtin_and_clocked() {
+        let chip = parse("CHIP DFF { IN in; OUT out; BUILTIN DFF; CLOCKED in; }").unwrap();
+        assert_eq!(chip.builtin, Some("DFF".to_string()));
+        assert_eq!(chip.clocked, vec!["in"]);
+        assert!(chip.parts.is_empty());
+    }
+
+    #[test]
+    fn backwards_range_is_an_error() {
+        let error = parse(
+            "CHIP Bad {
+                IN a[4];
+                OUT out[3];
+                PARTS:
+                Id(in=a[3..1], out=out);
+            }",
+        )
+        .unwrap_err();
+        assert_eq!(error.path, Path::new("Test.hdl"));
+        assert_eq!(error.line, 5);
+        assert_eq!(error.message, "a[3..1] runs backwards");
+        assert_eq!(error.to_string(), "Test.hdl:5: a[3..1] runs backwards");
+    }
+
+    #[test]
+    fn other_errors() {
+        let message = |text: &str| {
+            let error = parse(text).unwrap_err();
+            format!("{}: {}", error.line, error.message)
+        };
+        assert_eq!(
+            message("CHIP A {\n IN a[17];\n}"),
+            "2: a must be 1 to 16 bits wide"
+        );
+        assert_eq!(
+            message("CHIP A {\n IN a[x];\n}"),
+            "2: expected a number, not x"
+        );
+        assert_eq!(message("CHIP A { IN a; #"), "1: unexpected #");
+        assert_eq!(
+            message("CHIP A {\n IN a;\n PARTS:\n Not(in=a out=b);\n}"),
+            "4: expected ), found out"
+        );
+        assert_eq!(
+            message("CHIP A { PARTS: }\nCHIP B"),
+            "2: expected the end of the file, found CHIP"
+        );
+        assert_eq!(
+            message("CHIP A {\n PARTS:\n"),
+            "2: expected a name, found the end of the file"
+        );
+    }
+
+    #[test]
+    fn unreadable_file_is_an_error() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Missing.hdl");
+        let error = ChipDef::from_file(&path).unwrap_err();
+        assert_eq!(error.path, path);
+        assert_eq!(error.line, 0);
+        assert!(
+            error.message.starts_with("cannot read the file: "),
+            "{}",
+            error
+        );
+        assert!(!error.to_string().contains(":0:"));
+    }
+}
@@ -0,0 +1,11 @@
+pub mod builtin;
+pub mod check;
+pub mod computer;
+pub mod hdl;
+pub mod library;
+pub mod netlist;
+pub mod report;
+pub mod simulator;
+pub mod test_script;
+pub mod vcd;
+pub mod verilog;
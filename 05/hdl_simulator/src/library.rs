@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::builtin;
+use crate::hdl::{ChipDef, ParseError};
+
+// Finds chips by name: `Name.hdl` in the first directory that has one,
+// otherwise the built-in chip of that name.
+pub struct Library {
+    dirs: Vec<PathBuf>,
+    chips: HashMap<String, Rc<ChipDef>>,
+}
+
+impl Library {
+    pub fn new() -> Self {
+        Library {
+            dirs: Vec::new(),
+            chips: HashMap::new(),
+        }
+    }
+
+    // The chip's own directory, then every directory of .hdl files in the
+    // project it belongs to (the nearest parent with a 01 directory), so a
+    // chip from 05 can use the gates from 01 to 03.
+    pub fn for_file(path: &Path) -> Self {
+        let mut library = Library::new();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        library.add_dir(dir);
+        // `..` has no parents of its own, so look from the absolute path
+        let absolute = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let root = absolute
+            .ancestors()
+            .map(or_current)
+            .find(|dir| dir.join("01").is_dir());
+        if let Some(root) = root {
+            for dir in hdl_dirs(root, 2) {
+                library.add_dir(&dir);
+            }
+        }
+        library
+    }
+
+    pub fn add_dir(&mut self, dir: &Path) {
+        let dir = or_current(dir);
+        let same = |known: &PathBuf| known.canonicalize().ok() == dir.canonicalize().ok();
+        if !self.dirs.iter().any(same) {
+            self.dirs.push(dir.to_path_buf());
+        }
+    }
+
+    // use the built-in chip even where a .hdl file of that name exists, like
+    // the official tools do for chips outside the project directory
+    pub fn prefer_builtin(&mut self, name: &str) {
+        let chip =
+            builtin::chip(name).unwrap_or_else(|| panic!("ERROR: there is no built-in {}", name));
+        self.chips.insert(name.to_string(), Rc::new(chip));
+    }
+
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    // a chip given by its file; it is found under its CHIP name afterwards
+    pub fn load(&mut self, path: &Path) -> Result<Rc<ChipDef>, ParseError> {
+        Ok(self.add(ChipDef::from_file(path)?))
+    }
+
+    // a chip parsed elsewhere, found under its CHIP name like a loaded one
+    pub fn add(&mut self, chip: ChipDef) -> Rc<ChipDef> {
+        let chip = Rc::new(chip);
+        self.chips.insert(chip.name.to_string(), Rc::clone(&chip));
+        chip
+    }
+
+    // None when there is no such chip, a .hdl file that doesn't parse is an error
+    pub fn chip(&mut self, name: &str) -> Option<Rc<ChipDef>> {
+        self.try_chip(name)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error))
+    }
+
+    pub fn try_chip(&mut self, name: &str) -> Result<Option<Rc<ChipDef>>, ParseError> {
+        if let Some(chip) = self.chips.get(name) {
+            return Ok(Some(Rc::clone(chip)));
+        }
+        let file_name = format!("{}.hdl", name);
+        let chip = match self.dirs.iter().find(|dir| dir.join(&file_name).is_file()) {
+            Some(dir) => ChipDef::from_file(&dir.join(&file_name))?,
+            None => match builtin::chip(name) {
+                Some(chip) => chip,
+                None => return Ok(None),
+            },
+        };
+        let chip = Rc::new(chip);
+        self.chips.insert(name.to_string(), Rc::clone(&chip));
+        Ok(Some(chip))
+    }
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Library::new()
+    }
+}
+
+// `Path::parent` gives "" for a file in the current directory
+fn or_current(dir: &Path) -> &Path {
+    if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    }
+}
+
+// directories up to `depth` levels below `root` that hold .hdl files, sorted
+fn hdl_dirs(root: &Path, depth: usize) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut entries: Vec<PathBuf> = match fs::read_dir(root) {
+        Ok(entries) => entries.map(|entry| entry.unwrap().path()).collect(),
+        Err(_) => return dirs,
+    };
+    entries.sort();
+    if entries
+        .iter()
+        .any(|path| path.extension().is_some_and(|ext| ext == "hdl"))
+    {
+        dirs.push(root.to_path_buf());
+    }
+    if depth > 0 {
+        for entry in entries.iter().filter(|path| path.is_dir()) {
+            dirs.extend(hdl_dirs(entry, depth - 1));
+        }
+    }
+    dirs
+}
@@ -0,0 +1,211 @@
+use hdl_simulator::check::check;
+use hdl_simulator::computer::Computer;
+use hdl_simulator::library::Library;
+use hdl_simulator::netlist::Netlist;
+use hdl_simulator::report::Report;
+use hdl_simulator::simulator::Simulator;
+use hdl_simulator::test_script::TestBench;
+use hdl_simulator::vcd::Vcd;
+use hdl_simulator::verilog;
+
+use hack_cpu::cpu::Cpu;
+use hack_cpu::test_script::TestScript;
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+// usage: hdl_simulator Chip.hdl [pin=value ...] [--cycles=n]
+//                      [--builtin=RAM16K,Register,...]
+//                      [--vcd=Chip.vcd [--pins=a,b,...]]
+//        hdl_simulator Chip.tst [--builtin=...] [--vcd=...]
+//        hdl_simulator Chip.hdl --check
+//        hdl_simulator Computer.hdl --hack=Prog.hack --cycles=n [RAM[0]=value ...]
+//        hdl_simulator Chip.hdl --verilog=Chip.v [--flat] [--builtin=...]
+//        hdl_simulator Chip.hdl --report[=depth] [--builtin=...]
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let positionals: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let path = positionals
+        .first()
+        .map_or("../../02/ALU.hdl", |path| path.as_str());
+    let flag = |name: &str| args.iter().find_map(|arg| arg.strip_prefix(name));
+    let cycles = flag("--cycles=").map_or(0, |cycles| {
+        cycles.parse::<usize>().expect("cycles must be a number")
+    });
+
+    let builtins: Vec<&str> =
+        flag("--builtin=").map_or(Vec::new(), |names| names.split(',').collect());
+    let pins: Vec<String> = flag("--pins=").map_or(Vec::new(), |names| {
+        names.split(',').map(|name| name.to_string()).collect()
+    });
+    let vcd_file = flag("--vcd=");
+
+    if path.ends_with(".tst") {
+        let mut bench = TestBench::new();
+        for name in &builtins {
+            bench.prefer_builtin(name);
+        }
+        if let Some(vcd_file) = vcd_file {
+            bench.dump(Path::new(vcd_file), &pins);
+        }
+        let mut script = TestScript::new(path, bench);
+        match script.run() {
+            Ok(()) => println!("End of script - Comparison ended successfully"),
+            Err(mismatch) => {
+                print!("{}", mismatch);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(program) = flag("--hack=") {
+        run_program(
+            Path::new(path),
+            program,
+            &positionals[1..],
+            cycles,
+            &builtins,
+        );
+        return;
+    }
+
+    let mut library = Library::for_file(Path::new(path));
+    for name in &builtins {
+        library.prefer_builtin(name);
+    }
+
+    if args.iter().any(|arg| arg == "--check") {
+        let chip = match library.load(Path::new(path)) {
+            Ok(chip) => chip,
+            Err(error) => {
+                println!("{}", error);
+                std::process::exit(1);
+            }
+        };
+        let diagnostics = check(&mut library, &chip);
+        for diagnostic in &diagnostics {
+            println!("{}", diagnostic);
+        }
+        if !diagnostics.is_empty() {
+            std::process::exit(1);
+        }
+        println!("{}: no problems found", chip.name);
+        return;
+    }
+
+    if let Some(verilog_file) = flag("--verilog=") {
+        let chip = library
+            .load(Path::new(path))
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+        let text = if args.iter().any(|arg| arg == "--flat") {
+            verilog::flattened(&Netlist::new(&mut library, &chip))
+        } else {
+            verilog::hierarchical(&mut library, &chip)
+        };
+        fs::write(verilog_file, text).expect("Cannot write Verilog file!");
+        return;
+    }
+
+    let mut simulator = Simulator::with_library(&mut library, Path::new(path));
+    for arg in positionals.iter().skip(1) {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, value),
+            None => panic!("ERROR: expected pin=value, not {}", arg),
+        };
+        let value = value
+            .parse::<i16>()
+            .unwrap_or_else(|_| panic!("ERROR: bad value {}", value));
+        simulator.set(name, value);
+    }
+    simulator.eval();
+    let mut vcd = vcd_file.map(|vcd_file| {
+        let file = File::create(vcd_file).expect("Cannot write VCD file!");
+        Vcd::new(Box::new(BufWriter::new(file)), &simulator, &pins)
+    });
+    let mut sample = |simulator: &Simulator| {
+        if let Some(vcd) = &mut vcd {
+            vcd.sample(simulator);
+        }
+    };
+    sample(&simulator);
+    for _ in 0..cycles {
+        simulator.tick();
+        sample(&simulator);
+        simulator.tock();
+        sample(&simulator);
+    }
+
+    let netlist = simulator.netlist();
+    let report = Report::new(netlist);
+    println!(
+        "{}: {} Nand gates, {} DFFs, {} built-in parts",
+        netlist.chip.name, report.nands, report.dffs, report.builtins
+    );
+    if let Some(depth) = flag("--report") {
+        let depth = depth.strip_prefix('=').map_or(1, |depth| {
+            depth.parse::<usize>().expect("depth must be a number")
+        });
+        for count in &report.counts {
+            if count.depth > 0 && count.depth <= depth {
+                let indent = "  ".repeat(count.depth - 1);
+                println!("{:>8}  {}{}", count.nands, indent, count.path);
+            }
+        }
+        println!("critical path: {} Nand delays", report.delay());
+        for (owner, gates) in report.critical_steps(netlist, depth) {
+            println!("{:>8}  {}", gates, netlist.instances[owner].path);
+        }
+        return;
+    }
+    for pin in netlist.inputs.iter().chain(&netlist.outputs) {
+        println!("{} = {}", pin.name, simulator.get(&pin.name));
+    }
+}
+
+// runs a program on the chip and on the CPU emulator and compares their RAM
+fn run_program(path: &Path, program: &str, args: &[&String], cycles: usize, builtins: &[&str]) {
+    let mut cpu = Cpu::new();
+    if program.ends_with(".asm") {
+        cpu.load_asm(program);
+    } else {
+        cpu = Cpu::from_file(program);
+    }
+    for arg in args {
+        let address = arg.split_once('=').and_then(|(name, value)| {
+            let address = name.strip_prefix("RAM[")?.strip_suffix(']')?;
+            Some((address.parse::<usize>().ok()?, value.parse::<i16>().ok()?))
+        });
+        match address {
+            Some((address, value)) => cpu.set_ram(address, value),
+            None => panic!("ERROR: expected RAM[address]=value, not {}", arg),
+        }
+    }
+
+    let mut computer = Computer::new(path, builtins);
+    computer.load(&cpu);
+    // past a halt the chip would run whatever follows the program in ROM
+    let executed = cpu.run(cycles);
+    computer.run(executed);
+    let differences = computer.compare(&cpu);
+    println!(
+        "{}: {} cycles{}",
+        computer.simulator().netlist().chip.name,
+        executed,
+        if cpu.is_halted() { ", halted" } else { "" }
+    );
+    for difference in differences.iter().take(20) {
+        println!(
+            "{}: hardware {}, emulator {}",
+            difference.name, difference.hardware, difference.emulator
+        );
+    }
+    if differences.len() > 20 {
+        println!("... and {} more", differences.len() - 20);
+    }
+    if !differences.is_empty() {
+        std::process::exit(1);
+    }
+    println!("RAM agrees with the emulator");
+}
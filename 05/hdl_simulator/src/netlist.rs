@@ -0,0 +1,519 @@
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use crate::builtin;
+use crate::check::width_message;
+use crate::hdl::{ChipDef, PinDecl, PinRef, Signal};
+use crate::library::Library;
+
+// nets that always hold false and true
+pub const FALSE: usize = 0;
+pub const TRUE: usize = 1;
+
+// deeper than any sane design; a chip that uses itself stops here
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gate {
+    Nand { a: usize, b: usize, out: usize },
+    // out holds what in was at the last clock cycle
+    Dff { input: usize, out: usize },
+    // index into Netlist::blocks
+    Builtin(usize),
+}
+
+// the pins of a built-in memory chip, in declaration order
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub chip: String,
+    pub inputs: Vec<Vec<usize>>,
+    pub outputs: Vec<Vec<usize>>,
+    // inputs that change the output at once rather than at the clock edge
+    pub combinational: Vec<bool>,
+}
+
+// one part in the flattened design, `CPU/ALU:43/Mux16:12` style: each part
+// with the line it is on in its parent's file
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub path: String,
+    pub chip: String,
+    pub parent: Option<usize>,
+}
+
+// a pin of the top chip and the nets of its bits, lowest bit first
+#[derive(Debug, Clone)]
+pub struct Pin {
+    pub name: String,
+    pub nets: Vec<usize>,
+}
+
+// A chip flattened down to its primitive gates over single-bit nets. Gates are
+// kept in evaluation order: every gate comes after the gates that drive it.
+pub struct Netlist {
+    pub chip: Rc<ChipDef>,
+    pub nets: usize,
+    pub gates: Vec<Gate>,
+    // instance each gate belongs to
+    pub owners: Vec<usize>,
+    pub blocks: Vec<Block>,
+    pub instances: Vec<Instance>,
+    pub inputs: Vec<Pin>,
+    pub outputs: Vec<Pin>,
+}
+
+struct Builder<'a> {
+    library: &'a mut Library,
+    // union-find over nets; connected pins end up in one set
+    parent: Vec<usize>,
+    gates: Vec<Gate>,
+    owners: Vec<usize>,
+    blocks: Vec<Block>,
+    instances: Vec<Instance>,
+}
+
+impl Netlist {
+    pub fn new(library: &mut Library, chip: &Rc<ChipDef>) -> Self {
+        let mut builder = Builder {
+            library,
+            parent: vec![FALSE, TRUE],
+            gates: Vec::new(),
+            owners: Vec::new(),
+            blocks: Vec::new(),
+            instances: vec![Instance {
+                path: chip.name.to_string(),
+                chip: chip.name.to_string(),
+                parent: None,
+            }],
+        };
+        let mut pins = HashMap::new();
+        for pin in chip.inputs.iter().chain(&chip.outputs) {
+            let nets = builder.nets(pin.width);
+            pins.insert(pin.name.to_string(), nets);
+        }
+        builder.instantiate(chip, &pins, 0, 0);
+
+        // one net per set, numbered from 2 after false and true
+        let mut ids = vec![usize::MAX; builder.parent.len()];
+        ids[FALSE] = FALSE;
+        ids[TRUE] = TRUE;
+        let mut nets = 2;
+        let mut id = |builder: &mut Builder, net: usize| {
+            let root = builder.find(net);
+            if ids[root] == usize::MAX {
+                ids[root] = nets;
+                nets += 1;
+            }
+            ids[root]
+        };
+        let gates: Vec<Gate> = std::mem::take(&mut builder.gates)
+            .into_iter()
+            .map(|gate| match gate {
+                Gate::Nand { a, b, out } => Gate::Nand {
+                    a: id(&mut builder, a),
+                    b: id(&mut builder, b),
+                    out: id(&mut builder, out),
+                },
+                Gate::Dff { input, out } => Gate::Dff {
+                    input: id(&mut builder, input),
+                    out: id(&mut builder, out),
+                },
+                Gate::Builtin(block) => Gate::Builtin(block),
+            })
+            .collect();
+        let mut blocks = std::mem::take(&mut builder.blocks);
+        for block in &mut blocks {
+            for net in block.inputs.iter_mut().chain(&mut block.outputs).flatten() {
+                *net = id(&mut builder, *net);
+            }
+        }
+        let mut pin = |builder: &mut Builder, name: &str| Pin {
+            name: name.to_string(),
+            nets: pins[name].iter().map(|net| id(builder, *net)).collect(),
+        };
+        let inputs = chip
+            .inputs
+            .iter()
+            .map(|decl| pin(&mut builder, &decl.name))
+            .collect();
+        let outputs = chip
+            .outputs
+            .iter()
+            .map(|decl| pin(&mut builder, &decl.name))
+            .collect();
+
+        let mut netlist = Netlist {
+            chip: Rc::clone(chip),
+            nets,
+            gates,
+            owners: builder.owners,
+            blocks,
+            instances: builder.instances,
+            inputs,
+            outputs,
+        };
+        netlist.sort();
+        netlist
+    }
+
+    pub fn pin(&self, name: &str) -> Option<&Pin> {
+        self.inputs
+            .iter()
+            .chain(&self.outputs)
+            .find(|pin| pin.name == name)
+    }
+
+    // gate driving each net, if any
+    pub fn drivers(&self) -> Vec<Option<usize>> {
+        let mut drivers = vec![None; self.nets];
+        for (idx, gate) in self.gates.iter().enumerate() {
+            for net in self.gate_outputs(gate) {
+                drivers[net] = Some(idx);
+            }
+        }
+        drivers
+    }
+
+    pub fn gate_outputs(&self, gate: &Gate) -> Vec<usize> {
+        match gate {
+            Gate::Nand { out, .. } | Gate::Dff { out, .. } => vec![*out],
+            Gate::Builtin(block) => self.blocks[*block].outputs.concat(),
+        }
+    }
+
+    // the nets a gate reads before it can produce its output; a DFF
+    // needs none, its output was settled at the last clock edge
+    pub fn gate_inputs(&self, gate: &Gate) -> Vec<usize> {
+        match gate {
+            Gate::Nand { a, b, .. } => vec![*a, *b],
+            Gate::Dff { .. } => Vec::new(),
+            Gate::Builtin(block) => {
+                let block = &self.blocks[*block];
+                block
+                    .inputs
+                    .iter()
+                    .zip(&block.combinational)
+                    .filter(|(_, combinational)| **combinational)
+                    .flat_map(|(nets, _)| nets.iter().copied())
+                    .collect()
+            }
+        }
+    }
+
+    // topological order over the combinational dependencies
+    fn sort(&mut self) {
+        let drivers = self.drivers();
+        let mut waiting = vec![0; self.gates.len()];
+        let mut readers: Vec<Vec<usize>> = vec![Vec::new(); self.gates.len()];
+        for (idx, gate) in self.gates.iter().enumerate() {
+            for net in self.gate_inputs(gate) {
+                if let Some(driver) = drivers[net] {
+                    waiting[idx] += 1;
+                    readers[driver].push(idx);
+                }
+            }
+        }
+        let mut ready: VecDeque<usize> = (0..self.gates.len())
+            .filter(|idx| waiting[*idx] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.gates.len());
+        while let Some(idx) = ready.pop_front() {
+            order.push(idx);
+            for reader in &readers[idx] {
+                waiting[*reader] -= 1;
+                if waiting[*reader] == 0 {
+                    ready.push_back(*reader);
+                }
+            }
+        }
+        if order.len() < self.gates.len() {
+            let stuck = (0..self.gates.len()).find(|idx| waiting[*idx] > 0).unwrap();
+            panic!(
+                "ERROR: combinational loop through {}",
+                self.instances[self.owners[stuck]].path
+            );
+        }
+        self.gates = order.iter().map(|idx| self.gates[*idx]).collect();
+        self.owners = order.iter().map(|idx| self.owners[*idx]).collect();
+    }
+}
+
+impl Builder<'_> {
+    fn nets(&mut self, width: usize) -> Vec<usize> {
+        let start = self.parent.len();
+        self.parent.extend(start..start + width);
+        (start..start + width).collect()
+    }
+
+    fn find(&mut self, mut net: usize) -> usize {
+        while self.parent[net] != net {
+            self.parent[net] = self.parent[self.parent[net]];
+            net = self.parent[net];
+        }
+        net
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        self.parent[a] = b;
+    }
+
+    // `pins` has the nets of every IN and OUT pin of `chip`
+    fn instantiate(
+        &mut self,
+        chip: &ChipDef,
+        pins: &HashMap<String, Vec<usize>>,
+        instance: usize,
+        depth: usize,
+    ) {
+        if let Some(builtin) = &chip.builtin {
+            let gate = match builtin.as_str() {
+                "Nand" => Gate::Nand {
+                    a: pins["a"][0],
+                    b: pins["b"][0],
+                    out: pins["out"][0],
+                },
+                "DFF" => Gate::Dff {
+                    input: pins["in"][0],
+                    out: pins["out"][0],
+                },
+                _ => {
+                    // a .hdl file may name a built-in for its own interface
+                    let def = builtin::chip(builtin)
+                        .unwrap_or_else(|| panic!("ERROR: there is no built-in {}", builtin));
+                    let nets = |decls: &[PinDecl]| -> Vec<Vec<usize>> {
+                        decls
+                            .iter()
+                            .map(|decl| match pins.get(&decl.name) {
+                                Some(nets) if nets.len() == decl.width => nets.to_vec(),
+                                _ => panic!(
+                                    "ERROR: {} does not have the pins of built-in {}",
+                                    chip.name, builtin
+                                ),
+                            })
+                            .collect()
+                    };
+                    self.blocks.push(Block {
+                        chip: builtin.to_string(),
+                        inputs: nets(&def.inputs),
+                        outputs: nets(&def.outputs),
+                        combinational: def
+                            .inputs
+                            .iter()
+                            .map(|decl| !def.clocked.contains(&decl.name))
+                            .collect(),
+                    });
+                    Gate::Builtin(self.blocks.len() - 1)
+                }
+            };
+            self.gates.push(gate);
+            self.owners.push(instance);
+            return;
+        }
+        if depth == MAX_DEPTH {
+            panic!("ERROR: {} contains itself", chip.name);
+        }
+
+        let at = |line: usize| format!("{}:{}", chip.path.display(), line);
+        let defs: Vec<Rc<ChipDef>> = chip
+            .parts
+            .iter()
+            .map(|part| {
+                self.library.chip(&part.chip).unwrap_or_else(|| {
+                    panic!("ERROR: {}: unknown chip {}", at(part.line), part.chip)
+                })
+            })
+            .collect();
+
+        // internal pins take the width of the part output that drives them
+        let mut internal: HashMap<String, Vec<usize>> = HashMap::new();
+        for (part, def) in chip.parts.iter().zip(&defs) {
+            for connection in &part.connections {
+                let decl = match def.output(&connection.pin.name) {
+                    Some(decl) => decl,
+                    None => continue,
+                };
+                let target = match &connection.signal {
+                    Signal::Pin(target) if chip.pin(&target.name).is_none() => target,
+                    _ => continue,
+                };
+                if target.bits.is_some() {
+                    panic!(
+                        "ERROR: {}: sub-bus of internal pin {}",
+                        at(connection.line),
+                        target
+                    );
+                }
+                let (lo, hi) = bits(&connection.pin, decl.width, &at(connection.line));
+                match internal.get(&target.name) {
+                    Some(nets) if nets.len() != hi - lo + 1 => panic!(
+                        "ERROR: {}: {} is {} bits wide elsewhere",
+                        at(connection.line),
+                        target.name,
+                        nets.len()
+                    ),
+                    Some(_) => {}
+                    None => {
+                        let nets = self.nets(hi - lo + 1);
+                        internal.insert(target.name.to_string(), nets);
+                    }
+                }
+            }
+        }
+
+        for (part, def) in chip.parts.iter().zip(&defs) {
+            let mut part_pins: HashMap<String, Vec<usize>> = HashMap::new();
+            // unconnected inputs are false
+            for decl in &def.inputs {
+                part_pins.insert(decl.name.to_string(), vec![FALSE; decl.width]);
+            }
+            for decl in &def.outputs {
+                let nets = self.nets(decl.width);
+                part_pins.insert(decl.name.to_string(), nets);
+            }
+
+            for connection in &part.connections {
+                let at = at(connection.line);
+                let decl = def.pin(&connection.pin.name).unwrap_or_else(|| {
+                    panic!(
+                        "ERROR: {}: {} has no pin {}",
+                        at, def.name, connection.pin.name
+                    )
+                });
+                let (lo, hi) = bits(&connection.pin, decl.width, &at);
+                let nets = match &connection.signal {
+                    Signal::Const(value) => vec![if *value { TRUE } else { FALSE }; hi - lo + 1],
+                    Signal::Pin(signal) => {
+                        let nets = match (chip.pin(&signal.name), internal.get(&signal.name)) {
+                            (Some(decl), _) => {
+                                let (s_lo, s_hi) = bits(signal, decl.width, &at);
+                                pins[&signal.name][s_lo..=s_hi].to_vec()
+                            }
+                            (None, Some(nets)) => nets.to_vec(),
+                            (None, None) => {
+                                panic!("ERROR: {}: {} is not driven by any part", at, signal)
+                            }
+                        };
+                        if nets.len() != hi - lo + 1 {
+                            let message =
+                                width_message(&connection.pin, hi - lo + 1, signal, nets.len());
+                            panic!("ERROR: {}: {}", at, message);
+                        }
+                        nets
+                    }
+                };
+
+                if def.input(&decl.name).is_some() {
+                    if let Signal::Pin(signal) = &connection.signal {
+                        if chip.output(&signal.name).is_some() {
+                            panic!(
+                                "ERROR: {}: output pin {} cannot feed a part",
+                                at, signal.name
+                            );
+                        }
+                    }
+                    part_pins.get_mut(&decl.name).unwrap()[lo..=hi].copy_from_slice(&nets);
+                } else {
+                    match &connection.signal {
+                        Signal::Const(_) => panic!("ERROR: {}: cannot drive a constant", at),
+                        Signal::Pin(signal) if chip.input(&signal.name).is_some() => {
+                            panic!("ERROR: {}: cannot drive input pin {}", at, signal.name)
+                        }
+                        _ => {}
+                    }
+                    for (bit, net) in nets.into_iter().enumerate() {
+                        let out = part_pins[&decl.name][lo + bit];
+                        self.union(out, net);
+                    }
+                }
+            }
+
+            let child = self.instances.len();
+            self.instances.push(Instance {
+                path: format!(
+                    "{}/{}:{}",
+                    self.instances[instance].path, def.name, part.line
+                ),
+                chip: def.name.to_string(),
+                parent: Some(instance),
+            });
+            self.instantiate(def, &part_pins, child, depth + 1);
+        }
+    }
+}
+
+// first and last bit of `pin`, checked against its width
+fn bits(pin: &PinRef, width: usize, at: &str) -> (usize, usize) {
+    let (lo, hi) = pin.range(width);
+    if hi >= width {
+        panic!("ERROR: {}: {} is only {} bits wide", at, pin, width);
+    }
+    (lo, hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn flatten(name: &str) -> Netlist {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../..")
+            .join(name);
+        let mut library = Library::for_file(&path);
+        let chip = library.load(&path).unwrap();
+        Netlist::new(&mut library, &chip)
+    }
+
+    // (Nand gates, DFFs, built-in parts)
+    fn counts(netlist: &Netlist) -> (usize, usize, usize) {
+        let mut counts = (0, 0, 0);
+        for gate in &netlist.gates {
+            match gate {
+                Gate::Nand { .. } => counts.0 += 1,
+                Gate::Dff { .. } => counts.1 += 1,
+                Gate::Builtin(_) => counts.2 += 1,
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn and_is_two_nands() {
+        let netlist = flatten("01/And.hdl");
+        assert_eq!(counts(&netlist), (2, 0, 0));
+        let paths: Vec<&str> = netlist.instances.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["And", "And/Nand:18", "And/Not:19", "And/Not:19/Nand:17"]
+        );
+
+        // out = Nand(true, Nand(a, b))
+        let (a, b) = (netlist.inputs[0].nets[0], netlist.inputs[1].nets[0]);
+        let out = netlist.outputs[0].nets[0];
+        let x = match netlist.gates[0] {
+            Gate::Nand { a: ga, b: gb, out } if (ga, gb) == (a, b) => out,
+            gate => panic!("first gate is {:?}", gate),
+        };
+        assert_eq!(netlist.gates[1], Gate::Nand { a: TRUE, b: x, out });
+        assert_eq!(netlist.owners, vec![1, 3]);
+    }
+
+    #[test]
+    fn bit_is_a_mux_and_a_dff() {
+        let netlist = flatten("03/a/Bit.hdl");
+        // Mux: Not 1, two Ands 2 each, Or 3
+        assert_eq!(counts(&netlist), (8, 1, 0));
+        let dff = netlist
+            .gates
+            .iter()
+            .position(|gate| matches!(gate, Gate::Dff { .. }))
+            .unwrap();
+        assert_eq!(netlist.instances[netlist.owners[dff]].path, "Bit/DFF:19");
+        let out = netlist.outputs[0].nets[0];
+        assert_eq!(netlist.drivers()[out], Some(dff));
+        // the DFF's output feeds the Mux without making a loop
+        assert!(netlist.gate_inputs(&netlist.gates[dff]).is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::netlist::{Gate, Netlist};
+
+// Nand gates in one part of the design, its own parts included
+#[derive(Debug, Clone)]
+pub struct Count {
+    pub path: String,
+    pub chip: String,
+    // 0 for the top chip, 1 for its parts and so on
+    pub depth: usize,
+    pub nands: usize,
+}
+
+// Size and speed of a flattened chip. Delays are counted in Nand gates; DFFs
+// and built-in memories start a path afresh and cost nothing themselves.
+pub struct Report {
+    pub nands: usize,
+    pub dffs: usize,
+    pub builtins: usize,
+    // every instance, each before its parts
+    pub counts: Vec<Count>,
+    // the Nand gates of the longest combinational path, first to last
+    pub critical_path: Vec<usize>,
+}
+
+impl Report {
+    pub fn new(netlist: &Netlist) -> Self {
+        let mut counts: Vec<Count> = netlist
+            .instances
+            .iter()
+            .map(|instance| Count {
+                path: instance.path.to_string(),
+                chip: instance.chip.to_string(),
+                depth: 0,
+                nands: 0,
+            })
+            .collect();
+        for (idx, instance) in netlist.instances.iter().enumerate() {
+            if let Some(parent) = instance.parent {
+                counts[idx].depth = counts[parent].depth + 1;
+            }
+        }
+        let (mut dffs, mut builtins) = (0, 0);
+        for (gate, owner) in netlist.gates.iter().zip(&netlist.owners) {
+            match gate {
+                Gate::Nand { .. } => {
+                    let mut instance = Some(*owner);
+                    while let Some(idx) = instance {
+                        counts[idx].nands += 1;
+                        instance = netlist.instances[idx].parent;
+                    }
+                }
+                Gate::Dff { .. } => dffs += 1,
+                Gate::Builtin(_) => builtins += 1,
+            }
+        }
+
+        Report {
+            nands: counts[0].nands,
+            dffs,
+            builtins,
+            counts,
+            critical_path: critical_path(netlist),
+        }
+    }
+
+    pub fn delay(&self) -> usize {
+        self.critical_path.len()
+    }
+
+    // the critical path as (instance, Nand gates) steps, the instance being
+    // the part at most `depth` levels down that holds the gates
+    pub fn critical_steps(&self, netlist: &Netlist, depth: usize) -> Vec<(usize, usize)> {
+        let mut steps: Vec<(usize, usize)> = Vec::new();
+        for gate in &self.critical_path {
+            let mut owner = netlist.owners[*gate];
+            while self.counts[owner].depth > depth {
+                owner = netlist.instances[owner].parent.unwrap();
+            }
+            match steps.last_mut() {
+                Some((last, gates)) if *last == owner => *gates += 1,
+                _ => steps.push((owner, 1)),
+            }
+        }
+        steps
+    }
+}
+
+// Gates are in evaluation order, so one pass finds how many Nand delays
+// after the clock edge every net settles, and which input it waits for.
+fn critical_path(netlist: &Netlist) -> Vec<usize> {
+    let mut delay = vec![0; netlist.nets];
+    // gate driving the net and the input net it waited for
+    let mut from: Vec<Option<(usize, usize)>> = vec![None; netlist.nets];
+    for (idx, gate) in netlist.gates.iter().enumerate() {
+        let cost = match gate {
+            Gate::Nand { .. } => 1,
+            _ => 0,
+        };
+        let slowest = netlist
+            .gate_inputs(gate)
+            .into_iter()
+            .max_by_key(|net| delay[*net]);
+        let slowest = match slowest {
+            Some(net) => net,
+            None => continue,
+        };
+        for out in netlist.gate_outputs(gate) {
+            delay[out] = delay[slowest] + cost;
+            from[out] = Some((idx, slowest));
+        }
+    }
+
+    let mut net = (0..netlist.nets).max_by_key(|net| delay[*net]).unwrap_or(0);
+    let mut path = Vec::new();
+    while let Some((gate, input)) = from[net] {
+        if let Gate::Nand { .. } = netlist.gates[gate] {
+            path.push(gate);
+        }
+        net = input;
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::Library;
+    use std::path::PathBuf;
+
+    fn half_adder() -> Netlist {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../02/HalfAdder.hdl");
+        let mut library = Library::for_file(&path);
+        let chip = library.load(&path).unwrap();
+        Netlist::new(&mut library, &chip)
+    }
+
+    #[test]
+    fn half_adder_counts() {
+        let netlist = half_adder();
+        let report = Report::new(&netlist);
+        assert_eq!((report.nands, report.dffs, report.builtins), (8, 0, 0));
+        // Xor is Nand 1 + Or 3 + And 2
+        let parts: Vec<(&str, usize, usize)> = report
+            .counts
+            .iter()
+            .filter(|count| count.depth == 1)
+            .map(|count| (count.chip.as_str(), count.depth, count.nands))
+            .collect();
+        assert_eq!(parts, vec![("Xor", 1, 6), ("And", 1, 2)]);
+        assert_eq!(report.counts[0].path, "HalfAdder");
+        assert_eq!(report.counts[0].nands, 8);
+    }
+
+    #[test]
+    fn half_adder_critical_path() {
+        let netlist = half_adder();
+        let report = Report::new(&netlist);
+        // a through the Or's Not and Nand, then the And's Nand and Not
+        assert_eq!(report.delay(), 4);
+        let steps = |depth: usize| -> Vec<(String, usize)> {
+            report
+                .critical_steps(&netlist, depth)
+                .into_iter()
+                .map(|(owner, gates)| (netlist.instances[owner].path.to_string(), gates))
+                .collect()
+        };
+        assert_eq!(steps(0), vec![("HalfAdder".to_string(), 4)]);
+        assert_eq!(steps(1), vec![("HalfAdder/Xor:17".to_string(), 4)]);
+        assert_eq!(
+            steps(2),
+            vec![
+                ("HalfAdder/Xor:17/Or:18".to_string(), 2),
+                ("HalfAdder/Xor:17/And:19".to_string(), 2)
+            ]
+        );
+    }
+}
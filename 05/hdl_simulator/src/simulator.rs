@@ -0,0 +1,283 @@
+use std::path::Path;
+
+use crate::builtin::Builtin;
+use crate::library::Library;
+use crate::netlist::{Gate, Netlist, TRUE};
+
+// Evaluates a flattened chip. Pin values are the pin's bits read as a number,
+// lowest bit first, like the course's test scripts show them.
+//
+// A clock cycle is a tick, where DFFs and built-in memories read their
+// inputs, then a tock, where their outputs change.
+pub struct Simulator {
+    netlist: Netlist,
+    values: Vec<bool>,
+    // (in, out) of every DFF and the value in had at the last tick
+    dffs: Vec<(usize, usize)>,
+    latched: Vec<bool>,
+    builtins: Vec<Builtin>,
+    // half cycles since the start
+    time: usize,
+}
+
+impl Simulator {
+    pub fn new(netlist: Netlist) -> Self {
+        let mut values = vec![false; netlist.nets];
+        values[TRUE] = true;
+        let dffs: Vec<(usize, usize)> = netlist
+            .gates
+            .iter()
+            .filter_map(|gate| match gate {
+                Gate::Dff { input, out } => Some((*input, *out)),
+                _ => None,
+            })
+            .collect();
+        let builtins = netlist
+            .blocks
+            .iter()
+            .map(|block| Builtin::new(&block.chip))
+            .collect();
+        let mut simulator = Simulator {
+            netlist,
+            values,
+            latched: vec![false; dffs.len()],
+            dffs,
+            builtins,
+            time: 0,
+        };
+        simulator.eval();
+        simulator
+    }
+
+    // a .hdl file, with its parts found as Library::for_file finds them
+    pub fn load(path: &str) -> Self {
+        let path = Path::new(path);
+        Simulator::with_library(&mut Library::for_file(path), path)
+    }
+
+    pub fn with_library(library: &mut Library, path: &Path) -> Self {
+        let chip = library
+            .load(path)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+        Simulator::new(Netlist::new(library, &chip))
+    }
+
+    pub fn from_library(library: &mut Library, chip: &str) -> Self {
+        let chip = library
+            .chip(chip)
+            .unwrap_or_else(|| panic!("ERROR: unknown chip {}", chip));
+        Simulator::new(Netlist::new(library, &chip))
+    }
+
+    pub fn netlist(&self) -> &Netlist {
+        &self.netlist
+    }
+
+    // half cycles: even before a tick, odd between tick and tock
+    pub fn time(&self) -> usize {
+        self.time
+    }
+
+    // A pin as `in` or `in[3]`, or a word of a built-in part as `RAM16K[7]`
+    // or `ARegister[]`
+    pub fn get(&self, name: &str) -> i16 {
+        if let Some((builtin, address)) = self.memory_cell(name) {
+            return self.builtins[builtin].memory[address];
+        }
+        word(&self.values, &self.nets(name))
+    }
+
+    // bits in what get returns for the name
+    pub fn width(&self, name: &str) -> usize {
+        match self.memory_cell(name) {
+            Some(_) => 16,
+            None => self.nets(name).len(),
+        }
+    }
+
+    // input pins and built-in memory can be set; outputs follow on the next eval
+    pub fn set(&mut self, name: &str, value: i16) {
+        if let Some((builtin, address)) = self.memory_cell(name) {
+            self.builtins[builtin].memory[address] = value;
+            return;
+        }
+        let (pin, _) = split_index(name);
+        if self.netlist.chip.input(pin).is_none() {
+            panic!(
+                "ERROR: {} is not an input pin of {}",
+                pin, self.netlist.chip.name
+            );
+        }
+        let nets = self.nets(name);
+        set_word(&mut self.values, &nets, value);
+    }
+
+    // contents of the first built-in part with this chip name
+    pub fn memory(&self, chip: &str) -> Option<&[i16]> {
+        self.builtins
+            .iter()
+            .find(|builtin| builtin.name == chip)
+            .map(|builtin| builtin.memory.as_slice())
+    }
+
+    pub fn memory_mut(&mut self, chip: &str) -> Option<&mut Vec<i16>> {
+        self.builtins
+            .iter_mut()
+            .find(|builtin| builtin.name == chip)
+            .map(|builtin| &mut builtin.memory)
+    }
+
+    pub fn eval(&mut self) {
+        let values = &mut self.values;
+        for gate in &self.netlist.gates {
+            match *gate {
+                Gate::Nand { a, b, out } => values[out] = !(values[a] && values[b]),
+                Gate::Dff { .. } => {}
+                Gate::Builtin(idx) => {
+                    let block = &self.netlist.blocks[idx];
+                    let inputs: Vec<i16> =
+                        block.inputs.iter().map(|nets| word(values, nets)).collect();
+                    let outputs = self.builtins[idx].eval(&inputs);
+                    for (nets, value) in block.outputs.iter().zip(outputs) {
+                        set_word(values, nets, value);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.eval();
+        for (latched, (input, _)) in self.latched.iter_mut().zip(&self.dffs) {
+            *latched = self.values[*input];
+        }
+        let values = &self.values;
+        for (builtin, block) in self.builtins.iter_mut().zip(&self.netlist.blocks) {
+            let inputs: Vec<i16> = block.inputs.iter().map(|nets| word(values, nets)).collect();
+            builtin.tick(&inputs);
+        }
+        self.time += 1;
+    }
+
+    pub fn tock(&mut self) {
+        for (latched, (_, out)) in self.latched.iter().zip(&self.dffs) {
+            self.values[*out] = *latched;
+        }
+        for builtin in &mut self.builtins {
+            builtin.tock();
+        }
+        self.eval();
+        self.time += 1;
+    }
+
+    fn nets(&self, name: &str) -> Vec<usize> {
+        let (pin, index) = split_index(name);
+        let nets = match self.netlist.pin(pin) {
+            Some(pin) => &pin.nets,
+            None => panic!("ERROR: {} has no pin {}", self.netlist.chip.name, pin),
+        };
+        match index {
+            Some(index) if index >= nets.len() => {
+                panic!("ERROR: {} is only {} bits wide", pin, nets.len())
+            }
+            Some(index) => vec![nets[index]],
+            None => nets.to_vec(),
+        }
+    }
+
+    // `RAM16K[7]` when the chip has no pin of that name but a RAM16K part
+    fn memory_cell(&self, name: &str) -> Option<(usize, usize)> {
+        let open = name.find('[')?;
+        let (chip, index) = (&name[..open], name[open + 1..].strip_suffix(']')?);
+        if self.netlist.pin(chip).is_some() {
+            return None;
+        }
+        let builtin = self
+            .builtins
+            .iter()
+            .position(|builtin| builtin.name == chip)?;
+        let address = if index.is_empty() {
+            0
+        } else {
+            index
+                .parse()
+                .unwrap_or_else(|_| panic!("ERROR: bad address {}", name))
+        };
+        if address >= self.builtins[builtin].memory.len() {
+            panic!("ERROR: {} is out of range", name);
+        }
+        Some((builtin, address))
+    }
+}
+
+fn word(values: &[bool], nets: &[usize]) -> i16 {
+    nets.iter().enumerate().fold(0u16, |value, (bit, net)| {
+        value | (values[*net] as u16) << bit
+    }) as i16
+}
+
+fn set_word(values: &mut [bool], nets: &[usize], value: i16) {
+    for (bit, net) in nets.iter().enumerate() {
+        values[*net] = (value as u16) >> bit & 1 == 1;
+    }
+}
+
+// `in[3]` -> ("in", Some(3))
+fn split_index(name: &str) -> (&str, Option<usize>) {
+    let index = name
+        .find('[')
+        .and_then(|open| Some((open, name[open + 1..].strip_suffix(']')?.parse().ok()?)));
+    match index {
+        Some((open, index)) => (&name[..open], Some(index)),
+        None => (name, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project_chip(name: &str) -> Simulator {
+        Simulator::load(&format!("{}/../../{}", env!("CARGO_MANIFEST_DIR"), name))
+    }
+
+    #[test]
+    fn and_truth_table() {
+        let mut simulator = project_chip("01/And.hdl");
+        for (a, b) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter() {
+            simulator.set("a", *a);
+            simulator.set("b", *b);
+            simulator.eval();
+            assert_eq!(simulator.get("out"), a & b, "{} And {}", a, b);
+        }
+    }
+
+    // the rows of 03/a/Bit.cmp: in and load set before the tick, out after
+    // the tock
+    #[test]
+    fn bit_loads_and_holds() {
+        let mut simulator = project_chip("03/a/Bit.hdl");
+        assert_eq!(simulator.get("out"), 0);
+        let rows = [
+            (0, 0, 0),
+            (0, 1, 0),
+            (1, 0, 0),
+            (1, 1, 1),
+            (0, 0, 1),
+            (1, 0, 1),
+            (0, 1, 0),
+        ];
+        let mut before = 0;
+        for (cycle, (input, load, out)) in rows.iter().enumerate() {
+            simulator.set("in", *input);
+            simulator.set("load", *load);
+            simulator.tick();
+            // out only changes at the tock
+            assert_eq!(simulator.get("out"), before, "cycle {}+", cycle);
+            simulator.tock();
+            assert_eq!(simulator.get("out"), *out, "cycle {}", cycle + 1);
+            assert_eq!(simulator.time(), 2 * cycle + 2);
+            before = *out;
+        }
+    }
+}
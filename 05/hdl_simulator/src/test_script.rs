@@ -0,0 +1,182 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use hack_cpu::test_script::Target;
+
+use crate::library::Library;
+use crate::simulator::Simulator;
+use crate::vcd::Vcd;
+
+// The chip a hardware test script drives. `load Chip.hdl` builds it with the
+// chip's project as its library; the chips named in `prefer_builtin` are
+// simulated natively instead of from their .hdl files.
+pub struct TestBench {
+    builtins: Vec<String>,
+    simulator: Option<Simulator>,
+    // file and pins to dump once a chip is loaded, and the dump
+    vcd_file: Option<(PathBuf, Vec<String>)>,
+    vcd: Option<Vcd>,
+}
+
+impl TestBench {
+    pub fn new() -> Self {
+        TestBench {
+            builtins: Vec::new(),
+            simulator: None,
+            vcd_file: None,
+            vcd: None,
+        }
+    }
+
+    pub fn prefer_builtin(&mut self, name: &str) {
+        self.builtins.push(name.to_string());
+    }
+
+    // samples `pins` (every pin when empty) after each eval, tick and tock
+    pub fn dump(&mut self, path: &Path, pins: &[String]) {
+        self.vcd_file = Some((path.to_path_buf(), pins.to_vec()));
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        self.simulator
+            .as_ref()
+            .expect("ERROR: the script has not loaded a chip")
+    }
+
+    fn simulator_mut(&mut self) -> &mut Simulator {
+        self.simulator
+            .as_mut()
+            .expect("ERROR: the script has not loaded a chip")
+    }
+
+    fn sample(&mut self) {
+        if let (Some(vcd), Some(simulator)) = (&mut self.vcd, &self.simulator) {
+            vcd.sample(simulator);
+        }
+    }
+}
+
+impl Default for TestBench {
+    fn default() -> Self {
+        TestBench::new()
+    }
+}
+
+impl Target for TestBench {
+    fn load(&mut self, path: &Path) {
+        let mut library = Library::for_file(path);
+        for name in &self.builtins {
+            library.prefer_builtin(name);
+        }
+        let simulator = Simulator::with_library(&mut library, path);
+        if let Some((path, pins)) = &self.vcd_file {
+            let file = File::create(path).expect("Cannot write VCD file!");
+            self.vcd = Some(Vcd::new(Box::new(BufWriter::new(file)), &simulator, pins));
+        }
+        self.simulator = Some(simulator);
+    }
+
+    fn get(&self, name: &str) -> i16 {
+        self.simulator().get(name)
+    }
+
+    fn set(&mut self, name: &str, value: i16) {
+        self.simulator_mut().set(name, value);
+    }
+
+    fn eval(&mut self) {
+        self.simulator_mut().eval();
+        self.sample();
+    }
+
+    fn tick(&mut self) {
+        self.simulator_mut().tick();
+        self.sample();
+    }
+
+    fn tock(&mut self) {
+        self.simulator_mut().tock();
+        self.sample();
+    }
+
+    // cycles, with a + between tick and tock
+    fn time(&self) -> String {
+        let time = self
+            .simulator
+            .as_ref()
+            .map_or(0, |simulator| simulator.time());
+        let half = if time % 2 == 1 { "+" } else { "" };
+        format!("{}{}", time / 2, half)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use hack_cpu::test_script::TestScript;
+
+    use super::*;
+
+    fn project_file(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../..")
+            .join(name)
+    }
+
+    // runs the script, failing the test with the comparison failure; the
+    // .out file goes next to it like with the course tools
+    fn passes(path: &Path) -> TestScript<TestBench> {
+        let mut script = TestScript::new(path.to_str().unwrap(), TestBench::new());
+        if let Err(mismatch) = script.run() {
+            panic!("{}", mismatch);
+        }
+        script
+    }
+
+    #[test]
+    fn and_tst_passes() {
+        let script = passes(&project_file("01/And.tst"));
+        assert_eq!(script.output().len(), 5);
+        assert_eq!(script.output()[4], "|   1   |   1   |   1   |");
+    }
+
+    #[test]
+    fn bit_tst_passes() {
+        let script = passes(&project_file("03/a/Bit.tst"));
+        assert_eq!(script.output().len(), 15);
+        assert_eq!(script.output()[14], "| 7    |  0  |  1  |  0  |");
+        assert_eq!(script.target().simulator().get("out"), 0);
+    }
+
+    #[test]
+    fn corrupted_cmp_reports_the_pin_and_line() {
+        let dir = std::env::temp_dir().join(format!("hdl_test_script_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // And.tst loading the project's And.hdl, against a .cmp that has
+        // 1 And 0 = 1
+        let and = project_file("01/And.hdl");
+        let tst = fs::read_to_string(project_file("01/And.tst"))
+            .unwrap()
+            .replace("load And.hdl", &format!("load {}", and.to_str().unwrap()));
+        let cmp = fs::read_to_string(project_file("01/And.cmp"))
+            .unwrap()
+            .replace("|   1   |   0   |   0   |", "|   1   |   0   |   1   |");
+        fs::write(dir.join("And.tst"), tst).unwrap();
+        fs::write(dir.join("And.cmp"), cmp).unwrap();
+
+        let path = dir.join("And.tst");
+        let mut script = TestScript::new(path.to_str().unwrap(), TestBench::new());
+        let mismatch = script.run().unwrap_err();
+        assert_eq!(mismatch.line, 4);
+        assert_eq!(
+            mismatch.columns,
+            vec![("out".to_string(), "1".to_string(), "0".to_string())]
+        );
+        assert!(mismatch.to_string().contains("  out: expected 1, got 0\n"));
+        // the last row never ran
+        assert_eq!(script.output().len(), 4);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,211 @@
+use std::io::Write;
+
+use crate::simulator::Simulator;
+
+// Value change dump of chosen pins, for GTKWave and the like. Every sample is
+// one time unit, so a clock cycle sampled at tick and tock takes two.
+pub struct Vcd {
+    out: Box<dyn Write>,
+    traces: Vec<Trace>,
+    time: usize,
+}
+
+struct Trace {
+    name: String,
+    width: usize,
+    id: String,
+    // None until the first sample
+    value: Option<i16>,
+}
+
+impl Vcd {
+    // `names` are anything Simulator::get takes; none means every pin of the chip
+    pub fn new(mut out: Box<dyn Write>, simulator: &Simulator, names: &[String]) -> Self {
+        let netlist = simulator.netlist();
+        let names: Vec<String> = if names.is_empty() {
+            netlist
+                .inputs
+                .iter()
+                .chain(&netlist.outputs)
+                .map(|pin| pin.name.to_string())
+                .collect()
+        } else {
+            names.to_vec()
+        };
+        let traces: Vec<Trace> = names
+            .into_iter()
+            .enumerate()
+            .map(|(idx, name)| Trace {
+                width: simulator.width(&name),
+                name,
+                id: id(idx),
+                value: None,
+            })
+            .collect();
+
+        let mut header = String::new();
+        header.push_str("$version hdl_simulator $end\n");
+        header.push_str("$timescale 1ns $end\n");
+        header.push_str(&format!("$scope module {} $end\n", netlist.chip.name));
+        for trace in &traces {
+            let bits = if trace.width == 1 {
+                String::new()
+            } else {
+                format!(" [{}:0]", trace.width - 1)
+            };
+            // `RAM16K[7]` and `ARegister[]` as plain names
+            let name = trace.name.replace('[', "_").replace(']', "");
+            header.push_str(&format!(
+                "$var wire {} {} {}{} $end\n",
+                trace.width,
+                trace.id,
+                name.trim_end_matches('_'),
+                bits
+            ));
+        }
+        header.push_str("$upscope $end\n$enddefinitions $end\n");
+        out.write_all(header.as_bytes())
+            .expect("Cannot write VCD file!");
+        Vcd {
+            out,
+            traces,
+            time: 0,
+        }
+    }
+
+    // writes the pins that changed since the last sample
+    pub fn sample(&mut self, simulator: &Simulator) {
+        let mut changes = String::new();
+        for trace in &mut self.traces {
+            let value = simulator.get(&trace.name);
+            if trace.value == Some(value) {
+                continue;
+            }
+            trace.value = Some(value);
+            let bits = value as u16 as u32 & ((1 << trace.width) - 1);
+            if trace.width == 1 {
+                changes.push_str(&format!("{}{}\n", bits, trace.id));
+            } else {
+                changes.push_str(&format!("b{:b} {}\n", bits, trace.id));
+            }
+        }
+        if !changes.is_empty() {
+            write!(self.out, "#{}\n{}", self.time, changes).expect("Cannot write VCD file!");
+        }
+        self.time += 1;
+    }
+}
+
+// identifiers are printable characters from ! on, as many as needed
+fn id(mut idx: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (idx % 94) as u8) as char);
+        idx /= 94;
+        if idx == 0 {
+            return id;
+        }
+        idx -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::library::Library;
+
+    // a writer the test can still read after handing it to the Vcd
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().to_vec()).unwrap()
+        }
+    }
+
+    fn cycle(vcd: &mut Vcd, simulator: &mut Simulator) {
+        simulator.tick();
+        vcd.sample(simulator);
+        simulator.tock();
+        vcd.sample(simulator);
+    }
+
+    #[test]
+    fn bit_over_two_cycles() {
+        let mut simulator =
+            Simulator::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../../03/a/Bit.hdl"));
+        let out = Shared::default();
+        let mut vcd = Vcd::new(Box::new(out.clone()), &simulator, &[]);
+        simulator.set("in", 1);
+        simulator.set("load", 1);
+        simulator.eval();
+        vcd.sample(&simulator);
+        cycle(&mut vcd, &mut simulator);
+        simulator.set("load", 0);
+        simulator.set("in", 0);
+        cycle(&mut vcd, &mut simulator);
+        assert_eq!(
+            out.text(),
+            "$version hdl_simulator $end
+$timescale 1ns $end
+$scope module Bit $end
+$var wire 1 ! in $end
+$var wire 1 \" load $end
+$var wire 1 # out $end
+$upscope $end
+$enddefinitions $end
+#0
+1!
+1\"
+0#
+#2
+1#
+#3
+0!
+0\"
+"
+        );
+    }
+
+    #[test]
+    fn buses_and_memory() {
+        let mut simulator = Simulator::from_library(&mut Library::new(), "Register");
+        let names = vec!["in".to_string(), "Register[]".to_string()];
+        let out = Shared::default();
+        let mut vcd = Vcd::new(Box::new(out.clone()), &simulator, &names);
+        simulator.set("in", -2);
+        simulator.set("load", 1);
+        vcd.sample(&simulator);
+        cycle(&mut vcd, &mut simulator);
+        assert_eq!(
+            out.text(),
+            "$version hdl_simulator $end
+$timescale 1ns $end
+$scope module Register $end
+$var wire 16 ! in [15:0] $end
+$var wire 16 \" Register [15:0] $end
+$upscope $end
+$enddefinitions $end
+#0
+b1111111111111110 !
+b0 \"
+#2
+b1111111111111110 \"
+"
+        );
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::builtin;
+use crate::hdl::{ChipDef, PinDecl, Signal};
+use crate::library::Library;
+use crate::netlist::{Gate, Netlist, FALSE, TRUE};
+
+// Verilog for a chip and everything it uses: one module per chip with its
+// parts as instances, and behavioural modules for the built-in chips. Chips
+// with a DFF or a built-in memory somewhere inside get a clk input.
+pub fn hierarchical(library: &mut Library, chip: &Rc<ChipDef>) -> String {
+    // catches the mistakes the modules would otherwise inherit
+    Netlist::new(library, chip);
+    let mut writer = Hierarchy {
+        library,
+        clocked: HashMap::new(),
+        text: String::new(),
+    };
+    writer.module(chip);
+    writer.text
+}
+
+// One module with a wire per net of the flattened chip and a continuous
+// assignment per Nand gate. Built-in memories stay instances.
+pub fn flattened(netlist: &Netlist) -> String {
+    let net = |net: usize| match net {
+        FALSE => "1'b0".to_string(),
+        TRUE => "1'b1".to_string(),
+        _ => format!("n{}", net),
+    };
+    let nets = |nets: &[usize]| {
+        let bits: Vec<String> = nets.iter().rev().map(|n| net(*n)).collect();
+        concat(&bits, &|_| 0)
+    };
+
+    let mut regs = vec![false; netlist.nets];
+    for gate in &netlist.gates {
+        if let Gate::Dff { out, .. } = gate {
+            regs[*out] = true;
+        }
+    }
+    let clocked = regs.iter().any(|reg| *reg)
+        || netlist
+            .blocks
+            .iter()
+            .any(|block| block.combinational.contains(&false));
+
+    let mut modules = Vec::new();
+    let mut body = Vec::new();
+    for (id, reg) in regs.iter().enumerate().skip(2) {
+        body.push(if *reg {
+            format!("    reg n{} = 1'b0;", id)
+        } else {
+            format!("    wire n{};", id)
+        });
+    }
+    for pin in &netlist.inputs {
+        for (bit, id) in pin.nets.iter().enumerate() {
+            body.push(format!(
+                "    assign n{} = {};",
+                id,
+                bit_of(&pin.name, bit, pin.nets.len())
+            ));
+        }
+    }
+    let mut dffs = Vec::new();
+    for (idx, gate) in netlist.gates.iter().enumerate() {
+        match gate {
+            Gate::Nand { a, b, out } => body.push(format!(
+                "    assign n{} = ~({} & {});",
+                out,
+                net(*a),
+                net(*b)
+            )),
+            Gate::Dff { input, out } => dffs.push(format!("        n{} <= {};", out, net(*input))),
+            Gate::Builtin(block) => {
+                let block = &netlist.blocks[*block];
+                let def = builtin::chip(&block.chip).unwrap();
+                if !modules.contains(&block.chip) {
+                    modules.push(block.chip.to_string());
+                }
+                let mut ports = Vec::new();
+                if !def.clocked.is_empty() {
+                    ports.push(".clk(clk)".to_string());
+                }
+                for (decl, pins) in def
+                    .inputs
+                    .iter()
+                    .chain(&def.outputs)
+                    .zip(block.inputs.iter().chain(&block.outputs))
+                {
+                    ports.push(format!(".{}({})", ident(&decl.name), nets(pins)));
+                }
+                body.push(format!(
+                    "    {} block{} ({});",
+                    ident(&block.chip),
+                    idx,
+                    ports.join(", ")
+                ));
+            }
+        }
+    }
+    if !dffs.is_empty() {
+        body.push("    always @(posedge clk) begin".to_string());
+        body.append(&mut dffs);
+        body.push("    end".to_string());
+    }
+    for pin in &netlist.outputs {
+        for (bit, id) in pin.nets.iter().enumerate() {
+            body.push(format!(
+                "    assign {} = {};",
+                bit_of(&pin.name, bit, pin.nets.len()),
+                net(*id)
+            ));
+        }
+    }
+
+    let mut text = String::new();
+    for name in modules {
+        text.push_str(&builtin_module(&builtin::chip(&name).unwrap()));
+        text.push('\n');
+    }
+    text.push_str(&header(&netlist.chip, clocked));
+    for line in body {
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text.push_str("endmodule\n");
+    text
+}
+
+struct Hierarchy<'a> {
+    library: &'a mut Library,
+    // modules written so far and whether they take a clock
+    clocked: HashMap<String, bool>,
+    text: String,
+}
+
+impl Hierarchy<'_> {
+    // writes the chip's module after the modules of its parts
+    fn module(&mut self, chip: &Rc<ChipDef>) -> bool {
+        if let Some(clocked) = self.clocked.get(&chip.name) {
+            return *clocked;
+        }
+        if chip.builtin.is_some() {
+            let clocked = !chip.clocked.is_empty();
+            self.text.push_str(&builtin_module(chip));
+            self.text.push('\n');
+            self.clocked.insert(chip.name.to_string(), clocked);
+            return clocked;
+        }
+
+        let defs: Vec<Rc<ChipDef>> = chip
+            .parts
+            .iter()
+            .map(|part| self.library.chip(&part.chip).unwrap())
+            .collect();
+        let parts_clocked: Vec<bool> = defs.iter().map(|def| self.module(def)).collect();
+        let clocked = parts_clocked.contains(&true);
+
+        // internal pins take the width of the part output that drives them
+        let mut internal: Vec<PinDecl> = Vec::new();
+        for (part, def) in chip.parts.iter().zip(&defs) {
+            for connection in &part.connections {
+                let decl = match def.output(&connection.pin.name) {
+                    Some(decl) => decl,
+                    None => continue,
+                };
+                if let Signal::Pin(target) = &connection.signal {
+                    let known = chip.pin(&target.name).is_some()
+                        || internal.iter().any(|pin| pin.name == target.name);
+                    if !known {
+                        let (lo, hi) = connection.pin.range(decl.width);
+                        internal.push(PinDecl {
+                            name: target.name.to_string(),
+                            width: hi - lo + 1,
+                            line: connection.line,
+                        });
+                    }
+                }
+            }
+        }
+        let width = |name: &str| {
+            chip.pin(name)
+                .or_else(|| internal.iter().find(|pin| pin.name == name))
+                .map(|pin| pin.width)
+                .unwrap()
+        };
+
+        let mut body = Vec::new();
+        for pin in &internal {
+            body.push(format!(
+                "    wire {}{};",
+                range(pin.width),
+                ident(&pin.name)
+            ));
+        }
+        for (idx, ((part, def), part_clocked)) in
+            chip.parts.iter().zip(&defs).zip(parts_clocked).enumerate()
+        {
+            let mut ports = Vec::new();
+            // the part's outputs, assigned to the pins they drive
+            let mut assigns = Vec::new();
+            if part_clocked {
+                ports.push(".clk(clk)".to_string());
+            }
+            for decl in &def.inputs {
+                // unconnected input bits are false
+                let mut bits = vec!["1'b0".to_string(); decl.width];
+                for connection in part.connections.iter().filter(|c| c.pin.name == decl.name) {
+                    let (lo, hi) = connection.pin.range(decl.width);
+                    for (bit, slot) in bits[lo..=hi].iter_mut().enumerate() {
+                        *slot = match &connection.signal {
+                            Signal::Const(value) => format!("1'b{}", *value as u8),
+                            Signal::Pin(signal) => {
+                                let signal_width = width(&signal.name);
+                                let (s_lo, _) = signal.range(signal_width);
+                                bit_of(&signal.name, s_lo + bit, signal_width)
+                            }
+                        };
+                    }
+                }
+                bits.reverse();
+                ports.push(format!(".{}({})", ident(&decl.name), concat(&bits, &width)));
+            }
+            for decl in &def.outputs {
+                let connections: Vec<_> = part
+                    .connections
+                    .iter()
+                    .filter(|c| c.pin.name == decl.name)
+                    .collect();
+                if connections.is_empty() {
+                    continue;
+                }
+                let wire = format!("part{}_{}", idx, decl.name);
+                body.push(format!("    wire {}{};", range(decl.width), wire));
+                ports.push(format!(".{}({})", ident(&decl.name), wire));
+                for connection in connections {
+                    let target = match &connection.signal {
+                        Signal::Pin(target) => target,
+                        Signal::Const(_) => continue,
+                    };
+                    let (lo, hi) = connection.pin.range(decl.width);
+                    let target_width = width(&target.name);
+                    let (t_lo, t_hi) = target.range(target_width);
+                    assigns.push(format!(
+                        "    assign {} = {};",
+                        slice(&target.name, t_lo, t_hi, target_width),
+                        slice(&wire, lo, hi, decl.width)
+                    ));
+                }
+            }
+            body.push(format!(
+                "    {} part{} ({});",
+                ident(&def.name),
+                idx,
+                ports.join(", ")
+            ));
+            body.append(&mut assigns);
+        }
+
+        self.text.push_str(&header(chip, clocked));
+        for line in body {
+            self.text.push_str(&line);
+            self.text.push('\n');
+        }
+        self.text.push_str("endmodule\n\n");
+        self.clocked.insert(chip.name.to_string(), clocked);
+        clocked
+    }
+}
+
+// `module Name (input clk, input [15:0] in, ..., output out);`
+fn header(chip: &ChipDef, clocked: bool) -> String {
+    let mut ports = Vec::new();
+    if clocked {
+        ports.push("    input clk".to_string());
+    }
+    for pin in &chip.inputs {
+        ports.push(format!(
+            "    input {}{}",
+            range(pin.width),
+            ident(&pin.name)
+        ));
+    }
+    for pin in &chip.outputs {
+        ports.push(format!(
+            "    output {}{}",
+            range(pin.width),
+            ident(&pin.name)
+        ));
+    }
+    format!(
+        "module {} (\n{}\n);\n",
+        ident(&chip.name),
+        ports.join(",\n")
+    )
+}
+
+// behaviour of a built-in chip, under the name the chip has in its .hdl file
+fn builtin_module(chip: &ChipDef) -> String {
+    let behaviour = match chip.builtin.as_deref().unwrap_or("") {
+        "Nand" => "    assign out = ~(a & b);\n".to_string(),
+        "DFF" => "    reg state = 1'b0;\n    always @(posedge clk) state <= in;\n    assign out = state;\n".to_string(),
+        "Register" | "ARegister" | "DRegister" => "    reg [15:0] state = 16'b0;\n    always @(posedge clk) if (load) state <= in;\n    assign out = state;\n".to_string(),
+        // .hack files are what $readmemb reads
+        "ROM32K" => "    parameter PROGRAM = \"program.hack\";\n    reg [15:0] memory [0:32767];\n    initial $readmemb(PROGRAM, memory);\n    assign out = memory[address];\n".to_string(),
+        // set from a test bench as <instance>.key
+        "Keyboard" => "    reg [15:0] key = 16'b0;\n    assign out = key;\n".to_string(),
+        name => {
+            let words = builtin::Builtin::new(name).memory.len();
+            format!(
+                "    reg [15:0] memory [0:{}];\n    always @(posedge clk) if (load) memory[address] <= in;\n    assign out = memory[address];\n",
+                words - 1
+            )
+        }
+    };
+    format!(
+        "{}{}endmodule\n",
+        header(chip, !chip.clocked.is_empty()),
+        behaviour
+    )
+}
+
+// `{a[3:0], 2'b01, b}` from bit expressions given highest bit first, with
+// runs of one pin and of constants merged; `width` gives the width of a pin
+fn concat(bits: &[String], width: &dyn Fn(&str) -> usize) -> String {
+    enum Run {
+        Bits(String, usize, usize),
+        Const(String),
+        Other(String),
+    }
+    let mut runs: Vec<Run> = Vec::new();
+    for bit in bits {
+        let indexed = bit.strip_suffix(']').and_then(|bit| {
+            let (name, index) = bit.rsplit_once('[')?;
+            Some((name, index.parse::<usize>().ok()?))
+        });
+        let constant = bit.strip_prefix("1'b");
+        match (indexed, constant, runs.last_mut()) {
+            (Some((name, index)), _, Some(Run::Bits(last, _, lo)))
+                if last == name && *lo == index + 1 =>
+            {
+                *lo = index
+            }
+            (Some((name, index)), _, _) => runs.push(Run::Bits(name.to_string(), index, index)),
+            (_, Some(value), Some(Run::Const(values))) => values.push_str(value),
+            (_, Some(value), _) => runs.push(Run::Const(value.to_string())),
+            _ => runs.push(Run::Other(bit.to_string())),
+        }
+    }
+    let parts: Vec<String> = runs
+        .into_iter()
+        .map(|run| match run {
+            Run::Bits(name, hi, lo) if lo == 0 && hi + 1 == width(unescape(&name)) => name,
+            Run::Bits(name, hi, lo) if hi == lo => format!("{}[{}]", name, hi),
+            Run::Bits(name, hi, lo) => format!("{}[{}:{}]", name, hi, lo),
+            Run::Const(values) => format!("{}'b{}", values.len(), values),
+            Run::Other(bit) => bit,
+        })
+        .collect();
+    match parts.as_slice() {
+        [part] => part.to_string(),
+        _ => format!("{{{}}}", parts.join(", ")),
+    }
+}
+
+// bits lo to hi of a pin `width` bits wide
+fn slice(name: &str, lo: usize, hi: usize, width: usize) -> String {
+    let name = ident(name);
+    if lo == 0 && hi + 1 == width {
+        name
+    } else if lo == hi {
+        format!("{}[{}]", name, lo)
+    } else {
+        format!("{}[{}:{}]", name, hi, lo)
+    }
+}
+
+fn bit_of(name: &str, bit: usize, width: usize) -> String {
+    slice(name, bit, bit, width)
+}
+
+fn range(width: usize) -> String {
+    if width == 1 {
+        String::new()
+    } else {
+        format!("[{}:0] ", width - 1)
+    }
+}
+
+fn unescape(name: &str) -> &str {
+    name.trim_start_matches('\\').trim_end()
+}
+
+// HDL names that are Verilog keywords are escaped
+fn ident(name: &str) -> String {
+    const KEYWORDS: [&str; 24] = [
+        "always", "and", "assign", "begin", "buf", "case", "else", "end", "for", "if", "initial",
+        "inout", "input", "integer", "module", "nand", "nor", "not", "or", "output", "reg", "wire",
+        "xnor", "xor",
+    ];
+    if KEYWORDS.contains(&name) {
+        format!("\\{} ", name)
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn and() -> (Library, Rc<ChipDef>) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../01/And.hdl");
+        let mut library = Library::for_file(&path);
+        let chip = library.load(&path).unwrap();
+        (library, chip)
+    }
+
+    #[test]
+    fn and_hierarchical() {
+        let (mut library, chip) = and();
+        assert_eq!(
+            hierarchical(&mut library, &chip),
+            "module Nand (
+    input a,
+    input b,
+    output out
+);
+    assign out = ~(a & b);
+endmodule
+
+module Not (
+    input in,
+    output out
+);
+    wire part0_out;
+    Nand part0 (.a(1'b1), .b(in), .out(part0_out));
+    assign out = part0_out;
+endmodule
+
+module And (
+    input a,
+    input b,
+    output out
+);
+    wire x;
+    wire part0_out;
+    Nand part0 (.a(a), .b(b), .out(part0_out));
+    assign x = part0_out;
+    wire part1_out;
+    Not part1 (.in(x), .out(part1_out));
+    assign out = part1_out;
+endmodule
+
+"
+        );
+    }
+
+    #[test]
+    fn and_flattened() {
+        let (mut library, chip) = and();
+        assert_eq!(
+            flattened(&Netlist::new(&mut library, &chip)),
+            "module And (
+    input a,
+    input b,
+    output out
+);
+    wire n2;
+    wire n3;
+    wire n4;
+    wire n5;
+    assign n2 = a;
+    assign n3 = b;
+    assign n4 = ~(n2 & n3);
+    assign n5 = ~(1'b1 & n4);
+    assign out = n5;
+endmodule
+"
+        );
+    }
+}
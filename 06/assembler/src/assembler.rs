@@ -64,6 +64,41 @@ impl Assembler {
         }
     }
 
+    // address of a label, variable or predefined symbol
+    pub fn symbol(&self, s: &str) -> Option<usize> {
+        if self.symbol_table.contains(s) {
+            Some(self.symbol_table.get_address(s))
+        } else {
+            None
+        }
+    }
+
+    // (label, ROM address) in source order
+    pub fn labels(&self) -> Vec<(String, usize)> {
+        let mut labels = Vec::new();
+        let mut count = 0;
+        for line in &self.lines.lines {
+            match line {
+                Line::ACommand(_) | Line::CCommand(_) => count += 1,
+                Line::LCommand(s) => labels.push((s.to_string(), count)),
+                Line::NotCommand => {}
+            }
+        }
+        labels
+    }
+
+    // .asm line number (from 1) of every ROM word
+    pub fn source_lines(&self) -> Vec<usize> {
+        let mut source_lines = Vec::new();
+        for (row, line) in self.lines.lines.iter().enumerate() {
+            match line {
+                Line::ACommand(_) | Line::CCommand(_) => source_lines.push(row + 1),
+                _ => {}
+            }
+        }
+        source_lines
+    }
+
     pub fn to_binary(&self) -> String {
         let mut output = String::new();
         let bins = self.lines.to_binary(&self.symbol_table);
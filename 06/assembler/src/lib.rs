@@ -0,0 +1,4 @@
+pub mod assembler;
+mod code;
+mod parser;
+mod symbol_table;
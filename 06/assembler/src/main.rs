@@ -1,11 +1,6 @@
-mod parser;
-mod code;
-mod symbol_table;
-mod assembler;
-
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use crate::assembler::Assembler;
+use assembler::assembler::Assembler;
 
 fn main() {
     let input_file_pass = "../files/";
@@ -0,0 +1,162 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::parser::CommandType;
+
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    calls: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl CallGraph {
+    pub fn new() -> Self {
+        CallGraph {
+            calls: BTreeMap::new(),
+        }
+    }
+
+    // commands of one vm file; calls belong to the function declared above them
+    pub fn add_file(&mut self, commands: &[CommandType]) {
+        let mut current: Option<&String> = None;
+        for command in commands {
+            match command {
+                CommandType::CFunction(f, _) => {
+                    self.calls.entry(f.to_string()).or_default();
+                    current = Some(f);
+                }
+                CommandType::CCall(f, _) => {
+                    if let Some(caller) = current {
+                        self.calls.get_mut(caller).unwrap().insert(f.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn contains(&self, f: &str) -> bool {
+        self.calls.contains_key(f)
+    }
+
+    pub fn reachable(&self, root: &str) -> BTreeSet<String> {
+        let mut reachable = BTreeSet::new();
+        let mut stack = vec![root.to_string()];
+        while let Some(f) = stack.pop() {
+            if reachable.contains(&f) {
+                continue;
+            }
+            if let Some(callees) = self.calls.get(&f) {
+                stack.extend(callees.iter().cloned());
+            }
+            reachable.insert(f);
+        }
+        reachable
+    }
+
+    // defined functions that can never run when starting from `root`
+    pub fn unreachable(&self, root: &str) -> BTreeSet<String> {
+        let reachable = self.reachable(root);
+        self.calls
+            .keys()
+            .filter(|f| !reachable.contains(*f))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(class_name: &str, text: &str) -> Vec<CommandType> {
+        text.lines()
+            .map(|line| CommandType::new(class_name.to_string(), line.split_whitespace().collect()))
+            .collect()
+    }
+
+    // Sys.init -> Main.main -> Math.multiply -> Math.abs, and Main.main
+    // calling itself; Main.unused and the Math.divide only it calls are
+    // never reached
+    fn program() -> CallGraph {
+        let mut call_graph = CallGraph::new();
+        call_graph.add_file(&commands(
+            "Sys",
+            "function Sys.init 0
+             call Main.main 0
+             label HALT
+             goto HALT",
+        ));
+        call_graph.add_file(&commands(
+            "Main",
+            "function Main.main 0
+             push constant 6
+             push constant 7
+             call Math.multiply 2
+             call Main.main 0
+             return
+             function Main.unused 0
+             call Math.divide 2
+             return",
+        ));
+        call_graph.add_file(&commands(
+            "Math",
+            "function Math.multiply 0
+             call Math.abs 1
+             return
+             function Math.divide 0
+             return
+             function Math.abs 0
+             return",
+        ));
+        call_graph
+    }
+
+    fn names(functions: &[&str]) -> BTreeSet<String> {
+        functions.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn reachable_from_sys_init() {
+        let call_graph = program();
+        assert!(call_graph.contains("Main.unused"));
+        assert!(!call_graph.contains("Output.printInt"));
+        assert_eq!(
+            call_graph.reachable("Sys.init"),
+            names(&["Main.main", "Math.abs", "Math.multiply", "Sys.init"])
+        );
+    }
+
+    #[test]
+    fn uncalled_functions_are_unreachable() {
+        assert_eq!(
+            program().unreachable("Sys.init"),
+            names(&["Main.unused", "Math.divide"])
+        );
+    }
+
+    #[test]
+    fn indirect_only_callees_are_kept() {
+        // nothing calls Math.abs from Sys.init or Main.main directly
+        let call_graph = program();
+        assert!(!call_graph.calls["Main.main"].contains("Math.abs"));
+        assert!(!call_graph.unreachable("Sys.init").contains("Math.abs"));
+    }
+
+    #[test]
+    fn calls_outside_functions_and_to_undefined_functions() {
+        let mut call_graph = CallGraph::new();
+        call_graph.add_file(&commands(
+            "Test",
+            "call Test.f 0
+             function Test.f 0
+             call Output.printInt 1
+             return",
+        ));
+        // the top-level call has no caller, the OS function no definition
+        assert_eq!(
+            call_graph.reachable("Test.f"),
+            names(&["Output.printInt", "Test.f"])
+        );
+        assert!(!call_graph.contains("Output.printInt"));
+        assert_eq!(call_graph.unreachable("Sys.init"), names(&["Test.f"]));
+    }
+}
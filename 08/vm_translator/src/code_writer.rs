@@ -1,6 +1,6 @@
 use std::{
-    fs::File,
-    io::{BufWriter, Write},
+    collections::BTreeSet,
+    io::{self, Write},
 };
 
 use crate::parser::{Arithmetic, CommandType};
@@ -17,112 +17,357 @@ macro_rules! push {
     };
 }
 
-pub struct CodeWriter {
-    file_name: String,
-    commands: Vec<String>,
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: usize,
+    pub command: String,
 }
 
-impl CodeWriter {
-    pub fn new(path: &str) -> Self {
+pub struct CodeWriter<W: Write> {
+    out: W,
+    chunk_count: usize,
+    asm_line: usize,
+    rom_words: usize,
+    line_map: Vec<String>,
+    source: Option<SourceLine>,
+    annotate: bool,
+    extended: bool,
+    routines: BTreeSet<Routine>,
+    // labels are scoped to the function they appear in, as `f$label`,
+    // or to the file before its first function
+    file_name: Option<String>,
+    function: Option<String>,
+}
+
+impl<W: Write> CodeWriter<W> {
+    // asm is streamed into `out` as commands are written
+    pub fn new(out: W) -> Self {
         CodeWriter {
-            file_name: path.to_string(),
-            commands: Vec::new(),
+            out,
+            chunk_count: 0,
+            asm_line: 1,
+            rom_words: 0,
+            line_map: Vec::new(),
+            source: None,
+            annotate: false,
+            extended: false,
+            routines: BTreeSet::new(),
+            file_name: None,
+            function: None,
         }
     }
 
-    pub fn write_init(&mut self) {
-        self.commands.push("@256\nD=A\n@SP\nM=D".to_string());
-        self.write_call("Sys.init".to_string(), 0);
+    // accept mul, div, shl, shr, lte, gte and neq
+    pub fn set_extended(&mut self, extended: bool) {
+        self.extended = extended;
+    }
+
+    // prefix each chunk with its vm source and record the vm-to-asm line map
+    pub fn set_annotate(&mut self, annotate: bool) {
+        self.annotate = annotate;
+    }
+
+    // vm command the following chunks are generated from
+    pub fn set_source(&mut self, file: &str, line: usize, command: &str) {
+        self.source = Some(SourceLine {
+            file: file.to_string(),
+            line,
+            command: command.to_string(),
+        });
+    }
+
+    // the commands that follow come from `file_name`, outside any function
+    pub fn set_file_name(&mut self, file_name: &str) {
+        let name = file_name.strip_suffix(".vm").unwrap_or(file_name);
+        self.file_name = Some(name.to_string());
+        self.function = None;
+    }
+
+    pub fn write_init(&mut self) -> io::Result<()> {
+        self.source = None;
+        self.emit("@256\nD=A\n@SP\nM=D".to_string())?;
+        self.write_call("Sys.init".to_string(), 0)
     }
 
-    pub fn write_arithmetic(&mut self, arithmetic: Arithmetic) {
-        let n = self.commands.len();
+    pub fn write_command(&mut self, command: CommandType) -> io::Result<()> {
+        match command {
+            CommandType::CArithmetic(arithmetic) => self.write_arithmetic(arithmetic),
+
+            CommandType::CPush(_, _, _) => self.write_push_pop(command),
+            CommandType::CPop(_, _, _) => self.write_push_pop(command),
+
+            CommandType::CLabel(label) => self.write_label(label),
+            CommandType::CGoto(label) => self.write_goto(label),
+            CommandType::CIf(label) => self.write_if(label),
+
+            CommandType::CFunction(f, n) => self.write_function(f, n),
+            CommandType::CReturn => self.write_return(),
+            CommandType::CCall(f, n) => self.write_call(f, n),
+
+            CommandType::NotCommand => Ok(()),
+        }
+    }
+
+    pub fn write_arithmetic(&mut self, arithmetic: Arithmetic) -> io::Result<()> {
+        if arithmetic.is_extended() && !self.extended {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} needs the extended instruction set", arithmetic),
+            ));
+        }
+        let n = self.chunk_count;
         match arithmetic {
-            Arithmetic::Add => self.commands.push(binary_function("D=D+M")),
-            Arithmetic::Sub => self.commands.push(binary_function("D=M-D")),
-            Arithmetic::And => self.commands.push(binary_function("D=D&M")),
-            Arithmetic::Or => self.commands.push(binary_function("D=D|M")),
-            Arithmetic::Not => self.commands.push(unary_function("M=!M")),
-            Arithmetic::Neg => self.commands.push(unary_function("M=-M")),
-            Arithmetic::Eq => self.commands.push(logic_command("D;JEQ", n)),
-            Arithmetic::Lt => self.commands.push(logic_command("D;JLT", n)),
-            Arithmetic::Gt => self.commands.push(logic_command("D;JGT", n)),
+            Arithmetic::Add => self.emit(binary_function("D=D+M")),
+            Arithmetic::Sub => self.emit(binary_function("D=M-D")),
+            Arithmetic::And => self.emit(binary_function("D=D&M")),
+            Arithmetic::Or => self.emit(binary_function("D=D|M")),
+            Arithmetic::Not => self.emit(unary_function("M=!M")),
+            Arithmetic::Neg => self.emit(unary_function("M=-M")),
+            Arithmetic::Eq => self.emit(logic_command("D;JEQ", n)),
+            // sign-safe only when extended; it takes about twice the instructions
+            Arithmetic::Lt if self.extended => self.emit(compare_command("D;JLT", n)),
+            Arithmetic::Gt if self.extended => self.emit(compare_command("D;JGT", n)),
+            Arithmetic::Lt => self.emit(logic_command("D;JLT", n)),
+            Arithmetic::Gt => self.emit(logic_command("D;JGT", n)),
+            Arithmetic::Lte => self.emit(compare_command("D;JLE", n)),
+            Arithmetic::Gte => self.emit(compare_command("D;JGE", n)),
+            Arithmetic::Neq => self.emit(logic_command("D;JNE", n)),
+            Arithmetic::Mul => self.write_routine_call(Routine::Mul),
+            Arithmetic::Div => self.write_routine_call(Routine::Div),
+            Arithmetic::Shl => self.write_routine_call(Routine::Shl),
+            Arithmetic::Shr => self.write_routine_call(Routine::Shr),
         }
     }
 
-    pub fn write_push_pop(&mut self, command: CommandType) {
+    pub fn write_push_pop(&mut self, command: CommandType) -> io::Result<()> {
         match command {
             CommandType::CPush(class_name, segment, index) => {
                 let seg: &str = &segment;
                 match seg {
-                    "constant" => self.commands.push(push("constant", index)),
-                    "argument" => self.commands.push(push("@ARG", index)),
-                    "local" => self.commands.push(push("@LCL", index)),
-                    "that" => self.commands.push(push("@THAT", index)),
-                    "this" => self.commands.push(push("@THIS", index)),
-                    "temp" => self.commands.push(push("@TEMP", index)),
-                    "pointer" => self.commands.push(push("pointer", index + 3)),
-                    "static" => self.commands.push(push_static(class_name, index)),
-                    _ => {}
+                    "constant" => self.emit(push("constant", index)),
+                    "argument" => self.emit(push("@ARG", index)),
+                    "local" => self.emit(push("@LCL", index)),
+                    "that" => self.emit(push("@THAT", index)),
+                    "this" => self.emit(push("@THIS", index)),
+                    "temp" => self.emit(push("@TEMP", index)),
+                    "pointer" => self.emit(push("pointer", index + 3)),
+                    "static" => self.emit(push_static(class_name, index)),
+                    _ => Ok(()),
                 }
             }
             CommandType::CPop(class_name, segment, index) => {
                 let seg: &str = &segment;
                 match seg {
-                    "local" => self.commands.push(pop("@LCL", index)),
-                    "argument" => self.commands.push(pop("@ARG", index)),
-                    "this" => self.commands.push(pop("@THIS", index)),
-                    "that" => self.commands.push(pop("@THAT", index)),
-                    "temp" => self.commands.push(pop("@TEMP", index)),
-                    "pointer" => self.commands.push(pop("pointer", index + 3)),
-                    "static" => self.commands.push(pop_static(class_name, index)),
-                    _ => {}
+                    "local" => self.emit(pop("@LCL", index)),
+                    "argument" => self.emit(pop("@ARG", index)),
+                    "this" => self.emit(pop("@THIS", index)),
+                    "that" => self.emit(pop("@THAT", index)),
+                    "temp" => self.emit(pop("@TEMP", index)),
+                    "pointer" => self.emit(pop("pointer", index + 3)),
+                    "static" => self.emit(pop_static(class_name, index)),
+                    _ => Ok(()),
                 }
             }
-            _ => {}
+            _ => Ok(()),
         }
     }
 
-    pub fn write_call(&mut self, f: String, n: usize) {
-        self.commands.push(call(&f, n, self.commands.len()));
+    pub fn write_call(&mut self, f: String, n: usize) -> io::Result<()> {
+        self.emit(call(&f, n, self.chunk_count))
     }
 
-    pub fn write_function(&mut self, f: String, n: usize) {
-        self.commands.push(function(f, n))
+    pub fn write_function(&mut self, f: String, n: usize) -> io::Result<()> {
+        self.function = Some(f.clone());
+        self.emit(function(f, n))
     }
 
-    pub fn write_return(&mut self) {
-        self.commands.push(function_return());
+    pub fn write_return(&mut self) -> io::Result<()> {
+        self.emit(function_return())
     }
 
-    pub fn write_label(&mut self, label: String) {
-        let label = format!("({})", label);
-        self.commands.push(label);
+    pub fn write_label(&mut self, label: String) -> io::Result<()> {
+        let label = format!("({})", self.scoped(label));
+        self.emit(label)
     }
 
-    pub fn write_goto(&mut self, label: String) {
-        let command = format!("@{}\n0;JMP", label);
-        self.commands.push(command);
+    pub fn write_goto(&mut self, label: String) -> io::Result<()> {
+        let command = format!("@{}\n0;JMP", self.scoped(label));
+        self.emit(command)
     }
 
-    pub fn write_if(&mut self, label: String) {
-        let label = format!("@{}", label);
+    pub fn write_if(&mut self, label: String) -> io::Result<()> {
+        let label = format!("@{}", self.scoped(label));
         let commands: Vec<&str> = vec![pop!(), "D=M", &label, "D;JNE"];
         let commands = commands.join("\n");
-        self.commands.push(commands);
+        self.emit(commands)
+    }
+
+    // the same label in two functions must not become the same asm label
+    fn scoped(&self, label: String) -> String {
+        match (&self.function, &self.file_name) {
+            (Some(scope), _) | (None, Some(scope)) => format!("{}${}", scope, label),
+            (None, None) => label,
+        }
+    }
+
+    // x y -> routine(x, y); the routine itself is written once by close()
+    fn write_routine_call(&mut self, routine: Routine) -> io::Result<()> {
+        self.routines.insert(routine);
+        self.emit(routine_call(routine, self.chunk_count))
+    }
+
+    // instructions written so far; labels and comments take no ROM space
+    pub fn rom_words(&self) -> usize {
+        self.rom_words
+    }
+
+    // append the shared routines, flush the asm and hand back the sink
+    pub fn close(mut self) -> io::Result<W> {
+        self.source = None;
+        for routine in std::mem::take(&mut self.routines) {
+            self.emit(routine.asm().to_string())?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    // [{"asm_start":1,"asm_end":7,"file":"Main.vm","line":3,"command":"push local 2"}, ...]
+    // asm lines are 1-based and inclusive
+    pub fn line_map(&self) -> String {
+        format!("[\n{}\n]\n", self.line_map.join(",\n"))
+    }
+
+    fn emit(&mut self, chunk: String) -> io::Result<()> {
+        let chunk = match (&self.source, self.annotate) {
+            (Some(source), true) => format!(
+                "// {}:{}  {}\n{}",
+                source.file, source.line, source.command, chunk
+            ),
+            _ => chunk,
+        };
+        let lines = chunk.lines().count();
+        if let (Some(source), true) = (&self.source, self.annotate) {
+            self.line_map.push(format!(
+                "  {{\"asm_start\":{},\"asm_end\":{},\"file\":\"{}\",\"line\":{},\"command\":\"{}\"}}",
+                self.asm_line,
+                self.asm_line + lines - 1,
+                json_escape(&source.file),
+                source.line,
+                json_escape(&source.command),
+            ));
+        }
+        writeln!(self.out, "{}", chunk)?;
+        self.chunk_count += 1;
+        self.asm_line += lines;
+        self.rom_words += chunk
+            .lines()
+            .map(|line| line.split("//").next().unwrap().trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('('))
+            .count();
+        Ok(())
+    }
+}
+
+// Hack routines shared by every use of an extended command.
+// Operands are passed in R14 (x) and R13 (y), the return address in R15,
+// and the result comes back in D.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Routine {
+    Mul,
+    Div,
+    Shl,
+    Shr,
+}
+impl Routine {
+    fn label(&self) -> &'static str {
+        match self {
+            Routine::Mul => "$vm.mul",
+            Routine::Div => "$vm.div",
+            Routine::Shl => "$vm.shl",
+            Routine::Shr => "$vm.shr",
+        }
     }
 
-    pub fn close(&self) {
-        let mut output = String::new();
-        for line in &self.commands {
-            output = output + &line + &"\n";
+    fn asm(&self) -> &'static str {
+        match self {
+            // shift-and-add over the 16 bits of y
+            Routine::Mul => concat!(
+                "($vm.mul)\n@$vm.result\nM=0\n@$vm.mask\nM=1\n",
+                "($vm.mul.loop)\n@$vm.mask\nD=M\n@R13\nD=D&M\n@$vm.mul.skip\nD;JEQ\n",
+                "@R14\nD=M\n@$vm.result\nM=D+M\n",
+                "($vm.mul.skip)\n@R14\nD=M\nM=D+M\n@$vm.mask\nD=M\nM=D+M\nD=M\n",
+                "@$vm.mul.loop\nD;JNE\n",
+                "@$vm.result\nD=M\n@R15\nA=M\n0;JMP",
+            ),
+            // long division of |x| by |y|, rounded toward zero like Math.divide
+            Routine::Div => concat!(
+                "($vm.div)\n@$vm.sign\nM=0\n",
+                "@R14\nD=M\n@$vm.div.xpos\nD;JGE\n@R14\nM=-M\n@$vm.sign\nM=!M\n",
+                "($vm.div.xpos)\n@R13\nD=M\n@$vm.div.ypos\nD;JGE\n@R13\nM=-M\n@$vm.sign\nM=!M\n",
+                "($vm.div.ypos)\n@$vm.result\nM=0\n@$vm.rem\nM=0\n@16\nD=A\n@$vm.count\nM=D\n",
+                "($vm.div.loop)\n@$vm.rem\nD=M\nM=D+M\n",
+                "@R14\nD=M\n@$vm.div.nobit\nD;JGE\n@$vm.rem\nM=M+1\n",
+                "($vm.div.nobit)\n@R14\nD=M\nM=D+M\n@$vm.result\nD=M\nM=D+M\n",
+                "@$vm.rem\nD=M\n@$vm.div.sub\nD;JLT\n@R13\nD=D-M\n@$vm.div.next\nD;JLT\n",
+                "($vm.div.sub)\n@R13\nD=M\n@$vm.rem\nM=M-D\n@$vm.result\nM=M+1\n",
+                "($vm.div.next)\n@$vm.count\nMD=M-1\n@$vm.div.loop\nD;JGT\n",
+                "@$vm.sign\nD=M\n@$vm.div.end\nD;JEQ\n@$vm.result\nM=-M\n",
+                "($vm.div.end)\n@$vm.result\nD=M\n@R15\nA=M\n0;JMP",
+            ),
+            // x doubled y times, stopping once it is 0; y <= 0 leaves x
+            Routine::Shl => concat!(
+                "($vm.shl)\n@R14\nD=M\n@$vm.result\nM=D\n",
+                "($vm.shl.loop)\n@R13\nD=M\n@$vm.shl.end\nD;JLE\n@R13\nM=D-1\n",
+                "@$vm.result\nD=M\nMD=D+M\n@$vm.shl.end\nD;JEQ\n@$vm.shl.loop\n0;JMP\n",
+                "($vm.shl.end)\n@$vm.result\nD=M\n@R15\nA=M\n0;JMP",
+            ),
+            // arithmetic shift: bit i+y of x moves to bit i, the sign fills the top
+            Routine::Shr => concat!(
+                "($vm.shr)\n@$vm.mask\nM=1\n",
+                "($vm.shr.pow)\n@R13\nD=M\n@$vm.shr.bits\nD;JLE\n@R13\nM=D-1\n",
+                "@$vm.mask\nD=M\nMD=D+M\n@$vm.shr.bits\nD;JEQ\n@$vm.shr.pow\n0;JMP\n",
+                "($vm.shr.bits)\n@$vm.result\nM=0\n@$vm.bit\nM=1\n",
+                "($vm.shr.loop)\n@$vm.mask\nD=M\n@$vm.shr.sign\nD;JEQ\n@R14\nD=D&M\n@$vm.shr.test\n0;JMP\n",
+                "($vm.shr.sign)\n@R14\nD=M\n@$vm.shr.next\nD;JGE\nD=-1\n",
+                "($vm.shr.test)\n@$vm.shr.next\nD;JEQ\n@$vm.bit\nD=M\n@$vm.result\nM=D|M\n",
+                "($vm.shr.next)\n@$vm.mask\nD=M\nM=D+M\n@$vm.bit\nD=M\nMD=D+M\n",
+                "@$vm.shr.loop\nD;JNE\n",
+                "@$vm.result\nD=M\n@R15\nA=M\n0;JMP",
+            ),
         }
-        let mut file = BufWriter::new(File::create(&self.file_name).unwrap());
-        write!(file, "{}", output).unwrap();
-        file.flush().unwrap();
     }
 }
 
+fn routine_call(routine: Routine, n: usize) -> String {
+    let routine_a = format!("@{}", routine.label());
+    let return_a = format!("@$vm.return.{}", n);
+    let return_l = format!("($vm.return.{})", n);
+    let commands: Vec<&str> = vec![
+        pop!(),
+        "D=M",
+        "@R13",
+        "M=D",
+        pop!(),
+        "D=M",
+        "@R14",
+        "M=D",
+        &return_a,
+        "D=A",
+        "@R15",
+        "M=D",
+        &routine_a,
+        "0;JMP",
+        &return_l,
+        push!(),
+    ];
+    commands.join("\n")
+}
+
+fn json_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
 fn binary_function(command: &str) -> String {
     let commands: Vec<&str> = vec![pop!(), "D=M", pop!(), command, push!()];
     commands.join("\n")
@@ -164,6 +409,71 @@ fn logic_command(dist: &str, n: usize) -> String {
     commands.join("\n")
 }
 
+// x - y overflows when the signs differ (-32768 lt 1), so D is then set to
+// -1 or 1 from the signs alone; x is still in RAM just above SP
+fn compare_command(dist: &str, n: usize) -> String {
+    let ax = format!("@XNEG.{}", n);
+    let lx = format!("(XNEG.{})", n);
+    let ad = format!("@DIFF.{}", n);
+    let ld = format!("(DIFF.{})", n);
+    let ac = format!("@CMP.{}", n);
+    let lc = format!("(CMP.{})", n);
+    let at = format!("@TRUE.{}", n);
+    let af = format!("@FALSE.{}", n);
+    let ae = format!("@END.{}", n);
+    let lt = format!("(TRUE.{})", n);
+    let lf = format!("(FALSE.{})", n);
+    let le = format!("(END.{})", n);
+
+    let commands: Vec<&str> = vec![
+        pop!(),
+        "D=M",
+        "@R13",
+        "M=D",
+        pop!(),
+        "D=M",
+        &ax,
+        "D;JLT",
+        "@R13",
+        "D=M",
+        &ad,
+        "D;JGE",
+        "D=1",
+        &ac,
+        "0;JMP",
+        &lx,
+        "@R13",
+        "D=M",
+        &ad,
+        "D;JLT",
+        "D=-1",
+        &ac,
+        "0;JMP",
+        &ld,
+        "@SP",
+        "A=M",
+        "D=M",
+        "@R13",
+        "D=D-M",
+        &lc,
+        &at,
+        dist,
+        &af,
+        "0;JMP",
+        &lt,
+        "@0",
+        "D=!A",
+        &ae,
+        "0;JMP",
+        &lf,
+        "@0",
+        "D=A",
+        &le,
+        push!(),
+    ];
+    commands.join("\n")
+}
+
 fn push(dist: &str, n: usize) -> String {
     match dist {
         "pointer" => {
@@ -178,7 +488,7 @@ fn push(dist: &str, n: usize) -> String {
         }
         _ => {
             let idx = format!("@{}", n);
-            let commands = vec![
+            let commands: Vec<&str> = vec![
                 dist,
                 if dist == "@TEMP" {
                     "@5\nD=A"
@@ -346,3 +656,214 @@ fn function_return() -> String {
     ];
     commands.join("\n")
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use hack_cpu::cpu::Cpu;
+
+    use super::*;
+    use crate::vm_emulator::VMEmulator;
+
+    const OPERANDS: [i16; 11] = [0, 1, 2, 3, 7, -1, -2, -7, 300, i16::MAX, i16::MIN];
+
+    // `x op y` in VMEmulator and translated on hack_cpu, with x and y
+    // already on the stack
+    fn run_both(op: Arithmetic, x: i16, y: i16) -> (i16, i16) {
+        let mut vm = VMEmulator::new();
+        vm.load(vec![CommandType::CArithmetic(op.clone())]);
+        for (address, value) in &[(0, 258), (256, x), (257, y)] {
+            vm.set_ram(*address, *value);
+        }
+        vm.run(1);
+
+        let mut code_writer = CodeWriter::new(Vec::new());
+        code_writer.set_extended(true);
+        code_writer.write_arithmetic(op.clone()).unwrap();
+        code_writer.write_label("HALT".to_string()).unwrap();
+        code_writer.write_goto("HALT".to_string()).unwrap();
+        let asm = code_writer.close().unwrap();
+        let asm_path = std::env::temp_dir().join(format!(
+            "code_writer_{:?}_{}_{}_{}.asm",
+            op,
+            x,
+            y,
+            std::process::id()
+        ));
+        fs::write(&asm_path, asm).unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_asm(asm_path.to_str().unwrap());
+        fs::remove_file(&asm_path).ok();
+        for (address, value) in &[(0, 258), (256, x), (257, y)] {
+            cpu.set_ram(*address, *value);
+        }
+        cpu.run(1_000_000);
+        assert!(cpu.is_halted(), "{:?} {} {} did not finish", op, x, y);
+        assert_eq!(cpu.ram(0), 257);
+
+        (vm.peek(), cpu.ram(256))
+    }
+
+    fn assert_agree(op: Arithmetic, operands: &[(i16, i16)]) {
+        for (x, y) in operands {
+            let (vm, cpu) = run_both(op.clone(), *x, *y);
+            assert_eq!(vm, cpu, "{:?} {} {}", op, x, y);
+        }
+    }
+
+    fn pairs() -> Vec<(i16, i16)> {
+        let mut pairs = Vec::new();
+        for x in &OPERANDS {
+            for y in &OPERANDS {
+                pairs.push((*x, *y));
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn mul_matches_the_emulator() {
+        assert_agree(Arithmetic::Mul, &pairs());
+    }
+
+    #[test]
+    fn div_matches_the_emulator() {
+        let pairs: Vec<(i16, i16)> = pairs().into_iter().filter(|(_, y)| *y != 0).collect();
+        assert_agree(Arithmetic::Div, &pairs);
+    }
+
+    #[test]
+    fn shifts_match_the_emulator() {
+        let counts = [0, 1, 5, 15, 16, 17, 100, i16::MAX, -1, i16::MIN];
+        let mut pairs = Vec::new();
+        for x in &OPERANDS {
+            for y in &counts {
+                pairs.push((*x, *y));
+            }
+        }
+        assert_agree(Arithmetic::Shl, &pairs);
+        assert_agree(Arithmetic::Shr, &pairs);
+    }
+
+    // accepts `budget` bytes, then fails every write
+    struct FailingWriter {
+        budget: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.budget == 0 {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only"));
+            }
+            let n = buf.len().min(self.budget);
+            self.budget -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_into_a_vec() {
+        let mut code_writer = CodeWriter::new(Vec::new());
+        code_writer
+            .write_command(CommandType::CPush(
+                "Main".to_string(),
+                "static".to_string(),
+                3,
+            ))
+            .unwrap();
+        code_writer.write_return().unwrap();
+        let words = code_writer.rom_words();
+        let asm = String::from_utf8(code_writer.close().unwrap()).unwrap();
+        // no labels, so every line is an instruction
+        assert_eq!(asm.lines().count(), words);
+        assert!(asm.starts_with("@Main.3\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n@LCL\n"));
+        assert!(asm.ends_with("@R14\nA=M\n0;JMP\n"));
+    }
+
+    #[test]
+    fn write_errors_are_returned() {
+        let mut code_writer = CodeWriter::new(FailingWriter { budget: 10 });
+        // nothing is buffered, so the first chunk already runs out
+        let error = code_writer
+            .write_command(CommandType::CArithmetic(Arithmetic::Add))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(error.to_string(), "read-only");
+        let error = code_writer.write_label("END".to_string()).unwrap_err();
+        assert_eq!(error.to_string(), "read-only");
+        assert!(code_writer.close().is_ok());
+    }
+
+    #[test]
+    fn lt_and_gt_are_sign_safe_only_when_extended() {
+        let asm = |extended: bool, op: Arithmetic| {
+            let mut code_writer = CodeWriter::new(Vec::new());
+            code_writer.set_extended(extended);
+            code_writer.write_arithmetic(op).unwrap();
+            code_writer.rom_words()
+        };
+        assert_eq!(asm(false, Arithmetic::Lt), asm(false, Arithmetic::Eq));
+        assert_eq!(asm(false, Arithmetic::Gt), asm(false, Arithmetic::Eq));
+        assert!(asm(true, Arithmetic::Lt) > asm(false, Arithmetic::Lt));
+        assert!(asm(true, Arithmetic::Gt) > asm(false, Arithmetic::Gt));
+    }
+
+    #[test]
+    fn extended_commands_need_set_extended() {
+        let mut code_writer = CodeWriter::new(Vec::new());
+        let error = code_writer.write_arithmetic(Arithmetic::Mul).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "Mul needs the extended instruction set");
+        // nothing was written
+        assert!(code_writer.close().unwrap().is_empty());
+    }
+
+    #[test]
+    fn labels_are_scoped_to_their_function() {
+        let mut code_writer = CodeWriter::new(Vec::new());
+        code_writer.set_file_name("Main.vm");
+        code_writer.write_label("START".to_string()).unwrap();
+        code_writer.write_function("Main.f".to_string(), 0).unwrap();
+        code_writer.write_label("LOOP".to_string()).unwrap();
+        code_writer.write_function("Main.g".to_string(), 0).unwrap();
+        code_writer.write_goto("LOOP".to_string()).unwrap();
+        // a new file starts outside Main.g
+        code_writer.set_file_name("Sys.vm");
+        code_writer.write_if("LOOP".to_string()).unwrap();
+        let asm = String::from_utf8(code_writer.close().unwrap()).unwrap();
+        let labels: Vec<&str> = asm
+            .lines()
+            .filter(|line| line.starts_with('(') || line.contains('$'))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                "(Main$START)",
+                "(Main.f)",
+                "(Main.f$LOOP)",
+                "(Main.g)",
+                "@Main.g$LOOP",
+                "@Sys$LOOP",
+            ]
+        );
+    }
+
+    #[test]
+    fn comparisons_match_the_emulator() {
+        for op in &[
+            Arithmetic::Eq,
+            Arithmetic::Lt,
+            Arithmetic::Gt,
+            Arithmetic::Lte,
+            Arithmetic::Gte,
+            Arithmetic::Neq,
+        ] {
+            assert_agree(op.clone(), &pairs());
+        }
+    }
+}
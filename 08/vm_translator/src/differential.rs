@@ -0,0 +1,344 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use hack_cpu::cpu::Cpu;
+
+use crate::call_graph::CallGraph;
+use crate::code_writer::CodeWriter;
+use crate::parser::{CommandType, Parser};
+use crate::vm_emulator::VMEmulator;
+
+// RAM both runs must agree on besides the statics; R13-R15 are the
+// translation's scratch registers
+const GLOBALS: [(&str, usize); 13] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("temp 0", 5),
+    ("temp 1", 6),
+    ("temp 2", 7),
+    ("temp 3", 8),
+    ("temp 4", 9),
+    ("temp 5", 10),
+    ("temp 6", 11),
+    ("temp 7", 12),
+];
+
+// segment pointers for programs without Sys.init, as the course's test scripts set them
+const TEST_POINTERS: [(usize, i16); 5] = [(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub name: String,
+    pub vm: i16,
+    pub cpu: i16,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub vm_steps: usize,
+    pub vm_halted: bool,
+    pub cpu_cycles: usize,
+    pub cpu_halted: bool,
+    // globals and statics that were compared
+    pub compared: usize,
+    pub differences: Vec<Difference>,
+}
+
+impl Report {
+    pub fn is_match(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+// Runs the .vm files of a directory twice: in VMEmulator, and translated by
+// CodeWriter into Hack on hack_cpu. The globals and statics both runs leave
+// behind must be the same, otherwise the translation is wrong.
+//
+// The cpu has no native OS, so every function the program calls must be
+// defined in the directory; the emulator then runs those definitions too.
+pub struct Differential {
+    path: String,
+    trace: Option<String>,
+}
+
+impl Differential {
+    // `path` is a directory ending in `/`, as the translator takes it
+    pub fn new(path: &str) -> Self {
+        Differential {
+            path: path.to_string(),
+            trace: None,
+        }
+    }
+
+    // write the cpu run's trace (see hack_cpu::trace) to `path`
+    pub fn set_trace(&mut self, path: &str) {
+        self.trace = Some(path.to_string());
+    }
+
+    pub fn run(&self, max_steps: usize, max_cycles: usize) -> Report {
+        // sorted like the translator and VMEmulator::from_dir
+        let mut vm_files: Vec<String> = fs::read_dir(&self.path)
+            .expect("Not found dir!")
+            .map(|entry| entry.unwrap().file_name().to_str().unwrap().to_string())
+            .filter(|file_name| file_name.ends_with(".vm"))
+            .collect();
+        vm_files.sort();
+        let files: Vec<Vec<CommandType>> = vm_files
+            .iter()
+            .map(|file_name| Parser::new(&self.path, file_name).commands())
+            .collect();
+
+        let defined: BTreeSet<&String> = files
+            .iter()
+            .flatten()
+            .filter_map(|command| match command {
+                CommandType::CFunction(f, _) => Some(f),
+                _ => None,
+            })
+            .collect();
+        for command in files.iter().flatten() {
+            if let CommandType::CCall(f, _) = command {
+                if !defined.contains(f) {
+                    panic!(
+                        "ERROR: {} is called but not defined; the cpu has no native OS",
+                        f
+                    );
+                }
+            }
+        }
+        let bootstrap = defined.iter().any(|f| *f == "Sys.init");
+
+        let mut vm = VMEmulator::new();
+        for commands in &files {
+            vm.load(commands.clone());
+        }
+        if bootstrap {
+            vm.bootstrap();
+        } else {
+            for (address, value) in &TEST_POINTERS {
+                vm.set_ram(*address, *value);
+            }
+        }
+        let vm_steps = vm.run(max_steps);
+
+        let mut cpu = Cpu::new();
+        let assembler = {
+            let asm = translate(&vm_files, files, bootstrap).expect("Cannot write the asm!");
+
+            // the assembler only reads files
+            let asm_path =
+                std::env::temp_dir().join(format!("vm_differential_{}.asm", std::process::id()));
+            fs::write(&asm_path, asm).expect("Cannot write the asm!");
+            let assembler = cpu.load_asm(asm_path.to_str().unwrap());
+            fs::remove_file(&asm_path).ok();
+            assembler
+        };
+        if !bootstrap {
+            for (address, value) in &TEST_POINTERS {
+                cpu.set_ram(*address, *value);
+            }
+        }
+
+        let cpu_cycles = match &self.trace {
+            Some(trace_path) => {
+                let file = File::create(trace_path).expect("Cannot create trace file!");
+                let mut out = BufWriter::new(file);
+                let cycles = cpu.run_traced(max_cycles, &mut out);
+                out.flush().expect("Cannot write trace!");
+                cycles
+            }
+            None => cpu.run(max_cycles),
+        };
+
+        let mut values: Vec<(String, i16, i16)> = GLOBALS
+            .iter()
+            .map(|(name, address)| (name.to_string(), vm.ram(*address), cpu.ram(*address)))
+            .collect();
+        for (name, vm_address) in vm.statics() {
+            // statics only used by functions left out of the asm never change
+            if let Some(cpu_address) = assembler.symbol(&name) {
+                values.push((name, vm.ram(vm_address), cpu.ram(cpu_address)));
+            }
+        }
+        let differences = values
+            .iter()
+            .filter(|(_, vm, cpu)| vm != cpu)
+            .map(|(name, vm, cpu)| Difference {
+                name: name.to_string(),
+                vm: *vm,
+                cpu: *cpu,
+            })
+            .collect();
+
+        Report {
+            vm_steps,
+            vm_halted: vm.is_halted(),
+            cpu_cycles,
+            cpu_halted: cpu.is_halted(),
+            compared: values.len(),
+            differences,
+        }
+    }
+}
+
+// the whole program as Hack assembly, stopping in `$vm.halt` where the
+// emulator stops
+fn translate(
+    file_names: &[String],
+    files: Vec<Vec<CommandType>>,
+    bootstrap: bool,
+) -> io::Result<Vec<u8>> {
+    let mut code_writer = CodeWriter::new(Vec::new());
+    code_writer.set_extended(true);
+    // halt where the emulator does: when Sys.init returns, and when
+    // the commands run out rather than running into the shared routines
+    if bootstrap {
+        code_writer.write_init()?;
+        code_writer.write_label("$vm.halt".to_string())?;
+        code_writer.write_goto("$vm.halt".to_string())?;
+    }
+    // left out like the translator's --eliminate, so a program with
+    // the whole OS still fits in the ROM
+    let mut call_graph = CallGraph::new();
+    for commands in &files {
+        call_graph.add_file(commands);
+    }
+    let unreachable = if bootstrap {
+        call_graph.unreachable("Sys.init")
+    } else {
+        Default::default()
+    };
+    for (file_name, commands) in file_names.iter().zip(files) {
+        code_writer.set_file_name(file_name);
+        let mut current: Option<String> = None;
+        for command in commands {
+            if let CommandType::CFunction(f, _) = &command {
+                current = Some(f.to_string());
+            }
+            if !current.as_ref().is_some_and(|f| unreachable.contains(f)) {
+                code_writer.write_command(command)?;
+            }
+        }
+    }
+    code_writer.write_label("$vm.halt".to_string())?;
+    code_writer.write_goto("$vm.halt".to_string())?;
+    code_writer.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh program directory holding `files`, as Differential::new takes it
+    fn program(name: &str, files: &[(&str, &str)]) -> String {
+        let dir =
+            std::env::temp_dir().join(format!("differential_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        for (file_name, text) in files {
+            fs::write(dir.join(file_name), text).unwrap();
+        }
+        format!("{}/", dir.to_str().unwrap())
+    }
+
+    // FibonacciElement from the course: Sys.init computes fibonacci(4) with
+    // two recursive calls per level, keeps it in a static so the result is
+    // compared too, then loops in WHILE
+    const FIBONACCI_MAIN: &str = "function Main.fibonacci 0
+push argument 0
+push constant 2
+lt
+if-goto IF_TRUE
+goto IF_FALSE
+label IF_TRUE
+push argument 0
+return
+label IF_FALSE
+push argument 0
+push constant 2
+sub
+call Main.fibonacci 1
+push argument 0
+push constant 1
+sub
+call Main.fibonacci 1
+add
+return
+";
+    const FIBONACCI_SYS: &str = "function Sys.init 0
+push constant 4
+call Main.fibonacci 1
+pop static 0
+label WHILE
+goto WHILE
+";
+
+    #[test]
+    fn fibonacci_element_agrees() {
+        let path = program(
+            "fibonacci",
+            &[("Main.vm", FIBONACCI_MAIN), ("Sys.vm", FIBONACCI_SYS)],
+        );
+        let trace = format!("{}trace.txt", path);
+        let mut differential = Differential::new(&path);
+        differential.set_trace(&trace);
+        let report = differential.run(100_000, 1_000_000);
+        assert!(report.vm_halted && report.cpu_halted);
+        assert_eq!(report.differences, vec![]);
+        assert!(report.is_match());
+        // the globals and Sys.0
+        assert_eq!(report.compared, GLOBALS.len() + 1);
+
+        // one trace line per cpu cycle
+        let trace = fs::read_to_string(&trace).unwrap();
+        assert_eq!(trace.lines().count(), report.cpu_cycles);
+        fs::remove_dir_all(path).ok();
+    }
+
+    #[test]
+    fn reports_where_the_runs_differ() {
+        // RAM[13] is the translation's scratch register, which its return
+        // overwrites with the returning frame's LCL; the emulator keeps the 7
+        let path = program(
+            "divergence",
+            &[(
+                "Sys.vm",
+                "function Sys.init 0
+push constant 13
+pop pointer 1
+push constant 7
+pop that 0
+call Sys.f 0
+pop temp 1
+push that 0
+pop temp 0
+label HALT
+goto HALT
+function Sys.f 0
+push constant 0
+return
+",
+            )],
+        );
+        let report = Differential::new(&path).run(1000, 100_000);
+        assert!(report.vm_halted && report.cpu_halted);
+        assert!(!report.is_match());
+        // Sys.f's frame starts after Sys.init's at 256 + 5 and its own 5 words
+        assert_eq!(
+            report.differences,
+            vec![Difference {
+                name: "temp 0".to_string(),
+                vm: 7,
+                cpu: 266,
+            }]
+        );
+        fs::remove_dir_all(path).ok();
+    }
+}
@@ -0,0 +1,6 @@
+pub mod call_graph;
+pub mod code_writer;
+pub mod differential;
+pub mod native_os;
+pub mod parser;
+pub mod vm_emulator;
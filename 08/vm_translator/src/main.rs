@@ -1,58 +1,353 @@
-mod code_writer;
-mod parser;
+use vm_translator::call_graph::CallGraph;
+use vm_translator::code_writer::CodeWriter;
+use vm_translator::differential::Differential;
+use vm_translator::parser::{CommandType, Parser};
 
-use parser::CommandType;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
 
-use crate::code_writer::CodeWriter;
-use crate::parser::Parser;
+// usage: vm_translator [input_dir/] [output.asm | -]
+//                      [--annotate] [--bootstrap] [--eliminate] [--extended]
+//        vm_translator input_dir/ --diff [--trace=cpu_trace.txt]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let input_file_path = paths
+        .first()
+        .map_or("../FunctionCalls/StaticsTest/", |path| path.as_str());
+    let output_file_path = paths
+        .get(1)
+        .map_or("../FunctionCalls/StaticsTest/StaticsTest.asm", |path| {
+            path.as_str()
+        });
 
-use std::fs;
+    if args.iter().any(|arg| arg == "--diff") {
+        let trace_path = args.iter().find_map(|arg| arg.strip_prefix("--trace="));
+        diff(input_file_path, trace_path);
+        return;
+    }
 
-fn main() {
-    let input_file_path = "../FunctionCalls/StaticsTest/";
-    let output_file_path = "../FunctionCalls/StaticsTest/StaticsTest.asm";
+    match translate(input_file_path, output_file_path, &args) {
+        Ok(()) => {}
+        // `vm_translator dir/ - | head` closes stdout early; that is not an error
+        Err(why) if why.kind() == io::ErrorKind::BrokenPipe => {}
+        // an extended command without --extended
+        Err(why) if why.kind() == io::ErrorKind::InvalidInput => panic!("ERROR: {}", why),
+        Err(why) => panic!("ERROR: cannot write the asm: {}", why),
+    }
+}
+
+fn translate(input_file_path: &str, output_file_path: &str, args: &[String]) -> io::Result<()> {
+    let annotate = args.iter().any(|arg| arg == "--annotate");
+    let force_bootstrap = args.iter().any(|arg| arg == "--bootstrap");
+    let eliminate = args.iter().any(|arg| arg == "--eliminate");
+    let extended = args.iter().any(|arg| arg == "--extended");
 
-    let mut code_writer = CodeWriter::new(output_file_path);
-    code_writer.write_init();
+    // "-" streams the asm to stdout
+    let out: Box<dyn Write> = if output_file_path == "-" {
+        Box::new(BufWriter::new(io::stdout()))
+    } else {
+        Box::new(BufWriter::new(File::create(output_file_path)?))
+    };
 
-    let mut vm_files: Vec<_> = Vec::new();
+    let mut code_writer = CodeWriter::new(out);
+    code_writer.set_annotate(annotate);
+    code_writer.set_extended(extended);
+
+    // sorted so the output does not depend on the directory order
+    let mut vm_files: Vec<String> = Vec::new();
     match fs::read_dir(input_file_path) {
-        Err(why) => println!("Not found dir! {:?}", why),
+        Err(why) => eprintln!("Not found dir! {:?}", why),
         Ok(paths) => {
-            vm_files = paths.map(|path| path.unwrap().file_name()).collect();
-            println!("{:?}", vm_files);
+            vm_files = paths
+                .map(|path| path.unwrap().file_name().to_str().unwrap().to_string())
+                .filter(|file_name| file_name.ends_with(".vm"))
+                .collect();
+            vm_files.sort();
+            eprintln!("{:?}", vm_files);
+        }
+    }
+    let mut parsers: Vec<Parser> = vm_files
+        .iter()
+        .map(|file_name| Parser::new(input_file_path, file_name))
+        .collect();
+
+    // bootstrap only when there is a Sys.init to jump to
+    let commands: Vec<CommandType> = parsers
+        .iter()
+        .flat_map(|parser| parser.commands())
+        .collect();
+    let defines_sys_init = commands
+        .iter()
+        .any(|command| matches!(command, CommandType::CFunction(f, _) if f == "Sys.init"));
+    let calls_sys_init = commands
+        .iter()
+        .any(|command| matches!(command, CommandType::CCall(f, _) if f == "Sys.init"));
+    if (force_bootstrap || calls_sys_init) && !defines_sys_init {
+        eprintln!("Warning: Sys.init is called but not defined");
+    }
+    if force_bootstrap || defines_sys_init {
+        code_writer.write_init()?;
+    }
+
+    // functions Sys.init can never reach are left out of the asm
+    let mut call_graph = CallGraph::new();
+    for parser in &parsers {
+        call_graph.add_file(&parser.commands());
+    }
+    let unreachable = if eliminate && call_graph.contains("Sys.init") {
+        call_graph.unreachable("Sys.init")
+    } else {
+        if eliminate {
+            eprintln!("Warning: no Sys.init, keeping every function");
+        }
+        Default::default()
+    };
+    let mut removed: BTreeMap<String, Vec<CommandType>> = BTreeMap::new();
+
+    for parser in &mut parsers {
+        code_writer.set_file_name(&parser.file_name());
+        let mut current: Option<String> = None;
+        while parser.has_more_commands() {
+            let command = parser.command_type();
+            if let CommandType::CFunction(f, _) = &command {
+                current = Some(f.to_string());
+            }
+
+            match current.as_ref().filter(|f| unreachable.contains(*f)) {
+                Some(f) => removed.entry(f.to_string()).or_default().push(command),
+                None => {
+                    code_writer.set_source(
+                        &parser.file_name(),
+                        parser.line_number(),
+                        &parser.command_text(),
+                    );
+                    code_writer.write_command(command)?;
+                }
+            }
+            parser.advance();
+        }
+    }
+    let line_map = code_writer.line_map();
+    code_writer.close()?;
+
+    if eliminate {
+        let mut saved = 0;
+        eprintln!("Removed {} unreachable functions", removed.len());
+        for (f, commands) in removed {
+            let mut writer = CodeWriter::new(io::sink());
+            writer.set_extended(extended);
+            for command in commands {
+                writer.write_command(command)?;
+            }
+            let words = writer.rom_words();
+            eprintln!("  {} ({} words)", f, words);
+            saved += words;
         }
+        eprintln!("Saved {} ROM words", saved);
     }
 
-    for file in vm_files {
-        let file_name = file.to_str().unwrap();
-        if file_name.contains(".vm") {
-            let mut parser = Parser::new(input_file_path, file_name);
+    if annotate {
+        if output_file_path == "-" {
+            eprintln!("Line map is not written when the asm goes to stdout");
+        } else {
+            let map_path = Path::new(output_file_path).with_extension("map.json");
+            fs::write(map_path, line_map)?;
+        }
+    }
+    Ok(())
+}
 
-            while parser.has_more_commands() {
-                let command = parser.command_type();
+// vm commands for the emulator and hack instructions for the translation;
+// a translated command takes a few dozen instructions at most
+const DIFF_STEPS: usize = 1_000_000;
+const DIFF_CYCLES: usize = 64 * DIFF_STEPS;
 
-                match command {
-                    CommandType::CArithmetic(arithmetic) => {
-                        code_writer.write_arithmetic(arithmetic)
-                    }
+fn diff(input_file_path: &str, trace_path: Option<&str>) {
+    let mut differential = Differential::new(input_file_path);
+    if let Some(trace_path) = trace_path {
+        differential.set_trace(trace_path);
+    }
+    let report = differential.run(DIFF_STEPS, DIFF_CYCLES);
+    let halted = |halted: bool| if halted { ", halted" } else { "" };
+    println!("VM:  {} steps{}", report.vm_steps, halted(report.vm_halted));
+    println!(
+        "CPU: {} cycles{}",
+        report.cpu_cycles,
+        halted(report.cpu_halted)
+    );
+    if report.is_match() {
+        println!("{} globals and statics match", report.compared);
+        return;
+    }
+    for difference in &report.differences {
+        println!(
+            "{}: vm {}, cpu {}",
+            difference.name, difference.vm, difference.cpu
+        );
+    }
+    println!(
+        "{} of {} globals and statics differ",
+        report.differences.len(),
+        report.compared
+    );
+    std::process::exit(1);
+}
 
-                    CommandType::CPush(_, _, _) => code_writer.write_push_pop(command),
-                    CommandType::CPop(_, _, _) => code_writer.write_push_pop(command),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh input directory holding `files`, as translate takes it
+    fn program(name: &str, files: &[(&str, &str)]) -> String {
+        let dir =
+            std::env::temp_dir().join(format!("vm_translator_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        for (file_name, text) in files {
+            fs::write(dir.join(file_name), text).unwrap();
+        }
+        format!("{}/", dir.to_str().unwrap())
+    }
 
-                    CommandType::CLabel(label) => code_writer.write_label(label),
-                    CommandType::CGoto(label) => code_writer.write_goto(label),
-                    CommandType::CIf(label) => code_writer.write_if(label),
+    // the asm translate writes for `input` with `flags`
+    fn translated(input: &str, flags: &[&str]) -> String {
+        let output = format!("{}Out.asm", input);
+        let args: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+        translate(input, &output, &args).unwrap();
+        fs::read_to_string(output).unwrap()
+    }
 
-                    CommandType::CFunction(f, n) => code_writer.write_function(f, n),
-                    CommandType::CReturn => code_writer.write_return(),
-                    CommandType::CCall(f, n) => code_writer.write_call(f, n),
+    #[test]
+    fn eliminate_drops_uncalled_functions() {
+        let input = program(
+            "eliminate",
+            &[
+                (
+                    "Main.vm",
+                    "function Main.main 0\ncall Main.helper 0\nreturn\n\
+                     function Main.helper 0\npush constant 1\nreturn\n\
+                     function Main.unused 0\ncall Main.helper 0\nreturn\n",
+                ),
+                (
+                    "Sys.vm",
+                    "function Sys.init 0\ncall Main.main 0\nlabel HALT\ngoto HALT\n",
+                ),
+            ],
+        );
+        let kept = translated(&input, &[]);
+        assert!(kept.contains("(Main.unused)"));
 
-                    CommandType::NotCommand => {}
-                }
-                parser.advance();
-            }
+        // Main.helper is only called from Main.main, never from Sys.init
+        let asm = translated(&input, &["--eliminate"]);
+        assert!(!asm.contains("(Main.unused)"));
+        for f in &["(Sys.init)", "(Main.main)", "(Main.helper)"] {
+            assert!(asm.contains(f), "{} is missing", f);
         }
+        assert!(asm.lines().count() < kept.lines().count());
+        fs::remove_dir_all(input).ok();
+    }
+
+    #[test]
+    fn annotate_simple_add() {
+        let input = program(
+            "annotate",
+            &[(
+                "SimpleAdd.vm",
+                "// Pushes and adds two constants.\n\npush constant 7\npush constant 8\nadd\n",
+            )],
+        );
+        let asm = translated(&input, &["--annotate"]);
+        assert_eq!(
+            asm,
+            "// SimpleAdd.vm:3  push constant 7
+@7
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+// SimpleAdd.vm:4  push constant 8
+@8
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+// SimpleAdd.vm:5  add
+@SP
+M=M-1
+A=M
+D=M
+@SP
+M=M-1
+A=M
+D=D+M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+"
+        );
+        // each command's asm lines, comment included, against its vm line
+        let map = fs::read_to_string(format!("{}Out.map.json", input)).unwrap();
+        assert_eq!(
+            map,
+            r#"[
+  {"asm_start":1,"asm_end":8,"file":"SimpleAdd.vm","line":3,"command":"push constant 7"},
+  {"asm_start":9,"asm_end":16,"file":"SimpleAdd.vm","line":4,"command":"push constant 8"},
+  {"asm_start":17,"asm_end":30,"file":"SimpleAdd.vm","line":5,"command":"add"}
+]
+"#
+        );
+        fs::remove_dir_all(input).ok();
+    }
+
+    #[test]
+    fn files_are_translated_in_sorted_order() {
+        // written out of order, so the directory is not already sorted
+        let input = program(
+            "sorted",
+            &[
+                ("Zeta.vm", "function Zeta.f 0\nreturn\n"),
+                ("Main.vm", "function Main.f 0\nreturn\n"),
+                ("Alpha.vm", "function Alpha.f 0\nreturn\n"),
+            ],
+        );
+        fs::write(format!("{}Notes.txt", input), "not a vm file").unwrap();
+        let asm = translated(&input, &[]);
+        let functions: Vec<&str> = asm
+            .lines()
+            .filter(|line| line.starts_with('(') && line.ends_with(".f)"))
+            .collect();
+        assert_eq!(functions, vec!["(Alpha.f)", "(Main.f)", "(Zeta.f)"]);
+        fs::remove_dir_all(input).ok();
+    }
+
+    #[test]
+    fn bootstrap_only_with_sys_vm() {
+        let main = "function Main.main 0\npush constant 1\nreturn\n";
+        let input = program("no_sys", &[("Main.vm", main)]);
+        let asm = translated(&input, &[]);
+        assert!(asm.starts_with("(Main.main)\n"));
+        assert!(!asm.contains("Sys.init"));
+        fs::remove_dir_all(input).ok();
+
+        let sys = "function Sys.init 0\ncall Main.main 0\nlabel HALT\ngoto HALT\n";
+        let input = program("sys", &[("Main.vm", main), ("Sys.vm", sys)]);
+        let asm = translated(&input, &[]);
+        // SP = 256, then call Sys.init ahead of every file
+        assert!(asm.starts_with("@256\nD=A\n@SP\nM=D\n@return-address.1\n"));
+        let call = asm.find("@Sys.init\n0;JMP").unwrap();
+        assert!(call < asm.find("(Main.main)").unwrap());
+        fs::remove_dir_all(input).ok();
     }
-    code_writer.close();
 }
@@ -0,0 +1,901 @@
+use std::collections::BTreeMap;
+
+const SCREEN: usize = 16384;
+const SCREEN_END: usize = 24576;
+const KBD: usize = 24576;
+const HEAP_BASE: usize = 2048;
+const HEAP_END: usize = 16384;
+
+const NEW_LINE: i16 = 128;
+const BACKSPACE: i16 = 129;
+const DOUBLE_QUOTE: i16 = 34;
+
+// the standard Jack OS API; Sys.init is the program's own
+const FUNCTIONS: &[&str] = &[
+    "Math.init",
+    "Math.abs",
+    "Math.multiply",
+    "Math.divide",
+    "Math.min",
+    "Math.max",
+    "Math.sqrt",
+    "String.new",
+    "String.dispose",
+    "String.length",
+    "String.charAt",
+    "String.setCharAt",
+    "String.appendChar",
+    "String.eraseLastChar",
+    "String.intValue",
+    "String.setInt",
+    "String.backSpace",
+    "String.doubleQuote",
+    "String.newLine",
+    "Array.new",
+    "Array.dispose",
+    "Memory.init",
+    "Memory.peek",
+    "Memory.poke",
+    "Memory.alloc",
+    "Memory.deAlloc",
+    "Output.init",
+    "Output.moveCursor",
+    "Output.printChar",
+    "Output.printString",
+    "Output.printInt",
+    "Output.println",
+    "Output.backSpace",
+    "Screen.init",
+    "Screen.clearScreen",
+    "Screen.setColor",
+    "Screen.drawPixel",
+    "Screen.drawLine",
+    "Screen.drawRectangle",
+    "Screen.drawCircle",
+    "Keyboard.init",
+    "Keyboard.keyPressed",
+    "Keyboard.readChar",
+    "Keyboard.readLine",
+    "Keyboard.readInt",
+    "Sys.halt",
+    "Sys.error",
+    "Sys.wait",
+];
+
+// What the emulator does once a native function has run.
+#[derive(Debug, PartialEq)]
+pub enum Native {
+    Return(i16),
+    // waiting for the keyboard; the call is executed again on the next step
+    Retry,
+    Halt,
+}
+
+// Rust versions of the Jack OS classes, working on the emulator's RAM.
+// Strings are heap blocks laid out as [max length, length, chars...], and the
+// heap is managed here, so a program that brings its own Jack versions of
+// some classes should bring String and Memory together.
+pub struct NativeOs {
+    blocks: BTreeMap<usize, usize>,
+    free_blocks: Vec<(usize, usize)>,
+    heap_top: usize,
+    row: usize,
+    col: usize,
+    color: bool,
+    key: Option<i16>,
+    line: Option<Vec<i16>>,
+    text: String,
+    error: Option<i16>,
+}
+
+impl NativeOs {
+    pub fn new() -> Self {
+        NativeOs {
+            blocks: BTreeMap::new(),
+            free_blocks: Vec::new(),
+            heap_top: HEAP_BASE,
+            row: 0,
+            col: 0,
+            color: true,
+            key: None,
+            line: None,
+            text: String::new(),
+            error: None,
+        }
+    }
+
+    pub fn implements(&self, f: &str) -> bool {
+        FUNCTIONS.contains(&f)
+    }
+
+    // everything printed through Output, with newlines for println
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // code passed to Sys.error, if the program failed
+    pub fn error(&self) -> Option<i16> {
+        self.error
+    }
+
+    pub fn call(&mut self, ram: &mut [i16], f: &str, args: &[i16]) -> Native {
+        let arg = |i: usize| args.get(i).copied().unwrap_or(0);
+        let (class_name, function) = f.split_at(f.find('.').unwrap_or(0));
+        match (class_name, &function[1..]) {
+            ("Math", "init") => Native::Return(0),
+            ("Math", "abs") => Native::Return(arg(0).wrapping_abs()),
+            ("Math", "multiply") => Native::Return(arg(0).wrapping_mul(arg(1))),
+            ("Math", "divide") => {
+                if arg(1) == 0 {
+                    return self.sys_error(ram, 3);
+                }
+                Native::Return(arg(0).wrapping_div(arg(1)))
+            }
+            ("Math", "min") => Native::Return(arg(0).min(arg(1))),
+            ("Math", "max") => Native::Return(arg(0).max(arg(1))),
+            ("Math", "sqrt") => {
+                if arg(0) < 0 {
+                    return self.sys_error(ram, 4);
+                }
+                Native::Return((arg(0) as f64).sqrt() as i16)
+            }
+
+            ("String", "new") => {
+                if arg(0) < 0 {
+                    return self.sys_error(ram, 14);
+                }
+                self.string_new(ram, arg(0))
+            }
+            ("String", "dispose") => {
+                self.dealloc(arg(0));
+                Native::Return(0)
+            }
+            ("String", "length") => Native::Return(string_chars(ram, arg(0)).len() as i16),
+            ("String", "charAt") => match string_index(ram, arg(0), arg(1)) {
+                Some(address) => Native::Return(ram[address]),
+                None => self.sys_error(ram, 15),
+            },
+            ("String", "setCharAt") => match string_index(ram, arg(0), arg(1)) {
+                Some(address) => {
+                    ram[address] = arg(2);
+                    Native::Return(0)
+                }
+                None => self.sys_error(ram, 16),
+            },
+            ("String", "appendChar") => {
+                let s = address(arg(0));
+                if ram[s + 1] >= ram[s] {
+                    return self.sys_error(ram, 17);
+                }
+                ram[s + 2 + ram[s + 1] as usize] = arg(1);
+                ram[s + 1] += 1;
+                Native::Return(arg(0))
+            }
+            ("String", "eraseLastChar") => {
+                let s = address(arg(0));
+                if ram[s + 1] <= 0 {
+                    return self.sys_error(ram, 18);
+                }
+                ram[s + 1] -= 1;
+                Native::Return(0)
+            }
+            ("String", "intValue") => Native::Return(int_value(&string_chars(ram, arg(0)))),
+            ("String", "setInt") => {
+                let s = address(arg(0));
+                let digits: Vec<i16> = arg(1).to_string().bytes().map(|b| b as i16).collect();
+                if digits.len() > ram[s] as usize {
+                    return self.sys_error(ram, 19);
+                }
+                ram[s + 1] = digits.len() as i16;
+                ram[s + 2..s + 2 + digits.len()].copy_from_slice(&digits);
+                Native::Return(0)
+            }
+            ("String", "backSpace") => Native::Return(BACKSPACE),
+            ("String", "doubleQuote") => Native::Return(DOUBLE_QUOTE),
+            ("String", "newLine") => Native::Return(NEW_LINE),
+
+            ("Array", "new") => {
+                if arg(0) <= 0 {
+                    return self.sys_error(ram, 2);
+                }
+                self.alloc(ram, arg(0))
+            }
+            ("Array", "dispose") | ("Memory", "deAlloc") => {
+                self.dealloc(arg(0));
+                Native::Return(0)
+            }
+
+            ("Memory", "init") => Native::Return(0),
+            ("Memory", "peek") => Native::Return(ram[address(arg(0))]),
+            ("Memory", "poke") => {
+                ram[address(arg(0))] = arg(1);
+                Native::Return(0)
+            }
+            ("Memory", "alloc") => {
+                if arg(0) <= 0 {
+                    return self.sys_error(ram, 5);
+                }
+                self.alloc(ram, arg(0))
+            }
+
+            ("Output", "init") => {
+                self.row = 0;
+                self.col = 0;
+                Native::Return(0)
+            }
+            ("Output", "moveCursor") => {
+                if !(0..23).contains(&arg(0)) || !(0..64).contains(&arg(1)) {
+                    return self.sys_error(ram, 20);
+                }
+                self.row = arg(0) as usize;
+                self.col = arg(1) as usize;
+                self.draw_char(ram, b' ' as i16);
+                Native::Return(0)
+            }
+            ("Output", "printChar") => {
+                self.print_char(ram, arg(0));
+                Native::Return(0)
+            }
+            ("Output", "printString") => {
+                for c in string_chars(ram, arg(0)) {
+                    self.print_char(ram, c);
+                }
+                Native::Return(0)
+            }
+            ("Output", "printInt") => {
+                for b in arg(0).to_string().bytes() {
+                    self.print_char(ram, b as i16);
+                }
+                Native::Return(0)
+            }
+            ("Output", "println") => {
+                self.print_char(ram, NEW_LINE);
+                Native::Return(0)
+            }
+            ("Output", "backSpace") => {
+                self.print_char(ram, BACKSPACE);
+                Native::Return(0)
+            }
+
+            ("Screen", "init") => {
+                self.color = true;
+                Native::Return(0)
+            }
+            ("Screen", "clearScreen") => {
+                ram[SCREEN..SCREEN_END]
+                    .iter_mut()
+                    .for_each(|word| *word = 0);
+                Native::Return(0)
+            }
+            ("Screen", "setColor") => {
+                self.color = arg(0) != 0;
+                Native::Return(0)
+            }
+            ("Screen", "drawPixel") => {
+                if !on_screen(arg(0), arg(1)) {
+                    return self.sys_error(ram, 7);
+                }
+                self.draw_pixel(ram, arg(0), arg(1));
+                Native::Return(0)
+            }
+            ("Screen", "drawLine") => {
+                let (x1, y1, x2, y2) = (arg(0), arg(1), arg(2), arg(3));
+                if !on_screen(x1, y1) || !on_screen(x2, y2) {
+                    return self.sys_error(ram, 8);
+                }
+                self.draw_line(ram, x1, y1, x2, y2);
+                Native::Return(0)
+            }
+            ("Screen", "drawRectangle") => {
+                let (x1, y1, x2, y2) = (arg(0), arg(1), arg(2), arg(3));
+                if x1 > x2 || y1 > y2 || !on_screen(x1, y1) || !on_screen(x2, y2) {
+                    return self.sys_error(ram, 9);
+                }
+                for y in y1..=y2 {
+                    self.draw_line(ram, x1, y, x2, y);
+                }
+                Native::Return(0)
+            }
+            ("Screen", "drawCircle") => {
+                let (x, y, r) = (arg(0), arg(1), arg(2));
+                if !on_screen(x, y) {
+                    return self.sys_error(ram, 12);
+                }
+                if !(0..=181).contains(&r) {
+                    return self.sys_error(ram, 13);
+                }
+                for dy in -r..=r {
+                    let dx = ((r as i32 * r as i32 - dy as i32 * dy as i32) as f64).sqrt() as i16;
+                    let cy = y + dy;
+                    if (0..256).contains(&cy) {
+                        self.draw_line(ram, (x - dx).max(0), cy, (x + dx).min(511), cy);
+                    }
+                }
+                Native::Return(0)
+            }
+
+            ("Keyboard", "init") => Native::Return(0),
+            ("Keyboard", "keyPressed") => Native::Return(ram[KBD]),
+            ("Keyboard", "readChar") => match self.read_char(ram) {
+                Some(c) => {
+                    self.print_char(ram, c);
+                    Native::Return(c)
+                }
+                None => Native::Retry,
+            },
+            ("Keyboard", "readLine") => match self.read_line(ram, arg(0)) {
+                Some(line) => {
+                    let native = self.string_new(ram, line.len() as i16);
+                    if let Native::Return(s) = native {
+                        let s = address(s);
+                        ram[s + 1] = line.len() as i16;
+                        ram[s + 2..s + 2 + line.len()].copy_from_slice(&line);
+                    }
+                    native
+                }
+                None => Native::Retry,
+            },
+            ("Keyboard", "readInt") => match self.read_line(ram, arg(0)) {
+                Some(line) => Native::Return(int_value(&line)),
+                None => Native::Retry,
+            },
+
+            ("Sys", "halt") => Native::Halt,
+            ("Sys", "error") => self.sys_error(ram, arg(0)),
+            ("Sys", "wait") => {
+                if arg(0) < 0 {
+                    return self.sys_error(ram, 1);
+                }
+                Native::Return(0)
+            }
+
+            _ => panic!("ERROR: {} is not a native OS function", f),
+        }
+    }
+
+    // prints "ERR<code>" and halts, like Sys.error
+    fn sys_error(&mut self, ram: &mut [i16], code: i16) -> Native {
+        for b in format!("ERR{}", code).bytes() {
+            self.print_char(ram, b as i16);
+        }
+        self.error = Some(code);
+        Native::Halt
+    }
+
+    fn string_new(&mut self, ram: &mut [i16], max_length: i16) -> Native {
+        let native = self.alloc(ram, max_length + 2);
+        if let Native::Return(s) = native {
+            ram[address(s)] = max_length;
+            ram[address(s) + 1] = 0;
+        }
+        native
+    }
+
+    // first fit from the freed blocks, otherwise from the top of the heap
+    fn alloc(&mut self, ram: &mut [i16], size: i16) -> Native {
+        let size = size as usize;
+        let address = match self.free_blocks.iter().position(|(_, free)| *free >= size) {
+            Some(i) => {
+                let (address, free) = self.free_blocks.remove(i);
+                if free > size {
+                    self.free_blocks.insert(i, (address + size, free - size));
+                }
+                address
+            }
+            None => {
+                if self.heap_top + size > HEAP_END {
+                    return self.sys_error(ram, 6);
+                }
+                self.heap_top += size;
+                self.heap_top - size
+            }
+        };
+        self.blocks.insert(address, size);
+        Native::Return(address as i16)
+    }
+
+    fn dealloc(&mut self, o: i16) {
+        if let Some(size) = self.blocks.remove(&address(o)) {
+            self.free_blocks.push((address(o), size));
+            self.free_blocks.sort_unstable();
+            // merge neighbours so large blocks can be handed out again
+            let mut merged: Vec<(usize, usize)> = Vec::new();
+            for (address, size) in self.free_blocks.drain(..) {
+                match merged.last_mut() {
+                    Some((last, last_size)) if *last + *last_size == address => *last_size += size,
+                    _ => merged.push((address, size)),
+                }
+            }
+            self.free_blocks = merged;
+        }
+    }
+
+    fn print_char(&mut self, ram: &mut [i16], c: i16) {
+        match c {
+            NEW_LINE => {
+                self.text.push('\n');
+                self.col = 0;
+                self.row = (self.row + 1) % 23;
+            }
+            BACKSPACE => {
+                self.text.pop();
+                if self.col > 0 {
+                    self.col -= 1;
+                } else if self.row > 0 {
+                    self.row -= 1;
+                    self.col = 63;
+                }
+                self.draw_char(ram, b' ' as i16);
+            }
+            _ => {
+                self.text.push(if (32..127).contains(&c) {
+                    c as u8 as char
+                } else {
+                    '?'
+                });
+                self.draw_char(ram, c);
+                self.col += 1;
+                if self.col > 63 {
+                    self.col = 0;
+                    self.row = (self.row + 1) % 23;
+                }
+            }
+        }
+    }
+
+    // 11 rows of 8 pixels at the cursor; even columns use the low byte of a word
+    fn draw_char(&self, ram: &mut [i16], c: i16) {
+        let bitmap = if (32..127).contains(&c) {
+            &FONT[c as usize - 32]
+        } else {
+            &BLACK_SQUARE
+        };
+        for (i, bits) in bitmap.iter().enumerate() {
+            let address = SCREEN + (self.row * 11 + i) * 32 + self.col / 2;
+            let (mask, bits) = match self.col % 2 {
+                0 => (0xff00u16, *bits as u16),
+                _ => (0x00ffu16, (*bits as u16) << 8),
+            };
+            ram[address] = ((ram[address] as u16 & mask) | bits) as i16;
+        }
+    }
+
+    fn draw_pixel(&self, ram: &mut [i16], x: i16, y: i16) {
+        let address = SCREEN + y as usize * 32 + x as usize / 16;
+        let bit = 1u16 << (x % 16);
+        let word = ram[address] as u16;
+        ram[address] = if self.color { word | bit } else { word & !bit } as i16;
+    }
+
+    fn draw_line(&self, ram: &mut [i16], x1: i16, y1: i16, x2: i16, y2: i16) {
+        let (dx, dy) = ((x2 - x1).abs(), -(y2 - y1).abs());
+        let (sx, sy) = ((x2 - x1).signum(), (y2 - y1).signum());
+        let (mut x, mut y, mut err) = (x1, y1, dx + dy);
+        loop {
+            self.draw_pixel(ram, x, y);
+            if x == x2 && y == y2 {
+                break;
+            }
+            if 2 * err >= dy {
+                err += dy;
+                x += sx;
+            }
+            if 2 * err <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    // a key is read once it has been pressed and released
+    fn read_char(&mut self, ram: &[i16]) -> Option<i16> {
+        match (self.key, ram[KBD]) {
+            (None, 0) => None,
+            (None, pressed) => {
+                self.key = Some(pressed);
+                None
+            }
+            (Some(key), 0) => {
+                self.key = None;
+                Some(key)
+            }
+            (Some(_), _) => None,
+        }
+    }
+
+    // prints the message on the first call, then collects keys until newline
+    fn read_line(&mut self, ram: &mut [i16], message: i16) -> Option<Vec<i16>> {
+        if self.line.is_none() {
+            for c in string_chars(ram, message) {
+                self.print_char(ram, c);
+            }
+            self.line = Some(Vec::new());
+        }
+        let c = self.read_char(ram)?;
+        self.print_char(ram, c);
+        let line = self.line.as_mut().unwrap();
+        match c {
+            NEW_LINE => self.line.take(),
+            BACKSPACE => {
+                line.pop();
+                None
+            }
+            _ => {
+                line.push(c);
+                None
+            }
+        }
+    }
+}
+
+impl Default for NativeOs {
+    fn default() -> Self {
+        NativeOs::new()
+    }
+}
+
+fn address(pointer: i16) -> usize {
+    pointer as u16 as usize & 0x7fff
+}
+
+fn on_screen(x: i16, y: i16) -> bool {
+    (0..512).contains(&x) && (0..256).contains(&y)
+}
+
+fn string_chars(ram: &[i16], s: i16) -> Vec<i16> {
+    let s = address(s);
+    let length = ram[s + 1].max(0) as usize;
+    ram[s + 2..s + 2 + length].to_vec()
+}
+
+fn string_index(ram: &[i16], s: i16, j: i16) -> Option<usize> {
+    if j < 0 || j >= ram[address(s) + 1] {
+        None
+    } else {
+        Some(address(s) + 2 + j as usize)
+    }
+}
+
+// leading '-' and digits, like String.intValue
+fn int_value(chars: &[i16]) -> i16 {
+    let (sign, digits) = match chars.first() {
+        Some(&c) if c == b'-' as i16 => (-1, &chars[1..]),
+        _ => (1, chars),
+    };
+    let mut value: i16 = 0;
+    for c in digits {
+        if !(b'0' as i16..=b'9' as i16).contains(c) {
+            break;
+        }
+        value = value.wrapping_mul(10).wrapping_add(c - b'0' as i16);
+    }
+    value.wrapping_mul(sign)
+}
+
+const BLACK_SQUARE: [u8; 11] = [63, 63, 63, 63, 63, 63, 63, 63, 63, 0, 0];
+// bitmaps of the printable characters 32..=126, one row per line of the 11-pixel frame
+const FONT: [[u8; 11]; 95] = [
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],           // space
+    [12, 30, 30, 30, 12, 12, 0, 12, 12, 0, 0],   // !
+    [54, 54, 20, 0, 0, 0, 0, 0, 0, 0, 0],        // "
+    [0, 18, 18, 63, 18, 18, 63, 18, 18, 0, 0],   // #
+    [12, 30, 51, 3, 30, 48, 51, 30, 12, 12, 0],  // $
+    [0, 0, 35, 51, 24, 12, 6, 51, 49, 0, 0],     // %
+    [12, 30, 30, 12, 54, 27, 27, 27, 54, 0, 0],  // &
+    [12, 12, 6, 0, 0, 0, 0, 0, 0, 0, 0],         // '
+    [24, 12, 6, 6, 6, 6, 6, 12, 24, 0, 0],       // (
+    [6, 12, 24, 24, 24, 24, 24, 12, 6, 0, 0],    // )
+    [0, 0, 0, 51, 30, 63, 30, 51, 0, 0, 0],      // *
+    [0, 0, 0, 12, 12, 63, 12, 12, 0, 0, 0],      // +
+    [0, 0, 0, 0, 0, 0, 0, 12, 12, 6, 0],         // ,
+    [0, 0, 0, 0, 0, 63, 0, 0, 0, 0, 0],          // -
+    [0, 0, 0, 0, 0, 0, 0, 12, 12, 0, 0],         // .
+    [0, 0, 32, 48, 24, 12, 6, 3, 1, 0, 0],       // /
+    [12, 30, 51, 51, 51, 51, 51, 30, 12, 0, 0],  // 0
+    [12, 14, 15, 12, 12, 12, 12, 12, 63, 0, 0],  // 1
+    [30, 51, 48, 24, 12, 6, 3, 51, 63, 0, 0],    // 2
+    [30, 51, 48, 48, 28, 48, 48, 51, 30, 0, 0],  // 3
+    [16, 24, 28, 26, 25, 63, 24, 24, 60, 0, 0],  // 4
+    [63, 3, 3, 31, 48, 48, 48, 51, 30, 0, 0],    // 5
+    [28, 6, 3, 3, 31, 51, 51, 51, 30, 0, 0],     // 6
+    [63, 49, 48, 48, 24, 12, 12, 12, 12, 0, 0],  // 7
+    [30, 51, 51, 51, 30, 51, 51, 51, 30, 0, 0],  // 8
+    [30, 51, 51, 51, 62, 48, 48, 24, 14, 0, 0],  // 9
+    [0, 0, 12, 12, 0, 0, 12, 12, 0, 0, 0],       // :
+    [0, 0, 12, 12, 0, 0, 12, 12, 6, 0, 0],       // ;
+    [0, 0, 24, 12, 6, 3, 6, 12, 24, 0, 0],       // <
+    [0, 0, 0, 63, 0, 0, 63, 0, 0, 0, 0],         // =
+    [0, 0, 3, 6, 12, 24, 12, 6, 3, 0, 0],        // >
+    [30, 51, 51, 24, 12, 12, 0, 12, 12, 0, 0],   // ?
+    [30, 51, 51, 59, 59, 59, 27, 3, 30, 0, 0],   // @
+    [12, 30, 51, 51, 63, 51, 51, 51, 51, 0, 0],  // A
+    [31, 51, 51, 51, 31, 51, 51, 51, 31, 0, 0],  // B
+    [28, 54, 35, 3, 3, 3, 35, 54, 28, 0, 0],     // C
+    [15, 27, 51, 51, 51, 51, 51, 27, 15, 0, 0],  // D
+    [63, 51, 35, 11, 15, 11, 35, 51, 63, 0, 0],  // E
+    [63, 51, 35, 11, 15, 11, 3, 3, 3, 0, 0],     // F
+    [28, 54, 35, 3, 59, 51, 51, 54, 44, 0, 0],   // G
+    [51, 51, 51, 51, 63, 51, 51, 51, 51, 0, 0],  // H
+    [30, 12, 12, 12, 12, 12, 12, 12, 30, 0, 0],  // I
+    [60, 24, 24, 24, 24, 24, 27, 27, 14, 0, 0],  // J
+    [51, 51, 51, 27, 15, 27, 51, 51, 51, 0, 0],  // K
+    [3, 3, 3, 3, 3, 3, 35, 51, 63, 0, 0],        // L
+    [33, 51, 63, 63, 51, 51, 51, 51, 51, 0, 0],  // M
+    [51, 51, 55, 55, 63, 59, 59, 51, 51, 0, 0],  // N
+    [30, 51, 51, 51, 51, 51, 51, 51, 30, 0, 0],  // O
+    [31, 51, 51, 51, 31, 3, 3, 3, 3, 0, 0],      // P
+    [30, 51, 51, 51, 51, 51, 63, 59, 30, 48, 0], // Q
+    [31, 51, 51, 51, 31, 27, 51, 51, 51, 0, 0],  // R
+    [30, 51, 51, 6, 28, 48, 51, 51, 30, 0, 0],   // S
+    [63, 63, 45, 12, 12, 12, 12, 12, 30, 0, 0],  // T
+    [51, 51, 51, 51, 51, 51, 51, 51, 30, 0, 0],  // U
+    [51, 51, 51, 51, 51, 30, 30, 12, 12, 0, 0],  // V
+    [51, 51, 51, 51, 51, 63, 63, 63, 18, 0, 0],  // W
+    [51, 51, 30, 30, 12, 30, 30, 51, 51, 0, 0],  // X
+    [51, 51, 51, 51, 30, 12, 12, 12, 30, 0, 0],  // Y
+    [63, 51, 49, 24, 12, 6, 35, 51, 63, 0, 0],   // Z
+    [30, 6, 6, 6, 6, 6, 6, 6, 30, 0, 0],         // [
+    [0, 0, 1, 3, 6, 12, 24, 48, 32, 0, 0],       // \
+    [30, 24, 24, 24, 24, 24, 24, 24, 30, 0, 0],  // ]
+    [8, 28, 54, 0, 0, 0, 0, 0, 0, 0, 0],         // ^
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 63, 0],          // _
+    [6, 12, 24, 0, 0, 0, 0, 0, 0, 0, 0],         // `
+    [0, 0, 0, 14, 24, 30, 27, 27, 54, 0, 0],     // a
+    [3, 3, 3, 15, 27, 51, 51, 51, 30, 0, 0],     // b
+    [0, 0, 0, 30, 51, 3, 3, 51, 30, 0, 0],       // c
+    [48, 48, 48, 60, 54, 51, 51, 51, 30, 0, 0],  // d
+    [0, 0, 0, 30, 51, 63, 3, 51, 30, 0, 0],      // e
+    [28, 54, 38, 6, 15, 6, 6, 6, 15, 0, 0],      // f
+    [0, 0, 30, 51, 51, 51, 62, 48, 51, 30, 0],   // g
+    [3, 3, 3, 27, 55, 51, 51, 51, 51, 0, 0],     // h
+    [12, 12, 0, 14, 12, 12, 12, 12, 30, 0, 0],   // i
+    [48, 48, 0, 56, 48, 48, 48, 48, 51, 30, 0],  // j
+    [3, 3, 3, 51, 27, 15, 15, 27, 51, 0, 0],     // k
+    [14, 12, 12, 12, 12, 12, 12, 12, 30, 0, 0],  // l
+    [0, 0, 0, 29, 63, 43, 43, 43, 43, 0, 0],     // m
+    [0, 0, 0, 29, 51, 51, 51, 51, 51, 0, 0],     // n
+    [0, 0, 0, 30, 51, 51, 51, 51, 30, 0, 0],     // o
+    [0, 0, 0, 30, 51, 51, 51, 31, 3, 3, 0],      // p
+    [0, 0, 0, 30, 51, 51, 51, 62, 48, 48, 0],    // q
+    [0, 0, 0, 29, 55, 51, 3, 3, 7, 0, 0],        // r
+    [0, 0, 0, 30, 51, 6, 24, 51, 30, 0, 0],      // s
+    [4, 6, 6, 15, 6, 6, 6, 54, 28, 0, 0],        // t
+    [0, 0, 0, 27, 27, 27, 27, 27, 54, 0, 0],     // u
+    [0, 0, 0, 51, 51, 51, 51, 30, 12, 0, 0],     // v
+    [0, 0, 0, 51, 51, 51, 63, 63, 18, 0, 0],     // w
+    [0, 0, 0, 51, 30, 12, 12, 30, 51, 0, 0],     // x
+    [0, 0, 0, 51, 51, 51, 62, 48, 24, 15, 0],    // y
+    [0, 0, 0, 63, 27, 12, 6, 51, 63, 0, 0],      // z
+    [56, 12, 12, 12, 7, 12, 12, 12, 56, 0, 0],   // {
+    [12, 12, 12, 12, 12, 12, 12, 12, 12, 0, 0],  // |
+    [7, 12, 12, 12, 56, 12, 12, 12, 7, 0, 0],    // }
+    [38, 45, 25, 0, 0, 0, 0, 0, 0, 0, 0],        // ~
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ram() -> Vec<i16> {
+        vec![0; 32768]
+    }
+
+    // the value a native function returned
+    fn value(native: Native) -> i16 {
+        match native {
+            Native::Return(value) => value,
+            other => panic!("expected a return, got {:?}", other),
+        }
+    }
+
+    // a String holding `text`
+    fn string(os: &mut NativeOs, ram: &mut [i16], text: &str) -> i16 {
+        let s = value(os.call(ram, "String.new", &[text.len() as i16]));
+        for b in text.bytes() {
+            os.call(ram, "String.appendChar", &[s, b as i16]);
+        }
+        s
+    }
+
+    fn chars(ram: &[i16], s: i16) -> String {
+        string_chars(ram, s)
+            .iter()
+            .map(|c| *c as u8 as char)
+            .collect()
+    }
+
+    #[test]
+    fn math() {
+        let (mut os, mut ram) = (NativeOs::new(), ram());
+        let mut math = |f: &str, args: &[i16]| value(os.call(&mut ram, f, args));
+        assert_eq!(math("Math.multiply", &[-7, 6]), -42);
+        assert_eq!(math("Math.multiply", &[300, 300]), 300i16.wrapping_mul(300));
+        assert_eq!(math("Math.divide", &[-42, 5]), -8);
+        assert_eq!(math("Math.divide", &[i16::MIN, -1]), i16::MIN);
+        assert_eq!(math("Math.sqrt", &[0]), 0);
+        assert_eq!(math("Math.sqrt", &[35]), 5);
+        assert_eq!(math("Math.sqrt", &[36]), 6);
+        assert_eq!(math("Math.sqrt", &[i16::MAX]), 181);
+        assert_eq!(math("Math.abs", &[-5]), 5);
+        assert_eq!(math("Math.min", &[-5, 3]), -5);
+        assert_eq!(math("Math.max", &[-5, 3]), 3);
+    }
+
+    #[test]
+    fn math_errors() {
+        let (mut os, mut ram) = (NativeOs::new(), ram());
+        assert_eq!(os.call(&mut ram, "Math.divide", &[1, 0]), Native::Halt);
+        assert_eq!(os.error(), Some(3));
+        assert_eq!(os.text(), "ERR3");
+
+        let (mut os, mut ram) = (NativeOs::new(), self::ram());
+        assert_eq!(os.call(&mut ram, "Math.sqrt", &[-1]), Native::Halt);
+        assert_eq!(os.error(), Some(4));
+        assert_eq!(os.text(), "ERR4");
+    }
+
+    #[test]
+    fn strings() {
+        let (mut os, mut ram) = (NativeOs::new(), ram());
+        let s = value(os.call(&mut ram, "String.new", &[6]));
+        assert_eq!(s, HEAP_BASE as i16);
+        assert_eq!(&ram[HEAP_BASE..HEAP_BASE + 2], &[6, 0]);
+
+        // appendChar returns the string so calls can be chained
+        assert_eq!(value(os.call(&mut ram, "String.appendChar", &[s, 65])), s);
+        os.call(&mut ram, "String.appendChar", &[s, 66]);
+        assert_eq!(chars(&ram, s), "AB");
+        assert_eq!(value(os.call(&mut ram, "String.length", &[s])), 2);
+
+        os.call(&mut ram, "String.setInt", &[s, -1234]);
+        assert_eq!(chars(&ram, s), "-1234");
+        assert_eq!(value(os.call(&mut ram, "String.intValue", &[s])), -1234);
+        os.call(&mut ram, "String.setInt", &[s, 0]);
+        assert_eq!(chars(&ram, s), "0");
+
+        // digits up to the first other character
+        let t = string(&mut os, &mut ram, "42abc");
+        assert_eq!(value(os.call(&mut ram, "String.intValue", &[t])), 42);
+        let t = string(&mut os, &mut ram, "x1");
+        assert_eq!(value(os.call(&mut ram, "String.intValue", &[t])), 0);
+
+        // no room for a 7th character
+        let s = string(&mut os, &mut ram, "abcdef");
+        assert_eq!(
+            os.call(&mut ram, "String.appendChar", &[s, 67]),
+            Native::Halt
+        );
+        assert_eq!(os.error(), Some(17));
+    }
+
+    #[test]
+    fn set_int_too_long() {
+        let (mut os, mut ram) = (NativeOs::new(), ram());
+        let s = value(os.call(&mut ram, "String.new", &[3]));
+        assert_eq!(os.call(&mut ram, "String.setInt", &[s, -100]), Native::Halt);
+        assert_eq!(os.error(), Some(19));
+    }
+
+    #[test]
+    fn alloc_and_dealloc() {
+        let (mut os, mut ram) = (NativeOs::new(), ram());
+        let mut alloc =
+            |os: &mut NativeOs, size: i16| value(os.call(&mut ram, "Memory.alloc", &[size]));
+        let a = alloc(&mut os, 3);
+        let b = alloc(&mut os, 4);
+        let c = alloc(&mut os, 2);
+        assert_eq!((a, b, c), (2048, 2051, 2055));
+
+        os.dealloc(b);
+        // first fit, splitting the freed block
+        assert_eq!(alloc(&mut os, 1), 2051);
+        assert_eq!(os.free_blocks, vec![(2052, 3)]);
+        os.dealloc(2051);
+        assert_eq!(os.free_blocks, vec![(2051, 4)]);
+
+        // freed neighbours merge into one block of 7 words
+        os.dealloc(a);
+        assert_eq!(os.free_blocks, vec![(2048, 7)]);
+        assert_eq!(alloc(&mut os, 8), 2057);
+        assert_eq!(alloc(&mut os, 7), 2048);
+        assert!(os.free_blocks.is_empty());
+
+        // unknown addresses are ignored
+        os.dealloc(1000);
+        assert!(os.free_blocks.is_empty());
+    }
+
+    #[test]
+    fn alloc_errors() {
+        let (mut os, mut ram) = (NativeOs::new(), ram());
+        assert_eq!(os.call(&mut ram, "Memory.alloc", &[0]), Native::Halt);
+        assert_eq!(os.error(), Some(5));
+
+        let (mut os, mut ram) = (NativeOs::new(), self::ram());
+        let heap = (HEAP_END - HEAP_BASE) as i16;
+        assert_eq!(value(os.call(&mut ram, "Memory.alloc", &[heap])), 2048);
+        assert_eq!(os.call(&mut ram, "Memory.alloc", &[1]), Native::Halt);
+        assert_eq!(os.error(), Some(6));
+    }
+
+    // the 11 screen words of the character cell at row, col
+    fn cell(ram: &[i16], row: usize, col: usize) -> Vec<u8> {
+        (0..11)
+            .map(|i| {
+                let word = ram[SCREEN + (row * 11 + i) * 32 + col / 2] as u16;
+                match col % 2 {
+                    0 => word as u8,
+                    _ => (word >> 8) as u8,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn output_places_characters() {
+        let (mut os, mut ram) = (NativeOs::new(), ram());
+        os.call(&mut ram, "Output.printChar", &[b'A' as i16]);
+        os.call(&mut ram, "Output.printChar", &[b'B' as i16]);
+        assert_eq!(cell(&ram, 0, 0), FONT[(b'A' - 32) as usize]);
+        // odd columns in the high byte of the same words
+        assert_eq!(cell(&ram, 0, 1), FONT[(b'B' - 32) as usize]);
+
+        os.call(&mut ram, "Output.moveCursor", &[2, 63]);
+        os.call(&mut ram, "Output.printInt", &[-7]);
+        assert_eq!(cell(&ram, 2, 63), FONT[(b'-' - 32) as usize]);
+        // past the last column onto the next row
+        assert_eq!(cell(&ram, 3, 0), FONT[(b'7' - 32) as usize]);
+
+        os.call(&mut ram, "Output.println", &[]);
+        let s = string(&mut os, &mut ram, "hi");
+        os.call(&mut ram, "Output.printString", &[s]);
+        assert_eq!(cell(&ram, 4, 1), FONT[(b'i' - 32) as usize]);
+
+        os.call(&mut ram, "Output.backSpace", &[]);
+        assert_eq!(cell(&ram, 4, 1), FONT[0]);
+        assert_eq!(os.text(), "AB-7\nh");
+
+        assert_eq!(
+            os.call(&mut ram, "Output.moveCursor", &[23, 0]),
+            Native::Halt
+        );
+        assert_eq!(os.error(), Some(20));
+    }
+
+    #[test]
+    fn keyboard_retries_until_a_key_is_released() {
+        let (mut os, mut ram) = (NativeOs::new(), ram());
+        assert_eq!(os.call(&mut ram, "Keyboard.readChar", &[]), Native::Retry);
+        ram[KBD] = b'x' as i16;
+        assert_eq!(
+            value(os.call(&mut ram, "Keyboard.keyPressed", &[])),
+            b'x' as i16
+        );
+        assert_eq!(os.call(&mut ram, "Keyboard.readChar", &[]), Native::Retry);
+        // still held down
+        assert_eq!(os.call(&mut ram, "Keyboard.readChar", &[]), Native::Retry);
+        ram[KBD] = 0;
+        assert_eq!(
+            os.call(&mut ram, "Keyboard.readChar", &[]),
+            Native::Return(b'x' as i16)
+        );
+        assert_eq!(os.text(), "x");
+    }
+
+    #[test]
+    fn keyboard_read_int() {
+        let (mut os, mut ram) = (NativeOs::new(), ram());
+        let prompt = string(&mut os, &mut ram, "n? ");
+        let mut result = Native::Retry;
+        for key in &[b'1' as i16, b'9' as i16, BACKSPACE, b'2' as i16, NEW_LINE] {
+            for kbd in &[*key, 0] {
+                assert_eq!(result, Native::Retry);
+                ram[KBD] = *kbd;
+                result = os.call(&mut ram, "Keyboard.readInt", &[prompt]);
+            }
+        }
+        assert_eq!(result, Native::Return(12));
+        // the prompt once, then the keys as they were typed
+        assert_eq!(os.text(), "n? 12\n");
+    }
+}
@@ -12,7 +12,7 @@ impl Parser {
         let file_name = format!("{}{}", path, name);
         let mut file = File::open(file_name).expect("File not found!");
         let class_name = name.strip_suffix(".vm").unwrap();
-        println!("Class name -> {}", class_name);
+        eprintln!("Class name -> {}", class_name);
         let mut strings = String::new();
         file.read_to_string(&mut strings)
             .expect("Something went wrong reading the file!");
@@ -23,7 +23,7 @@ impl Parser {
             .collect::<Vec<String>>();
         Parser {
             name: class_name.to_string(),
-            lines: lines,
+            lines,
             idx: 0,
         }
     }
@@ -37,14 +37,38 @@ impl Parser {
     }
 
     pub fn command_type(&self) -> CommandType {
-        let line = self.lines.get(self.idx).unwrap();
+        CommandType::new(self.name.clone(), self.words(self.idx))
+    }
+
+    // every command in the file, without moving the cursor
+    pub fn commands(&self) -> Vec<CommandType> {
+        (0..self.lines.len())
+            .map(|idx| CommandType::new(self.name.clone(), self.words(idx)))
+            .collect()
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.vm", self.name)
+    }
+
+    // 1-based line of the current command
+    pub fn line_number(&self) -> usize {
+        self.idx + 1
+    }
+
+    // current command without comments and extra whitespace
+    pub fn command_text(&self) -> String {
+        self.words(self.idx).join(" ")
+    }
+
+    fn words(&self, idx: usize) -> Vec<&str> {
+        let line = self.lines.get(idx).unwrap();
         let line = line.split("//").collect::<Vec<&str>>();
-        let words = line[0].split_whitespace().collect::<Vec<&str>>();
-        CommandType::new(self.name.clone(), words)
+        line[0].split_whitespace().collect::<Vec<&str>>()
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Arithmetic {
     Add,
     Sub,
@@ -55,8 +79,30 @@ pub enum Arithmetic {
     Eq,
     Lt,
     Gt,
+    // extended instruction set, see CodeWriter::set_extended
+    Mul,
+    Div,
+    Shl,
+    Shr,
+    Lte,
+    Gte,
+    Neq,
+}
+impl Arithmetic {
+    pub fn is_extended(&self) -> bool {
+        matches!(
+            self,
+            Arithmetic::Mul
+                | Arithmetic::Div
+                | Arithmetic::Shl
+                | Arithmetic::Shr
+                | Arithmetic::Lte
+                | Arithmetic::Gte
+                | Arithmetic::Neq
+        )
+    }
 }
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum CommandType {
     CArithmetic(Arithmetic),
     CPush(String, String, usize),
@@ -70,8 +116,7 @@ pub enum CommandType {
     NotCommand,
 }
 impl CommandType {
-    fn new(class_name: String, words: Vec<&str>) -> Self {
-        println!("PARSER {:?}", words);
+    pub(crate) fn new(class_name: String, words: Vec<&str>) -> Self {
         match &words.len() {
             1 => {
                 let command = words[0];
@@ -85,6 +130,13 @@ impl CommandType {
                     "eq"    => CommandType::CArithmetic(Arithmetic::Eq),
                     "lt"    => CommandType::CArithmetic(Arithmetic::Lt),
                     "gt"    => CommandType::CArithmetic(Arithmetic::Gt),
+                    "mul"   => CommandType::CArithmetic(Arithmetic::Mul),
+                    "div"   => CommandType::CArithmetic(Arithmetic::Div),
+                    "shl"   => CommandType::CArithmetic(Arithmetic::Shl),
+                    "shr"   => CommandType::CArithmetic(Arithmetic::Shr),
+                    "lte"   => CommandType::CArithmetic(Arithmetic::Lte),
+                    "gte"   => CommandType::CArithmetic(Arithmetic::Gte),
+                    "neq"   => CommandType::CArithmetic(Arithmetic::Neq),
                     "return"=> CommandType::CReturn,
                     _ => CommandType::NotCommand,
                 }
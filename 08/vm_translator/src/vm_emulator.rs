@@ -0,0 +1,634 @@
+use std::collections::HashMap;
+
+use crate::native_os::{Native, NativeOs};
+use crate::parser::{Arithmetic, CommandType, Parser};
+
+const RAM_SIZE: usize = 32768;
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const TEMP: usize = 5;
+const STATIC: usize = 16;
+
+// return address of the bootstrap call; returning to it halts the program
+const HALT: u16 = 0xffff;
+
+// Runs vm commands directly on the same memory layout the Hack translation uses,
+// so RAM can be inspected after (or during) a run.
+pub struct VMEmulator {
+    ram: Vec<i16>,
+    commands: Vec<CommandType>,
+    // function each command belongs to, for label lookup
+    scopes: Vec<String>,
+    functions: HashMap<String, usize>,
+    labels: HashMap<(String, String), usize>,
+    statics: HashMap<String, usize>,
+    os: NativeOs,
+    pc: usize,
+    steps: usize,
+    halted: bool,
+}
+
+impl VMEmulator {
+    pub fn new() -> Self {
+        VMEmulator {
+            ram: vec![0; RAM_SIZE],
+            commands: Vec::new(),
+            scopes: Vec::new(),
+            functions: HashMap::new(),
+            labels: HashMap::new(),
+            statics: HashMap::new(),
+            os: NativeOs::new(),
+            pc: 0,
+            steps: 0,
+            halted: false,
+        }
+    }
+
+    // every .vm file of a directory, in sorted order like the translator
+    pub fn from_dir(path: &str) -> Self {
+        let mut vm_files: Vec<String> = std::fs::read_dir(path)
+            .expect("Not found dir!")
+            .map(|entry| entry.unwrap().file_name().to_str().unwrap().to_string())
+            .filter(|file_name| file_name.ends_with(".vm"))
+            .collect();
+        vm_files.sort();
+
+        let mut emulator = VMEmulator::new();
+        for file_name in vm_files {
+            emulator.load(Parser::new(path, &file_name).commands());
+        }
+        emulator
+    }
+
+    // commands of one vm file
+    pub fn load(&mut self, commands: Vec<CommandType>) {
+        let mut scope = String::new();
+        for command in commands {
+            match &command {
+                CommandType::NotCommand => continue,
+                CommandType::CFunction(f, _) => {
+                    scope = f.to_string();
+                    self.functions.insert(f.to_string(), self.commands.len());
+                }
+                CommandType::CLabel(label) => {
+                    let key = (scope.clone(), label.to_string());
+                    self.labels.insert(key, self.commands.len());
+                }
+                // allocated from 16 in source order, like the assembler's variables
+                CommandType::CPush(class_name, segment, index)
+                | CommandType::CPop(class_name, segment, index)
+                    if segment == "static" =>
+                {
+                    let next = STATIC + self.statics.len();
+                    self.statics
+                        .entry(format!("{}.{}", class_name, index))
+                        .or_insert(next);
+                }
+                _ => {}
+            }
+            self.commands.push(command);
+            self.scopes.push(scope.clone());
+        }
+    }
+
+    pub fn os(&self) -> &NativeOs {
+        &self.os
+    }
+
+    // SP = 256 and call Sys.init, as the translator's bootstrap does
+    pub fn bootstrap(&mut self) {
+        self.ram[SP] = 256;
+        self.call("Sys.init", 0, HALT);
+    }
+
+    pub fn ram(&self, address: usize) -> i16 {
+        self.ram[address]
+    }
+
+    pub fn set_ram(&mut self, address: usize, value: i16) {
+        self.ram[address] = value;
+    }
+
+    pub fn sp(&self) -> usize {
+        self.ram[SP] as u16 as usize
+    }
+
+    // value on top of the stack
+    pub fn peek(&self) -> i16 {
+        self.ram[self.sp() - 1]
+    }
+
+    // RAM address of a class's static variable, if the program refers to it
+    pub fn static_address(&self, class_name: &str, index: usize) -> Option<usize> {
+        self.statics
+            .get(&format!("{}.{}", class_name, index))
+            .copied()
+    }
+
+    // every static variable the program refers to as `Class.index`, with its RAM address
+    pub fn statics(&self) -> Vec<(String, usize)> {
+        let mut statics: Vec<(String, usize)> = self
+            .statics
+            .iter()
+            .map(|(name, address)| (name.to_string(), *address))
+            .collect();
+        statics.sort();
+        statics
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn current_command(&self) -> Option<&CommandType> {
+        self.commands.get(self.pc)
+    }
+
+    pub fn current_function(&self) -> Option<&str> {
+        self.scopes.get(self.pc).map(|scope| scope.as_str())
+    }
+
+    // Sys.init returned, execution ran off the end, or the program is
+    // parked in a `label END / goto END` loop
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // execute one command; false once the program has halted
+    pub fn step(&mut self) -> bool {
+        if self.halted {
+            return false;
+        }
+        let command = match self.commands.get(self.pc) {
+            Some(command) => command.clone(),
+            None => {
+                self.halted = true;
+                return false;
+            }
+        };
+        self.pc += 1;
+        self.steps += 1;
+
+        match command {
+            CommandType::CArithmetic(arithmetic) => self.arithmetic(arithmetic),
+            CommandType::CPush(class_name, segment, index) => {
+                let value = match segment.as_str() {
+                    "constant" => index as i16,
+                    _ => {
+                        let address = self.address(&class_name, &segment, index);
+                        self.ram[address]
+                    }
+                };
+                self.push(value);
+            }
+            CommandType::CPop(class_name, segment, index) => {
+                let address = self.address(&class_name, &segment, index);
+                self.ram[address] = self.pop();
+            }
+            CommandType::CLabel(_) => {}
+            CommandType::CGoto(label) => {
+                let target = self.label(&label);
+                // jumping back onto itself can never make progress
+                let is_loop = target < self.pc
+                    && self.commands[target..self.pc - 1]
+                        .iter()
+                        .all(|command| matches!(command, CommandType::CLabel(_)));
+                if is_loop {
+                    self.halted = true;
+                }
+                self.pc = target;
+            }
+            CommandType::CIf(label) => {
+                if self.pop() != 0 {
+                    self.pc = self.label(&label);
+                }
+            }
+            CommandType::CFunction(_, n) => {
+                for _ in 0..n {
+                    self.push(0);
+                }
+            }
+            CommandType::CCall(f, n) => {
+                let return_address = self.pc as u16;
+                self.call(&f, n, return_address);
+            }
+            CommandType::CReturn => self.ret(),
+            CommandType::NotCommand => {}
+        }
+        !self.halted
+    }
+
+    // run until halted or `max_steps` commands; returns the commands executed
+    pub fn run(&mut self, max_steps: usize) -> usize {
+        self.run_until(max_steps, |_| false)
+    }
+
+    // run until `stop` holds before a command, the program halts, or `max_steps` commands
+    pub fn run_until<F>(&mut self, max_steps: usize, stop: F) -> usize
+    where
+        F: Fn(&VMEmulator) -> bool,
+    {
+        let start = self.steps;
+        while self.steps - start < max_steps && !stop(self) && self.step() {}
+        self.steps - start
+    }
+
+    fn push(&mut self, value: i16) {
+        let sp = self.sp();
+        self.ram[sp] = value;
+        self.ram[SP] += 1;
+    }
+
+    fn pop(&mut self) -> i16 {
+        self.ram[SP] -= 1;
+        self.ram[self.sp()]
+    }
+
+    fn address(&self, class_name: &str, segment: &str, index: usize) -> usize {
+        let base = |emulator: &VMEmulator, pointer: usize| emulator.ram[pointer] as u16 as usize;
+        match segment {
+            "local" => base(self, LCL) + index,
+            "argument" => base(self, ARG) + index,
+            "this" => base(self, THIS) + index,
+            "that" => base(self, THAT) + index,
+            "temp" => TEMP + index,
+            "pointer" => THIS + index,
+            // allocated by load
+            "static" => self.statics[&format!("{}.{}", class_name, index)],
+            _ => panic!("ERROR: unknown segment {}", segment),
+        }
+    }
+
+    fn label(&self, label: &str) -> usize {
+        let key = (self.scopes[self.pc - 1].clone(), label.to_string());
+        match self.labels.get(&key) {
+            Some(idx) => *idx,
+            None => panic!("ERROR: label {} not found in {}", label, key.0),
+        }
+    }
+
+    // the program's own definition if it has one, otherwise the native OS
+    fn call(&mut self, f: &str, n: usize, return_address: u16) {
+        let target = match self.functions.get(f) {
+            Some(idx) => *idx,
+            None if self.os.implements(f) => {
+                self.call_native(f, n, return_address);
+                return;
+            }
+            None => panic!("ERROR: function {} not found", f),
+        };
+        self.push(return_address as i16);
+        for pointer in &[LCL, ARG, THIS, THAT] {
+            self.push(self.ram[*pointer]);
+        }
+        self.ram[ARG] = (self.sp() - n - 5) as i16;
+        self.ram[LCL] = self.ram[SP];
+        self.pc = target;
+    }
+
+    fn call_native(&mut self, f: &str, n: usize, return_address: u16) {
+        let sp = self.sp();
+        let args = self.ram[sp - n..sp].to_vec();
+        match self.os.call(&mut self.ram, f, &args) {
+            Native::Return(value) => {
+                self.ram[SP] = (sp - n) as i16;
+                self.push(value);
+                if return_address == HALT {
+                    self.halted = true;
+                }
+            }
+            Native::Retry => self.pc -= 1,
+            Native::Halt => self.halted = true,
+        }
+    }
+
+    fn ret(&mut self) {
+        let frame = self.ram[LCL] as u16 as usize;
+        let return_address = self.ram[frame - 5] as u16;
+        let value = self.pop();
+        let arg = self.ram[ARG] as u16 as usize;
+        self.ram[arg] = value;
+        self.ram[SP] = (arg + 1) as i16;
+        self.ram[THAT] = self.ram[frame - 1];
+        self.ram[THIS] = self.ram[frame - 2];
+        self.ram[ARG] = self.ram[frame - 3];
+        self.ram[LCL] = self.ram[frame - 4];
+
+        if return_address == HALT {
+            self.halted = true;
+        } else {
+            self.pc = return_address as usize;
+        }
+    }
+
+    fn arithmetic(&mut self, arithmetic: Arithmetic) {
+        let truth = |b: bool| if b { -1 } else { 0 };
+        match arithmetic {
+            Arithmetic::Not => {
+                let x = self.pop();
+                self.push(!x);
+            }
+            Arithmetic::Neg => {
+                let x = self.pop();
+                self.push(x.wrapping_neg());
+            }
+            _ => {
+                let y = self.pop();
+                let x = self.pop();
+                let value = match arithmetic {
+                    Arithmetic::Add => x.wrapping_add(y),
+                    Arithmetic::Sub => x.wrapping_sub(y),
+                    Arithmetic::And => x & y,
+                    Arithmetic::Or => x | y,
+                    Arithmetic::Eq => truth(x == y),
+                    Arithmetic::Lt => truth(x < y),
+                    Arithmetic::Gt => truth(x > y),
+                    Arithmetic::Mul => x.wrapping_mul(y),
+                    Arithmetic::Div => {
+                        if y == 0 {
+                            panic!("ERROR: division by zero");
+                        }
+                        x.wrapping_div(y)
+                    }
+                    Arithmetic::Shl => {
+                        if (0..16).contains(&y) {
+                            x << y
+                        } else if y < 0 {
+                            x
+                        } else {
+                            0
+                        }
+                    }
+                    Arithmetic::Shr => {
+                        if y < 0 {
+                            x
+                        } else {
+                            x >> y.min(15)
+                        }
+                    }
+                    Arithmetic::Lte => truth(x <= y),
+                    Arithmetic::Gte => truth(x >= y),
+                    Arithmetic::Neq => truth(x != y),
+                    Arithmetic::Not | Arithmetic::Neg => unreachable!(),
+                };
+                self.push(value);
+            }
+        }
+    }
+}
+
+impl Default for VMEmulator {
+    fn default() -> Self {
+        VMEmulator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(class_name: &str, text: &str) -> Vec<CommandType> {
+        text.lines()
+            .map(|line| CommandType::new(class_name.to_string(), line.split_whitespace().collect()))
+            .collect()
+    }
+
+    #[test]
+    fn push_and_pop_every_segment() {
+        let mut vm = VMEmulator::new();
+        for (address, value) in &[
+            (SP, 256),
+            (LCL, 300),
+            (ARG, 400),
+            (THIS, 3000),
+            (THAT, 3010),
+        ] {
+            vm.set_ram(*address, *value);
+        }
+        vm.load(commands(
+            "Test",
+            "push constant 10
+             pop local 2
+             push constant 21
+             pop argument 1
+             push constant 36
+             pop this 6
+             push constant 42
+             pop that 5
+             push constant 45
+             pop temp 6
+             push constant 7
+             pop static 3
+             push constant 3030
+             pop pointer 0
+             push constant 3040
+             pop pointer 1",
+        ));
+        // not to the end: the commands loaded next carry on from here
+        assert_eq!(vm.run(16), 16);
+        assert_eq!(vm.sp(), 256);
+        assert_eq!(vm.ram(302), 10);
+        assert_eq!(vm.ram(401), 21);
+        assert_eq!(vm.ram(3006), 36);
+        assert_eq!(vm.ram(3015), 42);
+        assert_eq!(vm.ram(11), 45);
+        assert_eq!(vm.static_address("Test", 3), Some(16));
+        assert_eq!(vm.ram(16), 7);
+        assert_eq!(vm.ram(THIS), 3030);
+        assert_eq!(vm.ram(THAT), 3040);
+
+        vm.set_ram(3030 + 1, 50);
+        vm.set_ram(3040 + 2, 60);
+        vm.load(commands(
+            "Test",
+            "push local 2
+             push argument 1
+             push temp 6
+             push static 3
+             push pointer 1
+             push this 1
+             push that 2",
+        ));
+        let expected = [10, 21, 45, 7, 3040, 50, 60];
+        for value in &expected {
+            vm.step();
+            assert_eq!(vm.peek(), *value);
+        }
+        assert_eq!(vm.sp(), 256 + expected.len());
+    }
+
+    #[test]
+    fn call_and_return_frames() {
+        let mut vm = VMEmulator::new();
+        vm.load(commands(
+            "Sys",
+            "function Sys.init 0
+             push constant 7
+             push constant 8
+             call Main.add 2
+             label HALT
+             goto HALT",
+        ));
+        vm.load(commands(
+            "Main",
+            "function Main.add 1
+             push constant 5000
+             pop pointer 0
+             push argument 0
+             push argument 1
+             add
+             return",
+        ));
+        vm.set_ram(THIS, 3000);
+        vm.set_ram(THAT, 4000);
+        vm.bootstrap();
+        // Sys.init's frame: return address, LCL, ARG, THIS, THAT
+        assert_eq!(vm.sp(), 261);
+        assert_eq!(vm.ram(ARG), 256);
+        assert_eq!(vm.ram(LCL), 261);
+
+        vm.run_until(100, |vm| {
+            vm.current_command() == Some(&CommandType::CArithmetic(Arithmetic::Add))
+        });
+        assert_eq!(vm.current_function(), Some("Main.add"));
+        // arguments at 261 and 262, then the caller's frame
+        assert_eq!(vm.ram(ARG), 261);
+        assert_eq!(&vm.ram[261..268], &[7, 8, 4, 261, 256, 3000, 4000]);
+        assert_eq!(vm.ram(LCL), 268);
+        assert_eq!(vm.ram(268), 0);
+        assert_eq!(&vm.ram[269..271], &[7, 8]);
+        assert_eq!(vm.ram(THIS), 5000);
+
+        vm.run(100);
+        assert!(vm.is_halted());
+        assert_eq!(vm.current_function(), Some("Sys.init"));
+        assert_eq!(vm.sp(), 262);
+        assert_eq!(vm.peek(), 15);
+        assert_eq!(vm.ram(LCL), 261);
+        assert_eq!(vm.ram(ARG), 256);
+        assert_eq!(vm.ram(THIS), 3000);
+        assert_eq!(vm.ram(THAT), 4000);
+    }
+
+    #[test]
+    fn statics_are_allocated_in_source_order() {
+        let mut vm = VMEmulator::new();
+        vm.set_ram(SP, 256);
+        // static 2 comes first in the source but never runs
+        vm.load(commands(
+            "Main",
+            "goto SKIP
+             push constant 1
+             pop static 2
+             label SKIP
+             push constant 4
+             pop static 0",
+        ));
+        vm.load(commands(
+            "Alpha",
+            "push constant 2
+             pop static 1
+             push static 1
+             pop static 0
+             push static 2",
+        ));
+        vm.run(100);
+        assert_eq!(vm.static_address("Main", 2), Some(16));
+        assert_eq!(vm.static_address("Main", 0), Some(17));
+        assert_eq!(vm.static_address("Alpha", 1), Some(18));
+        assert_eq!(vm.static_address("Alpha", 0), Some(19));
+        assert_eq!(vm.static_address("Alpha", 2), Some(20));
+        assert_eq!(vm.static_address("Alpha", 3), None);
+        assert_eq!(
+            vm.statics(),
+            vec![
+                ("Alpha.0".to_string(), 19),
+                ("Alpha.1".to_string(), 18),
+                ("Alpha.2".to_string(), 20),
+                ("Main.0".to_string(), 17),
+                ("Main.2".to_string(), 16),
+            ]
+        );
+        assert_eq!(&vm.ram[16..21], &[0, 4, 2, 2, 0]);
+        assert_eq!(vm.peek(), 0);
+    }
+
+    #[test]
+    fn bootstrap_halts_when_sys_init_returns() {
+        let mut vm = VMEmulator::new();
+        vm.load(commands(
+            "Sys",
+            "function Sys.init 0
+             push constant 3
+             return
+             push constant 4",
+        ));
+        vm.bootstrap();
+        assert_eq!(vm.run(100), 3);
+        assert!(vm.is_halted());
+        assert!(!vm.step());
+        assert_eq!(vm.sp(), 257);
+        assert_eq!(vm.ram(256), 3);
+    }
+
+    #[test]
+    fn own_sys_init_without_main_main() {
+        let mut vm = VMEmulator::new();
+        vm.load(commands(
+            "Sys",
+            "function Sys.init 0
+             push constant 6
+             call Main.double 1
+             return",
+        ));
+        vm.load(commands(
+            "Main",
+            "function Main.double 0
+             push argument 0
+             push argument 0
+             add
+             return",
+        ));
+        vm.bootstrap();
+        vm.run(100);
+        assert!(vm.is_halted());
+        assert_eq!(vm.ram(256), 12);
+    }
+
+    #[test]
+    fn native_os_only_for_missing_functions() {
+        let mut vm = VMEmulator::new();
+        vm.load(commands(
+            "Sys",
+            "function Sys.init 0
+             push constant 6
+             push constant 7
+             call Math.multiply 2
+             push constant 20
+             push constant 3
+             call Math.divide 2
+             add
+             return",
+        ));
+        // the program's own Math.multiply adds instead
+        vm.load(commands(
+            "Math",
+            "function Math.multiply 0
+             push argument 0
+             push argument 1
+             add
+             return",
+        ));
+        vm.bootstrap();
+        vm.run(100);
+        assert!(vm.is_halted());
+        assert_eq!(vm.ram(256), 13 + 6);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::jack_tokenizer::{Keyword, TokenData, Tokenizer};
+use jack_analyzer::jack_tokenizer::{Keyword, Symbol, TokenData, Tokenizer};
 
 pub struct CompilationEngine {
     tokenizer: Tokenizer,
@@ -276,7 +276,7 @@ impl CompilationEngine {
             let next_token = self.peek_token().unwrap();
 
             // varName[ expression ]
-            if next_token == TokenData::TSymbol("[".to_string()) {
+            if next_token == TokenData::TSymbol(Symbol::OpenSq) {
                 self.push_xml_this_token();
                 self.push_xml_this_token();
                 self.compile_expression();
@@ -285,7 +285,7 @@ impl CompilationEngine {
 
             // subroutineCall 1
             // name '.' subroutineName '(' expressionList ')'
-            else if next_token == TokenData::TSymbol(".".to_string()) {
+            else if next_token == TokenData::TSymbol(Symbol::Dot) {
                 self.push_xml_this_token();
                 self.push_xml_this_token();
                 self.push_xml_this_token();
@@ -296,7 +296,7 @@ impl CompilationEngine {
 
             // subroutineCall 2 
             // name '(' expressionList ')'
-            else if next_token == TokenData::TSymbol("(".to_string()) {
+            else if next_token == TokenData::TSymbol(Symbol::OpenParen) {
                 self.push_xml_this_token();
                 self.push_xml_this_token();
                 self.compile_expression_list();
@@ -338,7 +338,7 @@ impl CompilationEngine {
         let next_token = self.peek_token();
 
         // subroutineName '(' expressionList ')'
-        if next_token == Some(TokenData::TSymbol("(".to_string())) {
+        if next_token == Some(TokenData::TSymbol(Symbol::OpenParen)) {
             self.push_xml_this_token();
             self.push_xml_this_token();
             self.compile_expression_list();
@@ -447,7 +447,7 @@ impl CompilationEngine {
     }
 
     fn is_semicolon(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol(";".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::Semicolon))
     }
 
     fn is_var_dec(&self) -> bool {
@@ -484,37 +484,35 @@ impl CompilationEngine {
     }
 
     fn is_comma(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol(",".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::Comma))
     }
 
     fn is_open_paren(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol("(".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::OpenParen))
     }
     fn is_close_paren(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol(")".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::CloseParen))
     }
 
     fn is_open_sq(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol("[".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::OpenSq))
     }
 
     fn is_close_sq(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol("]".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::CloseSq))
     }
 
     fn is_op(&self) -> bool {
         if let &Some(TokenData::TSymbol(symbol)) = &self.get_token() {
-            symbol == "+" || symbol == "-" || symbol == "*" || symbol == "/"
-            || symbol == "&amp;" || symbol == "|" || symbol == "&lt;" 
-            || symbol == "&gt;" || symbol == "="
+            symbol.is_op()
         } else {
             false
         }
     }
 
     fn is_unary_op(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol("-".to_string()))
-        || self.get_token() == &Some(TokenData::TSymbol("~".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::Minus))
+        || self.get_token() == &Some(TokenData::TSymbol(Symbol::Not))
     }
 
 }
@@ -1,30 +1,22 @@
 use std::fs;
-use std::ops::Deref;
 use std::path::PathBuf;
-use regex::Regex;
 
+use crate::lexer::{self, Span, Token};
+pub use crate::lexer::{Keyword, Symbol, TokenData};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Tokenizer {
     token_data: Option<TokenData>,
-    tokens: Vec<String>,
+    tokens: Vec<Token>,
     idx: usize,
 }
 impl Tokenizer {
     pub fn new(pb: &PathBuf) -> Self {
         let jack = fs::read_to_string(pb).unwrap();
-        let jack = remove_comments(jack);
-        let (jack, string_consts) = get_string_consts(&jack);
-
-        let mut tokens: Vec<String> = jack.split_whitespace().map(|s| s.to_string()).collect();
-        let mut i = 0;
-        for token in &mut tokens {
-            if token == "/*STRING_CONST*/" {
-                let string_const = format!("\"{}\"", string_consts[i].clone());
-                *token = string_const;
-                i += 1;
-            }
-        }
+        let tokens = match lexer::tokenize(&jack) {
+            Ok(tokens) => tokens,
+            Err(error) => panic!("ERROR: {}:{}", pb.display(), error),
+        };
 
         Tokenizer {
             token_data: None,
@@ -33,6 +25,11 @@ impl Tokenizer {
         }
     }
 
+    pub fn reset(&mut self) {
+        self.token_data = None;
+        self.idx = 0;
+    }
+
     pub fn has_more_tokens(&self) -> bool {
         self.tokens.len() > self.idx
     }
@@ -52,9 +49,12 @@ impl Tokenizer {
         &self.token_data
     }
     pub fn peek_token(&self) -> Option<TokenData> {
-        if self.idx < self.tokens.len() {
-            let token_data = TokenData::new(&self.tokens[self.idx]);
-            Some(token_data)
+        self.tokens.get(self.idx).map(|token| token.data.clone())
+    }
+
+    pub fn get_string_val(&self) -> Option<String> {
+        if let Some(TokenData::TStringVal(str)) = self.get_token() {
+            Some(str.to_string())
         } else {
             None
         }
@@ -67,16 +67,16 @@ impl Tokenizer {
                     xml_helper("keyword", &keyword.get_xml())
                 },
                 TokenData::TSymbol(symbol) => {
-                    xml_helper("symbol", &symbol)
+                    xml_helper("symbol", &symbol.get_xml())
                 },
                 TokenData::TIdentifier(id) => {
-                    xml_helper("identifier", &id)
+                    xml_helper("identifier", id)
                 },
                 TokenData::TIntVal(n) => {
                     xml_helper("integerConstant", &n.to_string())
                 }
                 TokenData::TStringVal(string) => {
-                    xml_helper("stringConstant", &string)
+                    xml_helper("stringConstant", &escape(string))
                 }
             }
         } else {
@@ -84,152 +84,178 @@ impl Tokenizer {
         }
     }
 
-
-
     fn set_token_data(&mut self) {
-        let token_data = TokenData::new(&self.tokens[self.idx]);
+        let token_data = self.tokens[self.idx].data.clone();
         self.token_data = Some(token_data);
     }
 
-}
+    // where the current token is in the .jack file
+    pub fn get_span(&self) -> Option<Span> {
+        self.token_data.as_ref()?;
+        Some(self.tokens[self.idx - 1].span)
+    }
 
-#[derive(Debug, PartialEq)]
-pub enum TokenData {
-    TKeyword(Keyword),
-    TSymbol(String),
-    TIdentifier(String),
-    TIntVal(u16),
-    TStringVal(String),
-    // TNotToken,
-}
-impl TokenData {
-    fn new(str: &str) -> Self {
-        match str {
-            "class" => TokenData::TKeyword(Keyword::Class),
-            "method" => TokenData::TKeyword(Keyword::Method),
-            "function" => TokenData::TKeyword(Keyword::Function),
-            "constructor" => TokenData::TKeyword(Keyword::Constructor),
-            "int" => TokenData::TKeyword(Keyword::Int),
-            "boolean" => TokenData::TKeyword(Keyword::Boolean),
-            "char" => TokenData::TKeyword(Keyword::Char),
-            "void" => TokenData::TKeyword(Keyword::Void),
-            "var" => TokenData::TKeyword(Keyword::Var),
-            "static" => TokenData::TKeyword(Keyword::Static),
-            "field" => TokenData::TKeyword(Keyword::Field),
-            "let" => TokenData::TKeyword(Keyword::Let),
-            "do" => TokenData::TKeyword(Keyword::Do),
-            "if" => TokenData::TKeyword(Keyword::If),
-            "else" => TokenData::TKeyword(Keyword::Else),
-            "while" => TokenData::TKeyword(Keyword::While),
-            "return" => TokenData::TKeyword(Keyword::Return),
-            "true" => TokenData::TKeyword(Keyword::True),
-            "false" => TokenData::TKeyword(Keyword::False),
-            "null" => TokenData::TKeyword(Keyword::Null),
-            "this" => TokenData::TKeyword(Keyword::This),
-
-            // "/*STRING_CONST*/" => TokenData::TStringVal(str.to_string()),
-
-            str => {
-                if let Ok(n) = str.parse::<u16>() {
-                    TokenData::TIntVal(n)
-                } else if str.len() == 1 {
-                    let c = str.chars().into_iter().next().unwrap();
-                    match c {
-                        c if c.is_alphabetic() => TokenData::TIdentifier(c.to_string()),
-                        '<' => TokenData::TSymbol("&lt;".to_string()),
-                        '>' => TokenData::TSymbol("&gt;".to_string()),
-                        '&' => TokenData::TSymbol("&amp;".to_string()),
-                        _ => TokenData::TSymbol(c.to_string()),
-                    }
-                } else if str.contains("\"") {
-                    let str = str[1..(str.len() - 1)].to_string();
-                    TokenData::TStringVal(str)
-                } else {
-                    TokenData::TIdentifier(str.to_string())
-                }
-            }
-        }
+    // flag check
+    pub fn is_class_var_dec(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Static))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::Field))
+    }
+    pub fn is_class_static(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Static))
+    }
+    pub fn is_class_field(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Field))
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub enum Keyword {
-    Class,
-    Method,
-    Function,
-    Constructor,
-    Int,
-    Boolean,
-    Char,
-    Void,
-    Var,
-    Static,
-    Field,
-    Let,
-    Do,
-    If,
-    Else,
-    While,
-    Return,
-    True,
-    False,
-    Null,
-    This,
-}
-impl Keyword {
-    fn get_xml(&self) -> String {
-        let keyword = match self {
-            Keyword::Class => "class",
-            Keyword::Method => "method",
-            Keyword::Function => "function",
-            Keyword::Constructor => "constructor",
-            Keyword::Int => "int",
-            Keyword::Boolean => "boolean",
-            Keyword::Char => "char",
-            Keyword::Void => "void",
-            Keyword::Var => "var",
-            Keyword::Static => "static",
-            Keyword::Field => "field",
-            Keyword::Let => "let",
-            Keyword::Do => "do",
-            Keyword::If => "if",
-            Keyword::Else => "else",
-            Keyword::While => "while",
-            Keyword::Return => "return",
-            Keyword::True => "true",
-            Keyword::False => "false",
-            Keyword::Null => "null",
-            Keyword::This => "this",
-        };
-        keyword.to_string()
+    pub fn is_subroutine_dec(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Constructor))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::Function))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::Method))
     }
-}
 
+    pub fn is_constructor(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Constructor))
+    }
 
+    pub fn is_function(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Function))
+    }
 
-fn remove_comments(contents: String) -> String {
-    let re_comment_line = Regex::new(r"//.*\n").unwrap();
-    let re_comment_block = Regex::new(r"/\*[\s\S]*?\*/").unwrap();
-    let contents = re_comment_line.replace_all(&contents, "");
-    let contents = re_comment_block.replace_all(&contents, "");
-    contents.deref().to_string()
-}
+    pub fn is_method(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Method))
+    }
+
+
+    pub fn is_statement(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Let))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::Do))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::If))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::While))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::Return))
+    }
+
+    pub fn is_keyword_constant(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::True))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::False))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::Null))
+            || self.get_token() == &Some(TokenData::TKeyword(Keyword::This))
+    }
+
+    pub fn is_integer_const(&self) -> bool {
+        matches!(self.get_token(), Some(TokenData::TIntVal(_)))
+    }
+
+    pub fn is_string_constant(&self) -> bool {
+        matches!(self.get_token(), Some(TokenData::TStringVal(_)))
+    }
+
+    pub fn is_var_dec(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Var))
+    }
+
+    pub fn is_close_paren(&self) -> bool {
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::CloseParen))
+    }
+
+    pub fn is_open_paren(&self) -> bool {
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::OpenParen))
+    }
+
+    pub fn is_open_sq(&self) -> bool {
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::OpenSq))
+    }
+
+    pub fn is_comma(&self) -> bool {
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::Comma))
+    }
+
+    pub fn is_semicolon(&self) -> bool {
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::Semicolon))
+    }
+
+    pub fn is_else(&self) -> bool {
+        self.get_token() == &Some(TokenData::TKeyword(Keyword::Else))
+    }
+
+    pub fn is_class_method(&self) -> bool {
+        let next_token = self.peek_token();
+        next_token == Some(TokenData::TSymbol(Symbol::Dot))
+    }
+
+    pub fn is_op(&self) -> bool {
+        if let &Some(TokenData::TSymbol(symbol)) = &self.get_token() {
+            symbol.is_op()
+        } else {
+            false
+        }
+    }
+
+    pub fn is_unary_op(&self) -> bool {
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::Minus))
+            || self.get_token() == &Some(TokenData::TSymbol(Symbol::Not))
+    }
+
+    pub fn next_is_dot(&self) -> bool {
+        self.peek_token() == Some(TokenData::TSymbol(Symbol::Dot))
+    }
 
-fn get_string_consts(contents: &str) -> (String, Vec<String>) {
-    let re_string_const = Regex::new(r#"".*""#).unwrap();
-    let string_const_matches = re_string_const.find_iter(&contents);
-    let string_consts: Vec<String> = string_const_matches.map(|m| {
-        m.as_str().replace("\"","").to_string()
-        }).collect();
-    let re_alphanumeric = Regex::new(r"(?P<symbol>[^ 0-9a-zA-Z_])").unwrap();
-    let contents = re_alphanumeric.replace_all(&contents, " $symbol ");
-    let contents = re_string_const.replace_all(&contents, " /*STRING_CONST*/ ");
-
-    (contents.to_string(), string_consts)
 }
 
 fn xml_helper(tag: &str, content: &str) -> String {
     format!("<{}> {} </{}>", tag, content, tag)
 }
 
+// string constants may hold any of the characters XML reserves
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xml(jack: &str) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("Xml{}.jack", std::process::id()));
+        fs::write(&path, jack).unwrap();
+        let mut tokenizer = Tokenizer::new(&path);
+        fs::remove_file(&path).unwrap();
+        let mut xml = Vec::new();
+        while tokenizer.has_more_tokens() {
+            tokenizer.advance();
+            xml.push(tokenizer.get_xml());
+        }
+        xml
+    }
+
+    #[test]
+    fn string_constants_are_escaped() {
+        assert_eq!(
+            xml("\"a < b && c > 'd'\""),
+            vec!["<stringConstant> a &lt; b &amp;&amp; c &gt; 'd' </stringConstant>"]
+        );
+        // already escaped text is escaped again
+        assert_eq!(
+            xml("\"&lt;\""),
+            vec!["<stringConstant> &amp;lt; </stringConstant>"]
+        );
+    }
 
+    #[test]
+    fn symbols_are_escaped() {
+        assert_eq!(
+            xml("x < y & z > w"),
+            vec![
+                "<identifier> x </identifier>",
+                "<symbol> &lt; </symbol>",
+                "<identifier> y </identifier>",
+                "<symbol> &amp; </symbol>",
+                "<identifier> z </identifier>",
+                "<symbol> &gt; </symbol>",
+                "<identifier> w </identifier>",
+            ]
+        );
+    }
+}
@@ -0,0 +1,517 @@
+use std::fmt;
+
+// Hand-written lexer for Jack, shared by the analyzer and the compiler (11).
+// Every token keeps the span it came from so errors can point at the source.
+
+// line and column, both counted from 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+// from the first character of a token to just past its last
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+// the largest integer constant Jack allows
+const MAX_INT: u16 = 32767;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub position: Position,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub data: TokenData,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TokenData {
+    TKeyword(Keyword),
+    TSymbol(Symbol),
+    TIdentifier(String),
+    TIntVal(u16),
+    TStringVal(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Keyword {
+    Class,
+    Method,
+    Function,
+    Constructor,
+    Int,
+    Boolean,
+    Char,
+    Void,
+    Var,
+    Static,
+    Field,
+    Let,
+    Do,
+    If,
+    Else,
+    While,
+    Return,
+    True,
+    False,
+    Null,
+    This,
+}
+
+const KEYWORDS: [(&str, Keyword); 21] = [
+    ("class", Keyword::Class),
+    ("method", Keyword::Method),
+    ("function", Keyword::Function),
+    ("constructor", Keyword::Constructor),
+    ("int", Keyword::Int),
+    ("boolean", Keyword::Boolean),
+    ("char", Keyword::Char),
+    ("void", Keyword::Void),
+    ("var", Keyword::Var),
+    ("static", Keyword::Static),
+    ("field", Keyword::Field),
+    ("let", Keyword::Let),
+    ("do", Keyword::Do),
+    ("if", Keyword::If),
+    ("else", Keyword::Else),
+    ("while", Keyword::While),
+    ("return", Keyword::Return),
+    ("true", Keyword::True),
+    ("false", Keyword::False),
+    ("null", Keyword::Null),
+    ("this", Keyword::This),
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Symbol {
+    OpenBrace,
+    CloseBrace,
+    OpenParen,
+    CloseParen,
+    OpenSq,
+    CloseSq,
+    Dot,
+    Comma,
+    Semicolon,
+    Plus,
+    Minus,
+    Asterisk,
+    Slash,
+    And,
+    Or,
+    Lt,
+    Gt,
+    Eq,
+    Not,
+}
+
+const SYMBOLS: [(char, Symbol); 19] = [
+    ('{', Symbol::OpenBrace),
+    ('}', Symbol::CloseBrace),
+    ('(', Symbol::OpenParen),
+    (')', Symbol::CloseParen),
+    ('[', Symbol::OpenSq),
+    (']', Symbol::CloseSq),
+    ('.', Symbol::Dot),
+    (',', Symbol::Comma),
+    (';', Symbol::Semicolon),
+    ('+', Symbol::Plus),
+    ('-', Symbol::Minus),
+    ('*', Symbol::Asterisk),
+    ('/', Symbol::Slash),
+    ('&', Symbol::And),
+    ('|', Symbol::Or),
+    ('<', Symbol::Lt),
+    ('>', Symbol::Gt),
+    ('=', Symbol::Eq),
+    ('~', Symbol::Not),
+];
+
+impl Keyword {
+    pub fn new(word: &str) -> Option<Keyword> {
+        KEYWORDS
+            .iter()
+            .find(|(keyword, _)| *keyword == word)
+            .map(|(_, keyword)| keyword.clone())
+    }
+
+    pub fn get_xml(&self) -> String {
+        let (keyword, _) = KEYWORDS.iter().find(|(_, k)| k == self).unwrap();
+        keyword.to_string()
+    }
+}
+
+impl Symbol {
+    pub fn new(c: char) -> Option<Symbol> {
+        SYMBOLS
+            .iter()
+            .find(|(symbol, _)| *symbol == c)
+            .map(|(_, symbol)| *symbol)
+    }
+
+    pub fn as_char(&self) -> char {
+        let (c, _) = SYMBOLS.iter().find(|(_, s)| s == self).unwrap();
+        *c
+    }
+
+    // binary operators of a Jack expression
+    pub fn is_op(&self) -> bool {
+        matches!(
+            self,
+            Symbol::Plus
+                | Symbol::Minus
+                | Symbol::Asterisk
+                | Symbol::Slash
+                | Symbol::And
+                | Symbol::Or
+                | Symbol::Lt
+                | Symbol::Gt
+                | Symbol::Eq
+        )
+    }
+
+    // escaped for the .xml output
+    pub fn get_xml(&self) -> String {
+        match self {
+            Symbol::Lt => "&lt;".to_string(),
+            Symbol::Gt => "&gt;".to_string(),
+            Symbol::And => "&amp;".to_string(),
+            _ => self.as_char().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_char())
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
+pub fn tokenize(text: &str) -> Result<Vec<Token>, LexError> {
+    let mut lexer = Lexer {
+        chars: text.chars().collect(),
+        idx: 0,
+        line: 1,
+        column: 1,
+    };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    idx: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer {
+    // the next token, None at the end of the text
+    fn token(&mut self) -> Result<Option<Token>, LexError> {
+        self.skip_blanks()?;
+        let start = self.position();
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let data = if c == '"' {
+            self.string(start)?
+        } else if c.is_ascii_digit() {
+            // take the whole word so `9abc` is one bad token, not 9 and abc
+            let word = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !word.chars().all(|c| c.is_ascii_digit()) {
+                return Err(error(
+                    start,
+                    format!("identifier {} starts with a digit", word),
+                ));
+            }
+            match word.parse::<u16>() {
+                Ok(n) if n <= MAX_INT => TokenData::TIntVal(n),
+                _ => {
+                    return Err(error(
+                        start,
+                        format!("integer constant {} is larger than {}", word, MAX_INT),
+                    ))
+                }
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let word = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            match Keyword::new(&word) {
+                Some(keyword) => TokenData::TKeyword(keyword),
+                None => TokenData::TIdentifier(word),
+            }
+        } else if let Some(symbol) = Symbol::new(c) {
+            self.bump();
+            TokenData::TSymbol(symbol)
+        } else {
+            return Err(error(start, format!("unexpected character {:?}", c)));
+        };
+        Ok(Some(Token {
+            data,
+            span: Span {
+                start,
+                end: self.position(),
+            },
+        }))
+    }
+
+    // whitespace, `// ...` and `/* ... */` comments
+    fn skip_blanks(&mut self) -> Result<(), LexError> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => {
+                    self.take_while(|c| c != '\n');
+                }
+                (Some('/'), Some('*')) => {
+                    let start = self.position();
+                    self.bump();
+                    self.bump();
+                    while (self.peek(0), self.peek(1)) != (Some('*'), Some('/')) {
+                        if self.bump().is_none() {
+                            return Err(error(start, "unterminated comment".to_string()));
+                        }
+                    }
+                    self.bump();
+                    self.bump();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // a string constant runs to the next `"` on the same line
+    fn string(&mut self, start: Position) -> Result<TokenData, LexError> {
+        self.bump();
+        let text = self.take_while(|c| c != '"' && c != '\n');
+        if self.bump() != Some('"') {
+            return Err(error(start, "unterminated string".to_string()));
+        }
+        Ok(TokenData::TStringVal(text))
+    }
+
+    fn take_while<F>(&mut self, accept: F) -> String
+    where
+        F: Fn(char) -> bool,
+    {
+        let mut text = String::new();
+        while let Some(c) = self.peek(0).filter(|c| accept(*c)) {
+            text.push(c);
+            self.bump();
+        }
+        text
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.idx + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.idx += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+fn error(position: Position, message: String) -> LexError {
+    LexError { position, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(text: &str) -> Vec<TokenData> {
+        tokenize(text)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.data)
+            .collect()
+    }
+
+    fn span(start: (usize, usize), end: (usize, usize)) -> Span {
+        Span {
+            start: Position {
+                line: start.0,
+                column: start.1,
+            },
+            end: Position {
+                line: end.0,
+                column: end.1,
+            },
+        }
+    }
+
+    #[test]
+    fn token_kinds() {
+        assert_eq!(
+            data("let x_1 = 32767 + \"hi\";"),
+            vec![
+                TokenData::TKeyword(Keyword::Let),
+                TokenData::TIdentifier("x_1".to_string()),
+                TokenData::TSymbol(Symbol::Eq),
+                TokenData::TIntVal(32767),
+                TokenData::TSymbol(Symbol::Plus),
+                TokenData::TStringVal("hi".to_string()),
+                TokenData::TSymbol(Symbol::Semicolon),
+            ]
+        );
+        // keywords only as whole words
+        assert_eq!(
+            data("classy _do"),
+            vec![
+                TokenData::TIdentifier("classy".to_string()),
+                TokenData::TIdentifier("_do".to_string()),
+            ]
+        );
+        for (c, symbol) in SYMBOLS.iter() {
+            assert_eq!(data(&c.to_string()), vec![TokenData::TSymbol(*symbol)]);
+        }
+        for (word, keyword) in KEYWORDS.iter() {
+            assert_eq!(data(word), vec![TokenData::TKeyword(keyword.clone())]);
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = tokenize("class Main {\n  // comment\n  /* a\n b */ field int x;\n}").unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|token| token.span).collect();
+        assert_eq!(
+            spans,
+            vec![
+                span((1, 1), (1, 6)),
+                span((1, 7), (1, 11)),
+                span((1, 12), (1, 13)),
+                span((4, 7), (4, 12)),
+                span((4, 13), (4, 16)),
+                span((4, 17), (4, 18)),
+                span((4, 18), (4, 19)),
+                span((5, 1), (5, 2)),
+            ]
+        );
+        // the quotes belong to the string's span
+        let tokens = tokenize("x = \"ab\";").unwrap();
+        assert_eq!(tokens[2].span, span((1, 5), (1, 9)));
+        assert_eq!(tokens[2].span.to_string(), "1:5");
+    }
+
+    #[test]
+    fn comment_markers_inside_strings() {
+        assert_eq!(
+            data("\"http://x\" // gone\n\"/* not */\""),
+            vec![
+                TokenData::TStringVal("http://x".to_string()),
+                TokenData::TStringVal("/* not */".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn two_strings_on_one_line() {
+        assert_eq!(
+            data("do f(\"a\", \"b c\");"),
+            vec![
+                TokenData::TKeyword(Keyword::Do),
+                TokenData::TIdentifier("f".to_string()),
+                TokenData::TSymbol(Symbol::OpenParen),
+                TokenData::TStringVal("a".to_string()),
+                TokenData::TSymbol(Symbol::Comma),
+                TokenData::TStringVal("b c".to_string()),
+                TokenData::TSymbol(Symbol::CloseParen),
+                TokenData::TSymbol(Symbol::Semicolon),
+            ]
+        );
+        assert_eq!(data("\"\""), vec![TokenData::TStringVal(String::new())]);
+    }
+
+    // the error as `line:column: message`
+    fn error(text: &str) -> String {
+        tokenize(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn integer_constants_past_32767() {
+        assert_eq!(data("32767"), vec![TokenData::TIntVal(32767)]);
+        assert_eq!(
+            error("let x = 40000;"),
+            "1:9: integer constant 40000 is larger than 32767"
+        );
+        // too large even for a u16
+        assert_eq!(
+            error("\n  x = 99999;"),
+            "2:7: integer constant 99999 is larger than 32767"
+        );
+    }
+
+    #[test]
+    fn identifiers_starting_with_a_digit() {
+        let error = tokenize("do f(1,\n    9abc);").unwrap_err();
+        assert_eq!(error.position, Position { line: 2, column: 5 });
+        assert_eq!(error.message, "identifier 9abc starts with a digit");
+    }
+
+    #[test]
+    fn unterminated_strings() {
+        assert_eq!(error("x = \"abc"), "1:5: unterminated string");
+        // a string cannot run on to the next line
+        assert_eq!(error("\tlet s = \"abc\n\";"), "1:10: unterminated string");
+    }
+
+    #[test]
+    fn unterminated_comments() {
+        assert_eq!(
+            error("class A {\n  /* no end *\n}"),
+            "2:3: unterminated comment"
+        );
+        assert_eq!(error("/*/"), "1:1: unterminated comment");
+    }
+
+    #[test]
+    fn unexpected_characters() {
+        assert_eq!(error("x = y # z;"), "1:7: unexpected character '#'");
+    }
+}
@@ -0,0 +1,2 @@
+pub mod jack_tokenizer;
+pub mod lexer;
@@ -1,4 +1,3 @@
-mod jack_tokenizer;
 mod compilation_engine;
 
 use std::{env, thread, time::Duration};
@@ -7,7 +6,7 @@ use std::fs;
 use std::fs::File;
 use std::path::PathBuf;
 
-use crate::jack_tokenizer::Tokenizer;
+use jack_analyzer::jack_tokenizer::Tokenizer;
 use crate::compilation_engine::CompilationEngine;
 
 use indicatif::{ProgressBar, ProgressStyle};
@@ -1,8 +1,8 @@
 use crate::{
-    jack_tokenizer::{Keyword, TokenData, Tokenizer},
     symbol_table::{SymbolTable, VarKind},
     vm_writer::VMWriter,
 };
+use jack_analyzer::jack_tokenizer::{Keyword, Symbol, TokenData, Tokenizer};
 pub struct CompilationEngine {
     tokenizer: Tokenizer,
     symbol_table: SymbolTable,
@@ -24,6 +24,10 @@ impl CompilationEngine {
         }
     }
 
+    pub fn set_extended_vm(&mut self, extended: bool) {
+        self.vm_writer.set_extended(extended);
+    }
+
     pub fn start_compile(&mut self) {
         self.advance();
         self.compile_class();
@@ -226,7 +230,7 @@ impl CompilationEngine {
                         self.compile_return();
                     }
                     _ => {
-                        panic!("ERROR: This token is not statement");
+                        self.error("this token is not a statement");
                     }
                 }
             }
@@ -453,7 +457,7 @@ impl CompilationEngine {
         }
 
         if let Some(TokenData::TSymbol(symbol)) = arithmetic {
-            self.vm_writer.write_arithmetic(&symbol);
+            self.vm_writer.write_arithmetic(symbol);
         }
     }
 
@@ -511,7 +515,7 @@ impl CompilationEngine {
             self.compile_term();
 
             if let Some(TokenData::TSymbol(op)) = unary_op {
-                self.vm_writer.write_unary_op(&op);
+                self.vm_writer.write_unary_op(op);
             }
         }
         // '(' expression ')'
@@ -525,7 +529,7 @@ impl CompilationEngine {
             let next_token = self.peek_token().unwrap();
 
             // varName '[' expression ']'
-            if next_token == TokenData::TSymbol("[".to_string()) {
+            if next_token == TokenData::TSymbol(Symbol::OpenSq) {
                 let var_name = self.get_identifier();
                 self.write_push_to_vm(&var_name);
                 self.advance();
@@ -540,7 +544,7 @@ impl CompilationEngine {
             }
 
             // name '.' subroutineName '(' expressionList ')'
-            else if next_token == TokenData::TSymbol(".".to_string()) {
+            else if next_token == TokenData::TSymbol(Symbol::Dot) {
                 // className '.' subroutineName '(' expresionList ')'
                 if self.is_class_name() {
                     let class_name = self.get_identifier();
@@ -722,23 +726,23 @@ impl CompilationEngine {
 
 
     fn is_close_paren(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol(")".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::CloseParen))
     }
 
     fn is_open_paren(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol("(".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::OpenParen))
     }
 
     fn is_open_sq(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol("[".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::OpenSq))
     }
 
     fn is_comma(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol(",".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::Comma))
     }
 
     fn is_semicolon(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol(";".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::Semicolon))
     }
 
     fn is_else(&self) -> bool {
@@ -748,23 +752,15 @@ impl CompilationEngine {
 
     fn is_op(&self) -> bool {
         if let &Some(TokenData::TSymbol(symbol)) = &self.get_token() {
-            symbol == "+"
-                || symbol == "-"
-                || symbol == "*"
-                || symbol == "/"
-                || symbol == "&amp;"
-                || symbol == "|"
-                || symbol == "&lt;"
-                || symbol == "&gt;"
-                || symbol == "="
+            symbol.is_op()
         } else {
             false
         }
     }
 
     fn is_unary_op(&self) -> bool {
-        self.get_token() == &Some(TokenData::TSymbol("-".to_string()))
-            || self.get_token() == &Some(TokenData::TSymbol("~".to_string()))
+        self.get_token() == &Some(TokenData::TSymbol(Symbol::Minus))
+            || self.get_token() == &Some(TokenData::TSymbol(Symbol::Not))
     }
 
     // get functions
@@ -786,7 +782,7 @@ impl CompilationEngine {
         if let Some(TokenData::TIdentifier(id)) = self.get_token() {
             id.to_string()
         } else {
-            panic!("ERROR: CE.get_identifier()");
+            self.error("expected an identifier");
         }
     }
 
@@ -794,7 +790,7 @@ impl CompilationEngine {
         if let Some(TokenData::TIntVal(n)) = self.get_token() {
             *n
         } else {
-            panic!("ERROR: CE.get_integer_const()");
+            self.error("expected an integer constant");
         }
     }
 
@@ -806,16 +802,24 @@ impl CompilationEngine {
                     Keyword::Boolean => "boolean".to_string(),
                     Keyword::Char => "char".to_string(),
                     _ => {
-                        panic!("ERROR: get var name");
+                        self.error("expected a type");
                     }
                 },
                 TokenData::TIdentifier(id) => id.to_string(),
                 _ => {
-                    panic!("ERROR: get var name");
+                    self.error("expected a type");
                 }
             }
         } else {
-            panic!("ERROR: get var name");
+            self.error("expected a type");
+        }
+    }
+
+    // panics with where the current token is in the .jack file
+    fn error(&self, message: &str) -> ! {
+        match self.tokenizer.get_span() {
+            Some(span) => panic!("ERROR: {}: {}", span, message),
+            None => panic!("ERROR: {}", message),
         }
     }
 
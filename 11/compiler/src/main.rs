@@ -1,4 +1,3 @@
-mod jack_tokenizer;
 mod compilation_engine_xml;
 mod compilation_engine;
 mod symbol_table;
@@ -10,7 +9,7 @@ use std::fs;
 use std::fs::File;
 use std::path::PathBuf;
 
-use crate::jack_tokenizer::Tokenizer;
+use jack_analyzer::jack_tokenizer::Tokenizer;
 use crate::compilation_engine_xml::CompilationEngineXml;
 use crate::compilation_engine::CompilationEngine;
 
@@ -18,6 +17,8 @@ use indicatif::{ProgressBar, ProgressStyle};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
+    // emit mul/div for the VM translator's --extended mode
+    let extended_vm = args.iter().any(|arg| arg == "--extended-vm");
 
     // get jack files
     let jack_files = get_jack_files(&args[1]);
@@ -45,6 +46,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         // start compile to vm
         let mut compilation_engine = CompilationEngine::new(tokenizer);
+        compilation_engine.set_extended_vm(extended_vm);
         compilation_engine.start_compile();
         // write to vm
         let output_vm = compilation_engine.output_vm();
@@ -1,19 +1,26 @@
 use std::fmt::format;
 
-use crate::jack_tokenizer::TokenData;
+use jack_analyzer::jack_tokenizer::Symbol;
 
 #[derive(Debug, Clone)]
 pub struct VMWriter {
-    vm: Vec<String>
+    vm: Vec<String>,
+    extended: bool,
 }
 
 impl VMWriter {
     pub fn new() -> Self {
         VMWriter {
             vm: Vec::new(),
+            extended: false,
         }
     }
 
+    // target the translator's extended instruction set (mul, div, ...)
+    pub fn set_extended(&mut self, extended: bool) {
+        self.extended = extended;
+    }
+
     pub fn write_push(&mut self, segment: &str, n: u16) {
         let line = format!("push {} {}", segment, n);
         self.vm.push(line);
@@ -34,25 +41,27 @@ impl VMWriter {
         self.vm.push(line);
     }
  
-    pub fn write_arithmetic(&mut self, op: &str) {
+    pub fn write_arithmetic(&mut self, op: Symbol) {
         match op {
-            "+" => self.vm.push("add".to_string()),
-            "-" => self.vm.push("sub".to_string()),
-            "*" => self.vm.push("call Math.multiply 2".to_string()),
-            "/" => self.vm.push("call Math.divide 2".to_string()),
-            "&amp;" => self.vm.push("and".to_string()),
-            "|" => self.vm.push("or".to_string()),
-            "&lt;" => self.vm.push("lt".to_string()),
-            "&gt;" => self.vm.push("gt".to_string()),
-            "=" => self.vm.push("eq".to_string()),
+            Symbol::Plus => self.vm.push("add".to_string()),
+            Symbol::Minus => self.vm.push("sub".to_string()),
+            Symbol::Asterisk if self.extended => self.vm.push("mul".to_string()),
+            Symbol::Slash if self.extended => self.vm.push("div".to_string()),
+            Symbol::Asterisk => self.vm.push("call Math.multiply 2".to_string()),
+            Symbol::Slash => self.vm.push("call Math.divide 2".to_string()),
+            Symbol::And => self.vm.push("and".to_string()),
+            Symbol::Or => self.vm.push("or".to_string()),
+            Symbol::Lt => self.vm.push("lt".to_string()),
+            Symbol::Gt => self.vm.push("gt".to_string()),
+            Symbol::Eq => self.vm.push("eq".to_string()),
             _ => {},
         }
     }
 
-    pub fn write_unary_op(&mut self, op: &str) {
+    pub fn write_unary_op(&mut self, op: Symbol) {
         match op {
-            "-" => self.vm.push("neg".to_string()),
-            "~" => self.vm.push("not".to_string()),
+            Symbol::Minus => self.vm.push("neg".to_string()),
+            Symbol::Not => self.vm.push("not".to_string()),
             _ => {},
         }
     }